@@ -8,7 +8,7 @@ defmodule Frontend.RustApi do
     case HTTPoison.get(@rust_api_url, [], recv_timeout: 5000) do
       {:ok, %HTTPoison.Response{status_code: 200, body: body}} ->
         Logger.info("Rust API Response: #{body}")
-        {:ok, Jason.decode!(body)}
+        {:ok, Jason.decode!(body)["logs"]}
 
       {:ok, %HTTPoison.Response{status_code: code}} ->
         Logger.error("Rust API returned status: #{code}")
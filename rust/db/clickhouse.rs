@@ -1,28 +1,73 @@
-use clickhouse::{Client, Row};
+use clickhouse::inserter::Inserter;
+use clickhouse::query::Query;
+use clickhouse::Client;
 use dotenv::dotenv;
-use serde::{Deserialize, Serialize};
 use std::env;
-use tokio_stream::StreamExt;
+use std::sync::Arc;
+use tracing::debug;
 //use reqwest::Client;
 
+use crate::database::{BatchInsert, Database, DbResult};
+use crate::error::DbError;
+use crate::query::{LogPage, LogQuery, Param};
 use crate::schema::DbLogEntry;
 
-pub struct InsertResult {
-    pub rows: u64,
-    pub bytes: u64,
-    pub error: Option<String>,
+/// Retention applied to the `logs` table when `CLICKHOUSE_TTL_DAYS` is unset.
+pub const DEFAULT_TTL_DAYS: u32 = 90;
+
+/// Connection settings read from the environment.
+#[derive(Debug, Clone)]
+pub struct ClickHouseConfig {
+    pub url: String,
+    pub database: String,
+    pub user: String,
+    pub password: Option<String>,
+    /// Days rows are kept before the `logs` TTL removes them.
+    pub ttl_days: u32,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone, Row)]
-pub struct DbLogRow{
-    pub source_ip: String,
-    pub event_type: String,
-    pub request: String,
-    pub status: String,
-    pub threat_level: String,
-    pub targeted_service: String,
-    pub targeted_endpoint: String,
-    pub action_taken: String,
+impl ClickHouseConfig {
+    /// Reads `CLICKHOUSE_URL`, `CLICKHOUSE_DB`, `CLICKHOUSE_USER` and the optional
+    /// `CLICKHOUSE_PASSWORD` and `CLICKHOUSE_TTL_DAYS` (default 90), from the
+    /// environment or a `.env` file.
+    pub fn from_env() -> Result<Self, DbError> {
+        dotenv().ok();
+
+        let database = required_env("CLICKHOUSE_DB")?;
+        // The database name is part of DDL statements, so keep it to a plain identifier.
+        if database.is_empty() || !database.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(DbError::Config(format!("CLICKHOUSE_DB is not a valid identifier: {}", database)));
+        }
+
+        let ttl_days = match env::var("CLICKHOUSE_TTL_DAYS") {
+            Ok(days) => days
+                .parse()
+                .map_err(|_| DbError::Config(format!("CLICKHOUSE_TTL_DAYS is not a number: {}", days)))?,
+            Err(_) => DEFAULT_TTL_DAYS,
+        };
+
+        Ok(Self {
+            url: required_env("CLICKHOUSE_URL")?,
+            database,
+            user: required_env("CLICKHOUSE_USER")?,
+            password: env::var("CLICKHOUSE_PASSWORD").ok().filter(|password| !password.is_empty()),
+            ttl_days,
+        })
+    }
+
+    /// Client bound to the configured database.
+    pub fn client(&self) -> Client {
+        self.server_client().with_database(&self.database)
+    }
+
+    /// Client without a database, for statements that run before it exists.
+    pub fn server_client(&self) -> Client {
+        let client = Client::default().with_url(&self.url).with_user(&self.user);
+        match &self.password {
+            Some(password) => client.with_password(password),
+            None => client,
+        }
+    }
 }
 
 pub struct ClickHouseDB {
@@ -30,134 +75,237 @@ pub struct ClickHouseDB {
 }
 
 impl ClickHouseDB {
-    
-    pub fn new() -> Self {
-        dotenv().ok();
+    /// Connects with the settings from `ClickHouseConfig::from_env`.
+    pub fn new() -> Result<Self, DbError> {
+        Ok(Self::connect(&ClickHouseConfig::from_env()?))
+    }
 
-        let db_url = env::var("CLICKHOUSE_URL").expect("CLICKHOUSE_URL not set in .env");
-        let db = env::var("CLICKHOUSE_DB").expect("CLICKHOUSE_DB not set in .env");
-        let db_user = env::var("CLICKHOUSE_USER").expect("CLICKHOUSE_USER not set in .env");
-        println!("Connecting to ClickHouse at {}", db_url);
-        let client = Client::default().with_url(&db_url).with_user(&db_user).with_database(&db);
+    pub fn connect(config: &ClickHouseConfig) -> Self {
+        debug!("Connecting to ClickHouse at {}", config.url);
+        Self { client: config.client() }
+    }
 
+    /// Wraps an already configured client.
+    pub fn with_client(client: Client) -> Self {
         Self { client }
     }
 
-    pub async fn insert_log(&self, log: DbLogEntry) -> Result<(), Box<dyn std::error::Error>> {
-        let mut insert = self.client.insert("logs")?;
-        insert.write(&log).await?;
-        insert.end().await?;
-        println!("Inserted log entry into ClickHouse!");
-        Ok(())
+    fn bind(&self, sql: &str, params: Vec<Param>) -> Query {
+        let mut query = self.client.query(sql);
+        for param in params {
+            query = match param {
+                Param::Str(value) => query.bind(value),
+                Param::Int(value) => query.bind(value),
+            };
+        }
+        query
+    }
+}
+
+/// Sends a writer's batches through one long-lived `Inserter`.
+struct LogInserter {
+    client: Client,
+    inserter: Option<Inserter<DbLogEntry>>,
+}
+
+#[async_trait::async_trait]
+impl BatchInsert for LogInserter {
+    async fn insert(&mut self, rows: &[DbLogEntry]) -> DbResult<u64> {
+        let mut inserter = match self.inserter.take() {
+            Some(inserter) => inserter,
+            None => self.client.inserter("logs")?,
+        };
+        for row in rows {
+            inserter.write(row)?;
+        }
+        let quantities = inserter.force_commit().await?;
+        // An inserter whose write failed cannot be used again, so one is only
+        // kept once it has committed.
+        self.inserter = Some(inserter);
+        Ok(quantities.bytes)
+    }
+}
+
+#[async_trait::async_trait]
+impl Database for ClickHouseDB {
+    async fn insert_log(&self, log: DbLogEntry) -> DbResult<()> {
+        self.insert_logs(vec![log]).await
     }
 
-    pub async fn insert_logs(&self, logs: Vec<DbLogEntry>) -> Result<(), Box<dyn std::error::Error>> {
+    async fn insert_logs(&self, logs: Vec<DbLogEntry>) -> DbResult<()> {
         let mut insert = self.client.insert("logs")?;
 
-        //remove timestamp and id from logs
-        /* 
-         *  The order of the fields in the struct must match the order of the fields in the ClickHouse table
-         *  We remove the timestamp and id fields from the logs since they are inserted automatically by ClickHouse
-         */
-        let row_logs: Vec<DbLogRow> = logs.iter().map(|log| {
-            DbLogRow {
-                source_ip: log.source_ip.clone(),
-                event_type: log.event_type.clone(),
-                targeted_service: log.targeted_service.clone(),
-                targeted_endpoint: log.targeted_endpoint.clone(),
-                request: log.request.clone(),
-                status: log.status.clone(),
-                action_taken: log.action_taken.clone(),
-                threat_level: log.threat_level.clone(),
-            }
-        }).collect();
-
-        for log in row_logs {
-            insert.write(&log).await?;
+        // Rows carry their own id and event time, so they are written as they are.
+        for log in &logs {
+            insert.write(log).await?;
         }
         insert.end().await?;
-        println!("Inserted log entries into ClickHouse!");
+        debug!("Inserted {} rows into ClickHouse", logs.len());
         Ok(())
     }
 
-    pub async fn fetch_logs(
-        &self,
-        limit: Option<u32>,
-    ) -> Result<Vec<DbLogEntry>, Box<dyn std::error::Error>> {
-        // let query = format!(
-        //     "SELECT ?fields FROM logs ORDER BY timestamp DESC LIMIT {} FORMAT JSONEachRow",
-        //     limit.unwrap_or(50)
-        // );
+    fn batch_insert(self: Arc<Self>) -> Box<dyn BatchInsert> {
+        Box::new(LogInserter { client: self.client.clone(), inserter: None })
+    }
 
-        // let query = "SELECT ?fields FROM logs ORDER BY timestamp DESC LIMIT ?";
-        // println!("Fetching logs from ClickHouse...{}", query);
-        let mut rows = self.client.query("SELECT toString(id), toString(timestamp), source_ip, event_type, targeted_service, targeted_endpoint, request, status, action_taken, threat_level FROM test_db.logs ORDER BY timestamp DESC LIMIT ?")
-        .bind(limit.unwrap_or(50)).fetch::<DbLogEntry>()?;
+    /// Runs a filtered, sorted query and returns one page of logs.
+    async fn query_logs(&self, query: &LogQuery) -> DbResult<LogPage> {
+        let (sql, params) = query.to_sql("logs");
+        let mut rows = self.bind(&sql, params).fetch::<DbLogEntry>()?;
         let mut logs = Vec::new();
-        println!("Fetching logs from ClickHouse...");
-        while let Some(log) = rows.next().await? { 
+        while let Some(log) = rows.next().await? {
             logs.push(log);
         }
 
-        Ok(logs)
+        let limit = query.limit() as usize;
+        let next_cursor = if logs.len() > limit {
+            logs.truncate(limit);
+            logs.last().map(|last| query.cursor_after(last).encode())
+        } else {
+            None
+        };
+
+        Ok(LogPage { logs, next_cursor })
+    }
+
+    async fn count_logs(&self, query: &LogQuery) -> DbResult<u64> {
+        let (sql, params) = query.count_sql("logs");
+        Ok(self.bind(&sql, params).fetch_one::<u64>().await?)
+    }
+
+    async fn delete_logs(&self, query: &LogQuery) -> DbResult<()> {
+        let (sql, params) = query.delete_sql("logs");
+        self.bind(&sql, params).execute().await?;
+        Ok(())
     }
 }
 
+fn required_env(name: &str) -> Result<String, DbError> {
+    env::var(name).map_err(|_| DbError::Config(format!("{} not set in .env", name)))
+}
+
 #[cfg(test)]
 mod tests {
-    use std::net::IpAddr;
-
     use super::*;
-    
-    use crate::mock::database::{MockDB, Database};
-    use tokio::sync::Mutex;
-    use IpAddr::V4;
+    use chrono::NaiveDateTime;
+    use uuid::Uuid;
+
+    use crate::mock::database::MockDB;
+    use crate::schema::{ActionTaken, EventType, ThreatLevel};
 
     #[tokio::test]
     async fn test_insert_log() {
         let db = MockDB::new();
 
-        let log = DbLogEntry {
-            id: "123".to_string(),
-            timestamp: "2021-01-01T00:00:00".to_string(),
-            source_ip: "192.168.1.1".to_string(),
-            event_type: "login".to_string(),
-            targeted_service: "auth".to_string(),
-            targeted_endpoint: "/login".to_string(),
-            request: "POST /login".to_string(),
-            status: "200".to_string(),
-            action_taken: "allow".to_string(),
-            threat_level: "low".to_string(),
-        };
-
-        let result = db.insert_log(log).await.unwrap();
-        assert_eq!(result, ());
+        db.insert_log(row(123, "2021-01-01 00:00:00")).await.unwrap();
 
         let logs = db.fetch_logs(None).await.unwrap();
-        assert_eq!(logs.len(), 110);
+        assert_eq!(logs.len(), 1);
     }
 
     #[tokio::test]
     async fn test_fetch_logs() {
         let db = MockDB::new();
 
+        db.insert_log(row(123, "2021-01-01 00:00:00")).await.unwrap();
+
+        let logs = db.fetch_logs(None).await.unwrap();
+        assert_eq!(logs.len(), 1);
+    }
+
+    fn row(id: u128, timestamp: &str) -> DbLogEntry {
+        DbLogEntry {
+            id: Uuid::from_u128(id),
+            timestamp: NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S").unwrap().and_utc(),
+            source_ip: "192.168.1.1".parse().unwrap(),
+            country: String::new(),
+            asn: 0,
+            event_type: EventType::HttpRequest,
+            targeted_service: "HTTP".to_string(),
+            targeted_endpoint: "/".to_string(),
+            request: "GET / HTTP/1.1".to_string(),
+            status: 200,
+            action_taken: ActionTaken::Logged,
+            threat_level: ThreatLevel::Info,
+            host: String::new(),
+            agent_id: String::new(),
+            environment: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rows_round_trip_as_native_types() {
+        let mock = clickhouse::test::Mock::new();
+        let db = ClickHouseDB::with_client(Client::default().with_url(mock.url()));
+        let recording = mock.add(clickhouse::test::handlers::record());
+
         let log = DbLogEntry {
-            id: "123".to_string(),
-            timestamp: "2021-01-01T00:00:00".to_string(),
-            source_ip: "192.168.1.1".to_string(),
-            event_type: "login".to_string(),
-            targeted_service: "auth".to_string(),
-            targeted_endpoint: "/login".to_string(),
-            request: "POST /login".to_string(),
-            status: "200".to_string(),
-            action_taken: "allow".to_string(),
-            threat_level: "low".to_string(),
+            source_ip: "2001:db8::7".parse().unwrap(),
+            event_type: EventType::Other("Port Scanning".to_string()),
+            threat_level: ThreatLevel::Critical,
+            ..row(42, "2025-03-09 12:34:56")
         };
+        db.insert_logs(vec![log.clone(), row(43, "2025-03-09 12:34:57")]).await.unwrap();
 
-        let result = db.insert_log(log).await.unwrap();
-        assert_eq!(result, ());
+        let rows: Vec<DbLogEntry> = recording.collect().await;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].id, log.id);
+        assert_eq!(rows[0].timestamp, log.timestamp);
+        assert_eq!(rows[0].source_ip, log.source_ip);
+        assert_eq!(rows[0].event_type, log.event_type);
+        assert_eq!(rows[0].threat_level, ThreatLevel::Critical);
+        // IPv4 addresses come back as IPv4, not as their IPv4-mapped IPv6 form.
+        assert_eq!(rows[1].source_ip, "192.168.1.1".parse::<std::net::IpAddr>().unwrap());
+        assert_eq!(rows[1].status, 200);
+    }
 
-        let logs = db.fetch_logs(None).await.unwrap();
-        assert_eq!(logs.len(), 1);
+    #[tokio::test]
+    async fn test_batches_are_sent_through_one_inserter() {
+        let mock = clickhouse::test::Mock::new();
+        let db = Arc::new(ClickHouseDB::with_client(Client::default().with_url(mock.url())));
+        let mut inserter = db.batch_insert();
+
+        let first = mock.add(clickhouse::test::handlers::record());
+        let bytes = inserter.insert(&[row(1, "2025-03-09 12:34:56"), row(2, "2025-03-09 12:34:57")]).await.unwrap();
+        assert!(bytes > 0);
+        assert_eq!(first.collect::<Vec<DbLogEntry>>().await.len(), 2);
+
+        // A row DateTime cannot hold fails its batch without breaking the next one.
+        let poisoned = row(3, "2200-01-01 00:00:00");
+        assert!(matches!(inserter.insert(&[poisoned]).await, Err(DbError::Serialization(_))));
+        let second = mock.add(clickhouse::test::handlers::record());
+        inserter.insert(&[row(4, "2025-03-09 12:34:58")]).await.unwrap();
+        assert_eq!(second.collect::<Vec<DbLogEntry>>().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_query_logs_returns_cursor_when_more_rows_exist() {
+        let mock = clickhouse::test::Mock::new();
+        let db = ClickHouseDB::with_client(Client::default().with_url(mock.url()));
+        mock.add(clickhouse::test::handlers::provide(vec![
+            row(3, "2025-03-09 12:00:03"),
+            row(2, "2025-03-09 12:00:02"),
+            row(1, "2025-03-09 12:00:01"),
+        ]));
+
+        let page = db.query_logs(&LogQuery { limit: Some(2), ..Default::default() }).await.unwrap();
+        assert_eq!(page.logs.len(), 2);
+
+        let cursor = crate::query::Cursor::decode(&page.next_cursor.unwrap()).unwrap();
+        assert_eq!(cursor.value, "1741521602");
+        assert_eq!(cursor.id, Uuid::from_u128(2));
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_query_logs_last_page_has_no_cursor() {
+        let mock = clickhouse::test::Mock::new();
+        let db = ClickHouseDB::with_client(Client::default().with_url(mock.url()));
+        mock.add(clickhouse::test::handlers::provide(vec![
+            row(1, "2025-03-09 12:00:01"),
+        ]));
+
+        let page = db.query_logs(&LogQuery { limit: Some(2), ..Default::default() }).await.unwrap();
+        assert_eq!(page.logs.len(), 1);
+        assert!(page.next_cursor.is_none());
+    }
+}
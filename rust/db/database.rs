@@ -0,0 +1,47 @@
+use std::sync::Arc;
+
+use crate::error::DbError;
+use crate::query::{LogPage, LogQuery};
+use crate::schema::DbLogEntry;
+use crate::writer::InsertLogs;
+
+pub type DbResult<T> = Result<T, DbError>;
+
+/// Storage backend for log rows.
+///
+/// Implemented by `ClickHouseDB` and the in-memory `MockDB`, so the API and the
+/// ingestion pipeline can run against either through an `Arc<dyn Database>`.
+#[async_trait::async_trait]
+pub trait Database: Send + Sync + 'static {
+    async fn insert_log(&self, log: DbLogEntry) -> DbResult<()>;
+
+    async fn insert_logs(&self, logs: Vec<DbLogEntry>) -> DbResult<()>;
+
+    /// Returns one page of rows matching `query`.
+    async fn query_logs(&self, query: &LogQuery) -> DbResult<LogPage>;
+
+    /// Counts the rows matching the filters of `query`, ignoring pagination.
+    async fn count_logs(&self, query: &LogQuery) -> DbResult<u64>;
+
+    /// Deletes the rows matching the filters of `query`.
+    async fn delete_logs(&self, query: &LogQuery) -> DbResult<()>;
+
+    /// Starts the consecutive inserts of a `LogWriter`. By default each batch
+    /// is sent with `insert_logs`.
+    fn batch_insert(self: Arc<Self>) -> Box<dyn BatchInsert> {
+        Box::new(InsertLogs(self))
+    }
+
+    /// Most recent rows, newest first.
+    async fn fetch_logs(&self, limit: Option<u32>) -> DbResult<Vec<DbLogEntry>> {
+        let page = self.query_logs(&LogQuery { limit, ..Default::default() }).await?;
+        Ok(page.logs)
+    }
+}
+
+/// Batches of rows inserted one after another by a `LogWriter`.
+#[async_trait::async_trait]
+pub trait BatchInsert: Send {
+    /// Inserts `rows` as one batch and returns the bytes sent.
+    async fn insert(&mut self, rows: &[DbLogEntry]) -> DbResult<u64>;
+}
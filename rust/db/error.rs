@@ -0,0 +1,121 @@
+use thiserror::Error;
+
+/// Errors returned by every `Database` backend.
+#[derive(Debug, Error)]
+pub enum DbError {
+    /// Missing or invalid settings, such as an unset `CLICKHOUSE_URL`.
+    #[error("configuration error: {0}")]
+    Config(String),
+    /// The database could not be reached or did not answer in time.
+    #[error("connection error: {0}")]
+    Connection(String),
+    /// The table does not have the columns or types the row structs expect.
+    #[error("schema mismatch: {0}")]
+    SchemaMismatch(String),
+    /// A row could not be encoded or decoded.
+    #[error("serialization error: {0}")]
+    Serialization(String),
+    /// The database rejected the query.
+    #[error("query error: {0}")]
+    Query(String),
+    #[error("not found")]
+    NotFound,
+}
+
+impl DbError {
+    /// Short machine-readable name of the variant, used in API error bodies.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DbError::Config(_) => "config",
+            DbError::Connection(_) => "connection",
+            DbError::SchemaMismatch(_) => "schema_mismatch",
+            DbError::Serialization(_) => "serialization",
+            DbError::Query(_) => "query",
+            DbError::NotFound => "not_found",
+        }
+    }
+
+    /// Whether trying again later can succeed. Bad settings, schema or data fail the same way every time.
+    pub fn is_transient(&self) -> bool {
+        matches!(self, DbError::Connection(_) | DbError::Query(_))
+    }
+
+    /// Whether the error may come from some rows of an insert rather than from all of them.
+    pub fn is_row_error(&self) -> bool {
+        matches!(self, DbError::Serialization(_))
+    }
+}
+
+/// ClickHouse server error codes that mean the table does not look like we expect.
+const SCHEMA_ERROR_CODES: [&str; 4] = [
+    "Code: 16.", // NO_SUCH_COLUMN_IN_TABLE
+    "Code: 47.", // UNKNOWN_IDENTIFIER
+    "Code: 53.", // TYPE_MISMATCH
+    "Code: 60.", // UNKNOWN_TABLE
+];
+
+/// Server error codes caused by connection settings rather than the query.
+const CONFIG_ERROR_CODES: [&str; 2] = [
+    "Code: 81.",  // UNKNOWN_DATABASE
+    "Code: 516.", // AUTHENTICATION_FAILED
+];
+
+impl From<clickhouse::error::Error> for DbError {
+    fn from(error: clickhouse::error::Error) -> Self {
+        use clickhouse::error::Error;
+
+        let message = error.to_string();
+        match error {
+            Error::Network(_) | Error::TimedOut => DbError::Connection(message),
+            Error::NotEnoughData | Error::InvalidTagEncoding(_) | Error::VariantDiscriminatorIsOutOfBound(_) => {
+                DbError::SchemaMismatch(message)
+            }
+            Error::Compression(_)
+            | Error::Decompression(_)
+            | Error::SequenceMustHaveLength
+            | Error::DeserializeAnyNotSupported
+            | Error::InvalidUtf8Encoding(_)
+            | Error::Custom(_) => DbError::Serialization(message),
+            Error::RowNotFound => DbError::NotFound,
+            Error::BadResponse(ref response) if SCHEMA_ERROR_CODES.iter().any(|code| response.contains(code)) => {
+                DbError::SchemaMismatch(message)
+            }
+            Error::BadResponse(ref response) if CONFIG_ERROR_CODES.iter().any(|code| response.contains(code)) => {
+                DbError::Config(message)
+            }
+            _ => DbError::Query(message),
+        }
+    }
+}
+
+impl From<serde_json::Error> for DbError {
+    fn from(error: serde_json::Error) -> Self {
+        DbError::Serialization(error.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clickhouse::error::Error;
+
+    #[test]
+    fn test_clickhouse_errors_are_classified() {
+        assert!(matches!(DbError::from(Error::TimedOut), DbError::Connection(_)));
+        assert!(matches!(DbError::from(Error::NotEnoughData), DbError::SchemaMismatch(_)));
+        assert!(matches!(DbError::from(Error::Custom("bad".to_string())), DbError::Serialization(_)));
+        assert!(matches!(DbError::from(Error::RowNotFound), DbError::NotFound));
+    }
+
+    #[test]
+    fn test_server_error_codes_are_classified() {
+        let unknown_table = Error::BadResponse("Code: 60. DB::Exception: Table test_db.logs does not exist.".to_string());
+        assert!(matches!(DbError::from(unknown_table), DbError::SchemaMismatch(_)));
+
+        let auth = Error::BadResponse("Code: 516. DB::Exception: default: Authentication failed".to_string());
+        assert!(matches!(DbError::from(auth), DbError::Config(_)));
+
+        let syntax = Error::BadResponse("Code: 62. DB::Exception: Syntax error".to_string());
+        assert!(matches!(DbError::from(syntax), DbError::Query(_)));
+    }
+}
@@ -0,0 +1,261 @@
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::clickhouse::ClickHouseConfig;
+use crate::error::DbError;
+use crate::schema::ThreatLevel;
+
+/// A schema change, applied once and recorded in `schema_migrations`.
+pub struct Migration {
+    pub version: u32,
+    pub description: &'static str,
+    /// Statements to run, in order. They take the config so the TTL and
+    /// database name can be filled in.
+    pub up: fn(&ClickHouseConfig) -> Vec<String>,
+}
+
+/// Every migration in version order. Never edit an entry that has shipped, add a new one.
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        description: "create logs table",
+        up: |config| {
+            vec![format!(
+                "CREATE TABLE IF NOT EXISTS logs (
+                    id UUID DEFAULT generateUUIDv4(),
+                    timestamp DateTime DEFAULT now(),
+                    source_ip String,
+                    event_type LowCardinality(String),
+                    targeted_service String,
+                    targeted_endpoint String,
+                    request String,
+                    status String,
+                    action_taken String,
+                    threat_level String
+                ) ENGINE = MergeTree()
+                PARTITION BY toYYYYMM(timestamp)
+                ORDER BY (timestamp, source_ip, event_type)
+                TTL timestamp + INTERVAL {} DAY",
+                config.ttl_days
+            )]
+        },
+    },
+    Migration {
+        version: 2,
+        description: "add geo and ASN columns",
+        up: |_| {
+            vec![
+                "ALTER TABLE logs ADD COLUMN IF NOT EXISTS country LowCardinality(String) DEFAULT '' AFTER source_ip".to_string(),
+                "ALTER TABLE logs ADD COLUMN IF NOT EXISTS asn UInt32 DEFAULT 0 AFTER country".to_string(),
+            ]
+        },
+    },
+    Migration {
+        version: 3,
+        description: "native types for source_ip, status and threat_level",
+        // source_ip is part of the sorting key, which cannot change type in place,
+        // so the table is rebuilt and swapped in.
+        up: |config| {
+            let levels = ThreatLevel::ALL.map(|level| format!("'{}'", level)).join(", ");
+            vec![
+                "DROP TABLE IF EXISTS logs_v3".to_string(),
+                format!(
+                    "CREATE TABLE logs_v3 (
+                        id UUID DEFAULT generateUUIDv4(),
+                        timestamp DateTime DEFAULT now(),
+                        source_ip IPv6,
+                        country LowCardinality(String) DEFAULT '',
+                        asn UInt32 DEFAULT 0,
+                        event_type LowCardinality(String),
+                        targeted_service LowCardinality(String),
+                        targeted_endpoint String,
+                        request String,
+                        status UInt16,
+                        action_taken LowCardinality(String),
+                        threat_level {}
+                    ) ENGINE = MergeTree()
+                    PARTITION BY toYYYYMM(timestamp)
+                    ORDER BY (timestamp, source_ip, event_type)
+                    TTL timestamp + INTERVAL {} DAY",
+                    ThreatLevel::COLUMN_TYPE, config.ttl_days
+                ),
+                format!(
+                    "INSERT INTO logs_v3 SELECT id, timestamp, toIPv6OrDefault(source_ip), country, asn, event_type,
+                        targeted_service, targeted_endpoint, request, toUInt16OrZero(status), action_taken,
+                        if(threat_level IN ({}), threat_level, 'Info')
+                    FROM logs",
+                    levels
+                ),
+                "EXCHANGE TABLES logs AND logs_v3".to_string(),
+                "DROP TABLE logs_v3".to_string(),
+            ]
+        },
+    },
+    Migration {
+        version: 4,
+        description: "add host, agent and environment columns",
+        up: |_| {
+            vec![
+                "ALTER TABLE logs ADD COLUMN IF NOT EXISTS host LowCardinality(String) DEFAULT '' AFTER threat_level".to_string(),
+                "ALTER TABLE logs ADD COLUMN IF NOT EXISTS agent_id LowCardinality(String) DEFAULT '' AFTER host".to_string(),
+                "ALTER TABLE logs ADD COLUMN IF NOT EXISTS environment LowCardinality(String) DEFAULT '' AFTER agent_id".to_string(),
+                // Lets per-host queries skip granules without reordering the table.
+                "ALTER TABLE logs ADD INDEX IF NOT EXISTS idx_host host TYPE set(1024) GRANULARITY 4".to_string(),
+                "ALTER TABLE logs MATERIALIZE INDEX idx_host".to_string(),
+            ]
+        },
+    },
+];
+
+const CREATE_MIGRATIONS_TABLE: &str = "CREATE TABLE IF NOT EXISTS schema_migrations (
+    version UInt32,
+    description String,
+    applied_at DateTime DEFAULT now()
+) ENGINE = MergeTree()
+ORDER BY version";
+
+#[derive(Debug, Serialize, Deserialize, Row)]
+struct AppliedMigration {
+    version: u32,
+    description: String,
+}
+
+/// Migrations from `MIGRATIONS` whose version is not in `applied`.
+pub fn pending(applied: &[u32]) -> Vec<&'static Migration> {
+    MIGRATIONS
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .collect()
+}
+
+/// Brings the configured database up to the latest schema.
+pub struct Migrator {
+    config: ClickHouseConfig,
+}
+
+impl Migrator {
+    pub fn new(config: ClickHouseConfig) -> Self {
+        Self { config }
+    }
+
+    /// Creates the database if needed, applies pending migrations and makes the
+    /// `logs` TTL match the config. Returns the versions that were applied.
+    pub async fn run(&self) -> Result<Vec<u32>, DbError> {
+        self.config
+            .server_client()
+            .query(&format!("CREATE DATABASE IF NOT EXISTS {}", self.config.database))
+            .execute()
+            .await?;
+
+        let client = self.config.client();
+        client.query(CREATE_MIGRATIONS_TABLE).execute().await?;
+
+        let applied = client
+            .query("SELECT version FROM schema_migrations")
+            .fetch_all::<u32>()
+            .await?;
+
+        let mut newly_applied = Vec::new();
+        for migration in pending(&applied) {
+            info!("Applying migration {}: {}", migration.version, migration.description);
+            for statement in (migration.up)(&self.config) {
+                client.query(&statement).execute().await?;
+            }
+
+            let mut insert = client.insert("schema_migrations")?;
+            insert
+                .write(&AppliedMigration {
+                    version: migration.version,
+                    description: migration.description.to_string(),
+                })
+                .await?;
+            insert.end().await?;
+            newly_applied.push(migration.version);
+        }
+
+        self.ensure_ttl(&client).await?;
+        Ok(newly_applied)
+    }
+
+    /// The TTL is set when the table is created, so a changed `CLICKHOUSE_TTL_DAYS`
+    /// has to be applied to an existing table explicitly.
+    async fn ensure_ttl(&self, client: &Client) -> Result<(), DbError> {
+        let engine = client
+            .query("SELECT engine_full FROM system.tables WHERE database = currentDatabase() AND name = 'logs'")
+            .fetch_one::<String>()
+            .await?;
+
+        if !has_ttl(&engine, self.config.ttl_days) {
+            info!("Setting logs TTL to {} days", self.config.ttl_days);
+            client
+                .query("ALTER TABLE logs MODIFY TTL timestamp + INTERVAL ? DAY")
+                .bind(self.config.ttl_days)
+                .execute()
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+/// ClickHouse normalises `INTERVAL n DAY` to `toIntervalDay(n)` in `engine_full`.
+fn has_ttl(engine_full: &str, days: u32) -> bool {
+    engine_full.contains(&format!("toIntervalDay({})", days))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(ttl_days: u32) -> ClickHouseConfig {
+        ClickHouseConfig {
+            url: "http://localhost:8123".to_string(),
+            database: "test_db".to_string(),
+            user: "default".to_string(),
+            password: None,
+            ttl_days,
+        }
+    }
+
+    #[test]
+    fn test_migrations_are_ordered_and_unique() {
+        let versions: Vec<u32> = MIGRATIONS.iter().map(|migration| migration.version).collect();
+        let mut sorted = versions.clone();
+        sorted.sort_unstable();
+        sorted.dedup();
+        assert_eq!(versions, sorted);
+    }
+
+    #[test]
+    fn test_pending_skips_applied_versions() {
+        let versions: Vec<u32> = pending(&[]).iter().map(|migration| migration.version).collect();
+        assert_eq!(versions, vec![1, 2, 3, 4]);
+
+        let versions: Vec<u32> = pending(&[1]).iter().map(|migration| migration.version).collect();
+        assert_eq!(versions, vec![2, 3, 4]);
+
+        assert!(pending(&[1, 2, 3, 4]).is_empty());
+    }
+
+    #[test]
+    fn test_create_table_uses_configured_ttl() {
+        let statements = (MIGRATIONS[0].up)(&config(30));
+        assert!(statements[0].contains("TTL timestamp + INTERVAL 30 DAY"));
+    }
+
+    #[test]
+    fn test_rebuild_keeps_configured_ttl() {
+        let statements = (MIGRATIONS[2].up)(&config(30));
+        assert!(statements[1].contains("threat_level Enum8('Info' = 0"));
+        assert!(statements[1].contains("TTL timestamp + INTERVAL 30 DAY"));
+        assert!(statements[2].contains("IN ('Info', 'Low', 'Medium', 'High', 'Critical')"));
+    }
+
+    #[test]
+    fn test_has_ttl() {
+        let engine = "MergeTree PARTITION BY toYYYYMM(timestamp) ORDER BY (timestamp, source_ip, event_type) TTL timestamp + toIntervalDay(90) SETTINGS index_granularity = 8192";
+        assert!(has_ttl(engine, 90));
+        assert!(!has_ttl(engine, 9));
+        assert!(!has_ttl(engine, 30));
+    }
+}
@@ -1,34 +1,14 @@
-use async_trait::async_trait;
 use tokio::sync::Mutex;
 use std::sync::Arc;
-use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
+use uuid::Uuid;
+use crate::database::{Database, DbResult};
+use crate::query::{LogPage, LogQuery};
 use crate::schema::DbLogEntry;
 
-#[async_trait::async_trait]
-pub trait Database: Send + Sync {
-    async fn insert_log(&self, log: DbLogEntry) -> Result<(), String>;
-    async fn fetch_logs(&self, limit: Option<u32>) -> Result<Vec<DbLogEntry>, String>;
-}
-
-pub struct ClickHouseDB;
-
-#[async_trait::async_trait]
-impl Database for ClickHouseDB {
-    async fn insert_log(&self, log: DbLogEntry) -> Result<(), String> {
-        // Real ClickHouse implementation
-        println!("Inserting log: {:?}", log);
-        Ok(())
-    }
-
-    async fn fetch_logs(&self, limit: Option<u32>) -> Result<Vec<DbLogEntry>, String> {
-        // Real ClickHouse implementation
-        Ok(vec![])
-    }
-}
-
+/// In-memory `Database` keyed by row id, for tests and running without ClickHouse.
 pub struct MockDB {
-    logs: Arc<Mutex<HashMap<String, DbLogEntry>>>,
+    logs: Arc<Mutex<HashMap<Uuid, DbLogEntry>>>,
 }
 
 impl MockDB {
@@ -39,18 +19,83 @@ impl MockDB {
     }
 }
 
+impl Default for MockDB {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 
 #[async_trait::async_trait]
 impl Database for MockDB {
-    async fn insert_log(&self, log: DbLogEntry) -> Result<(), String> {
+    async fn insert_log(&self, log: DbLogEntry) -> DbResult<()> {
         let mut logs = self.logs.lock().await;
-        logs.insert(log.id.clone(), log);
+        logs.insert(log.id, log);
         Ok(())
     }
 
-    async fn fetch_logs(&self, limit: Option<u32>) -> Result<Vec<DbLogEntry>, String> {
+    async fn insert_logs(&self, new_logs: Vec<DbLogEntry>) -> DbResult<()> {
+        let mut logs = self.logs.lock().await;
+        for log in new_logs {
+            logs.insert(log.id, log);
+        }
+        Ok(())
+    }
+
+    async fn query_logs(&self, query: &LogQuery) -> DbResult<LogPage> {
         let logs = self.logs.lock().await;
-        let logs: Vec<DbLogEntry> = logs.values().cloned().take(limit.unwrap_or(logs.len() as u32) as usize).collect();
-        Ok(logs)
+        let matching = logs.values().filter(|log| query.matches(log)).cloned().collect();
+        Ok(query.paginate(matching))
+    }
+
+    async fn count_logs(&self, query: &LogQuery) -> DbResult<u64> {
+        let logs = self.logs.lock().await;
+        Ok(logs.values().filter(|log| query.matches(log)).count() as u64)
+    }
+
+    async fn delete_logs(&self, query: &LogQuery) -> DbResult<()> {
+        let mut logs = self.logs.lock().await;
+        logs.retain(|_, log| !query.matches(log));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::{ActionTaken, EventType, ThreatLevel};
+    use chrono::{TimeZone, Utc};
+
+    fn log(id: u128, source_ip: &str) -> DbLogEntry {
+        DbLogEntry {
+            id: Uuid::from_u128(id),
+            timestamp: Utc.with_ymd_and_hms(2025, 3, 9, 12, 34, 56).unwrap(),
+            source_ip: source_ip.parse().unwrap(),
+            country: String::new(),
+            asn: 0,
+            event_type: EventType::SshFailedLogin,
+            targeted_service: "SSH".to_string(),
+            targeted_endpoint: "user root".to_string(),
+            request: "Failed password for root".to_string(),
+            status: 0,
+            action_taken: ActionTaken::Logged,
+            threat_level: ThreatLevel::Low,
+            host: String::new(),
+            agent_id: String::new(),
+            environment: String::new(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_count_and_delete_by_cidr() {
+        let db = MockDB::new();
+        db.insert_logs(vec![log(1, "10.0.0.1"), log(2, "10.0.0.2"), log(3, "192.168.1.1")]).await.unwrap();
+
+        let internal = LogQuery { source_ip: Some("10.0.0.0/8".parse().unwrap()), ..Default::default() };
+        assert_eq!(db.count_logs(&internal).await.unwrap(), 2);
+
+        db.delete_logs(&internal).await.unwrap();
+        assert_eq!(db.count_logs(&LogQuery::default()).await.unwrap(), 1);
+        assert_eq!(db.fetch_logs(None).await.unwrap()[0].id, Uuid::from_u128(3));
     }
 }
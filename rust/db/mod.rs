@@ -1,4 +1,9 @@
 pub mod clickhouse;
+pub mod database;
+pub mod error;
 pub mod schema;
 pub mod mock;
-pub mod util;
\ No newline at end of file
+pub mod util;
+pub mod query;
+pub mod migrations;
+pub mod writer;
\ No newline at end of file
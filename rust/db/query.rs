@@ -0,0 +1,514 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv6Addr};
+use uuid::Uuid;
+
+use crate::schema::{to_ipv6, DbLogEntry, EventType, ThreatLevel};
+
+/// Columns selected for `DbLogEntry`, in struct field order.
+pub const LOG_COLUMNS: &str = "id, timestamp, source_ip, country, asn, event_type, \
+    targeted_service, targeted_endpoint, request, status, action_taken, threat_level, \
+    host, agent_id, environment";
+
+pub const DEFAULT_LIMIT: u32 = 50;
+pub const MAX_LIMIT: u32 = 1000;
+
+/// Columns logs can be sorted by. Only these names ever reach the SQL text.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortField {
+    #[default]
+    Timestamp,
+    SourceIp,
+    EventType,
+    ThreatLevel,
+    Status,
+    TargetedService,
+}
+
+impl SortField {
+    fn column(self) -> &'static str {
+        match self {
+            SortField::Timestamp => "timestamp",
+            SortField::SourceIp => "source_ip",
+            SortField::EventType => "event_type",
+            SortField::ThreatLevel => "threat_level",
+            SortField::Status => "status",
+            SortField::TargetedService => "targeted_service",
+        }
+    }
+
+    /// Placeholder for a cursor value, converting it back to the column type.
+    fn placeholder(self) -> &'static str {
+        match self {
+            SortField::Timestamp => "toDateTime(toUInt32(?))",
+            SortField::SourceIp => "toIPv6(?)",
+            SortField::Status => "toUInt16(?)",
+            _ => "?",
+        }
+    }
+
+    fn value_of(self, row: &DbLogEntry) -> SortValue {
+        match self {
+            SortField::Timestamp => SortValue::Timestamp(row.timestamp),
+            SortField::SourceIp => SortValue::Ip(to_ipv6(row.source_ip)),
+            SortField::EventType => SortValue::Text(row.event_type.to_string()),
+            SortField::ThreatLevel => SortValue::Level(row.threat_level),
+            SortField::Status => SortValue::Status(row.status),
+            SortField::TargetedService => SortValue::Text(row.targeted_service.clone()),
+        }
+    }
+
+    /// Reads a cursor value written by `SortValue::to_cursor`.
+    fn parse_value(self, value: &str) -> Option<SortValue> {
+        Some(match self {
+            SortField::Timestamp => SortValue::Timestamp(DateTime::from_timestamp(value.parse().ok()?, 0)?),
+            SortField::SourceIp => SortValue::Ip(to_ipv6(value.parse().ok()?)),
+            SortField::EventType | SortField::TargetedService => SortValue::Text(value.to_string()),
+            SortField::ThreatLevel => SortValue::Level(value.parse().ok()?),
+            SortField::Status => SortValue::Status(value.parse().ok()?),
+        })
+    }
+}
+
+/// A row's value in the sort column, ordered the way ClickHouse orders the column.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Timestamp(DateTime<Utc>),
+    Ip(Ipv6Addr),
+    Text(String),
+    Level(ThreatLevel),
+    Status(u16),
+}
+
+impl SortValue {
+    fn to_cursor(&self) -> String {
+        match self {
+            SortValue::Timestamp(timestamp) => timestamp.timestamp().to_string(),
+            SortValue::Ip(ip) => ip.to_canonical().to_string(),
+            SortValue::Text(text) => text.clone(),
+            SortValue::Level(level) => level.to_string(),
+            SortValue::Status(status) => status.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Position after the last row of a page: its sort column value and id.
+///
+/// Timestamps are kept as seconds since the epoch, other values in their text form.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursor {
+    pub value: String,
+    pub id: Uuid,
+}
+
+impl Cursor {
+    /// Encodes the cursor as an opaque URL-safe token.
+    pub fn encode(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).expect("cursor serializes"))
+    }
+
+    pub fn decode(token: &str) -> Option<Self> {
+        let bytes = URL_SAFE_NO_PAD.decode(token).ok()?;
+        serde_json::from_slice(&bytes).ok()
+    }
+
+    /// Whether `value` can be read as a value of `sort`, i.e. the cursor was
+    /// issued for a query sorted by that field.
+    pub fn fits(&self, sort: SortField) -> bool {
+        sort.parse_value(&self.value).is_some()
+    }
+}
+
+/// A value bound to a `?` placeholder. Bound values are escaped by the client,
+/// so user input never becomes part of the SQL text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Param {
+    Str(String),
+    Int(i64),
+}
+
+/// Filters, ordering and keyset pagination for reading logs.
+#[derive(Debug, Clone, Default)]
+pub struct LogQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// CIDR block, e.g. `10.0.0.0/8` or `192.168.1.1/32`.
+    pub source_ip: Option<IpNet>,
+    pub event_type: Option<EventType>,
+    pub threat_level: Option<ThreatLevel>,
+    pub status: Option<u16>,
+    pub targeted_service: Option<String>,
+    pub host: Option<String>,
+    pub environment: Option<String>,
+    /// Case-insensitive substring match on request, endpoint and event type.
+    pub search: Option<String>,
+    pub sort: SortField,
+    pub order: SortOrder,
+    pub limit: Option<u32>,
+    pub cursor: Option<Cursor>,
+}
+
+impl LogQuery {
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    /// Builds the SELECT for `table`. One extra row is requested to tell whether
+    /// another page follows.
+    pub fn to_sql(&self, table: &str) -> (String, Vec<Param>) {
+        let (mut conditions, mut params) = self.filter_conditions();
+
+        let column = self.sort.column();
+        let (direction, comparison) = match self.order {
+            SortOrder::Asc => ("ASC", ">"),
+            SortOrder::Desc => ("DESC", "<"),
+        };
+        if let Some(cursor) = &self.cursor {
+            conditions.push(format!("({}, id) {} ({}, toUUID(?))", column, comparison, self.sort.placeholder()));
+            params.push(Param::Str(cursor.value.clone()));
+            params.push(Param::Str(cursor.id.to_string()));
+        }
+
+        let mut sql = format!("SELECT {} FROM {}", LOG_COLUMNS, table);
+        push_where(&mut sql, &conditions);
+        sql.push_str(&format!(" ORDER BY {} {}, id {} LIMIT ?", column, direction, direction));
+        params.push(Param::Int(i64::from(self.limit()) + 1));
+
+        (sql, params)
+    }
+
+    /// Counts the rows matching the filters. Sorting and pagination are ignored.
+    pub fn count_sql(&self, table: &str) -> (String, Vec<Param>) {
+        let (conditions, params) = self.filter_conditions();
+        let mut sql = format!("SELECT count() FROM {}", table);
+        push_where(&mut sql, &conditions);
+        (sql, params)
+    }
+
+    /// Deletes the rows matching the filters. An empty filter deletes everything.
+    pub fn delete_sql(&self, table: &str) -> (String, Vec<Param>) {
+        let (conditions, params) = self.filter_conditions();
+        let mut sql = format!("DELETE FROM {}", table);
+        if conditions.is_empty() {
+            sql.push_str(" WHERE 1");
+        }
+        push_where(&mut sql, &conditions);
+        (sql, params)
+    }
+
+    fn filter_conditions(&self) -> (Vec<String>, Vec<Param>) {
+        let mut conditions = Vec::new();
+        let mut params = Vec::new();
+
+        if let Some(from) = self.from {
+            conditions.push("timestamp >= toDateTime(?)".to_string());
+            params.push(Param::Int(from.timestamp()));
+        }
+        if let Some(to) = self.to {
+            conditions.push("timestamp < toDateTime(?)".to_string());
+            params.push(Param::Int(to.timestamp()));
+        }
+        if let Some(net) = &self.source_ip {
+            // A range scan over the IPv6 column, IPv4 blocks map into ::ffff:0:0/96.
+            conditions.push("source_ip BETWEEN toIPv6(?) AND toIPv6(?)".to_string());
+            params.push(Param::Str(net.network().to_string()));
+            params.push(Param::Str(net.broadcast().to_string()));
+        }
+        if let Some(event_type) = &self.event_type {
+            conditions.push("event_type = ?".to_string());
+            params.push(Param::Str(event_type.to_string()));
+        }
+        if let Some(threat_level) = self.threat_level {
+            conditions.push("threat_level = ?".to_string());
+            params.push(Param::Str(threat_level.to_string()));
+        }
+        if let Some(status) = self.status {
+            conditions.push("status = ?".to_string());
+            params.push(Param::Int(i64::from(status)));
+        }
+        if let Some(targeted_service) = &self.targeted_service {
+            conditions.push("targeted_service = ?".to_string());
+            params.push(Param::Str(targeted_service.clone()));
+        }
+        if let Some(host) = &self.host {
+            conditions.push("host = ?".to_string());
+            params.push(Param::Str(host.clone()));
+        }
+        if let Some(environment) = &self.environment {
+            conditions.push("environment = ?".to_string());
+            params.push(Param::Str(environment.clone()));
+        }
+        if let Some(search) = &self.search {
+            conditions.push(
+                "(positionCaseInsensitive(request, ?) > 0 OR positionCaseInsensitive(targeted_endpoint, ?) > 0 \
+                 OR positionCaseInsensitive(event_type, ?) > 0)"
+                    .to_string(),
+            );
+            params.extend(std::iter::repeat_n(Param::Str(search.clone()), 3));
+        }
+
+        (conditions, params)
+    }
+
+    /// Evaluates the filters in memory, for backends without SQL.
+    pub fn matches(&self, row: &DbLogEntry) -> bool {
+        if self.from.is_some_and(|from| row.timestamp < from) {
+            return false;
+        }
+        if self.to.is_some_and(|to| row.timestamp >= to) {
+            return false;
+        }
+        if let Some(net) = &self.source_ip {
+            if !contains(net, row.source_ip) {
+                return false;
+            }
+        }
+        if self.event_type.as_ref().is_some_and(|event_type| *event_type != row.event_type) {
+            return false;
+        }
+        if self.threat_level.is_some_and(|threat_level| threat_level != row.threat_level) {
+            return false;
+        }
+        if self.status.is_some_and(|status| status != row.status) {
+            return false;
+        }
+        if self.targeted_service.as_ref().is_some_and(|service| *service != row.targeted_service) {
+            return false;
+        }
+        if self.host.as_ref().is_some_and(|host| *host != row.host) {
+            return false;
+        }
+        if self.environment.as_ref().is_some_and(|environment| *environment != row.environment) {
+            return false;
+        }
+        if let Some(search) = &self.search {
+            let search = search.to_lowercase();
+            let found = [row.request.as_str(), row.targeted_endpoint.as_str(), row.event_type.as_str()]
+                .iter()
+                .any(|field| field.to_lowercase().contains(&search));
+            if !found {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Sorts `rows` and applies cursor and limit in memory, mirroring `to_sql`.
+    pub fn paginate(&self, mut rows: Vec<DbLogEntry>) -> LogPage {
+        let key = |row: &DbLogEntry| (self.sort.value_of(row), row.id);
+        rows.sort_by_key(key);
+        if self.order == SortOrder::Desc {
+            rows.reverse();
+        }
+
+        if let Some(cursor) = &self.cursor {
+            match self.sort.parse_value(&cursor.value) {
+                Some(value) => {
+                    let after = (value, cursor.id);
+                    rows.retain(|row| match self.order {
+                        SortOrder::Asc => key(row) > after,
+                        SortOrder::Desc => key(row) < after,
+                    });
+                }
+                None => rows.clear(),
+            }
+        }
+
+        let limit = self.limit() as usize;
+        let next_cursor = if rows.len() > limit {
+            rows.truncate(limit);
+            rows.last().map(|last| self.cursor_after(last).encode())
+        } else {
+            None
+        };
+        LogPage { logs: rows, next_cursor }
+    }
+
+    /// Cursor pointing after `row`, for the current sort field.
+    pub fn cursor_after(&self, row: &DbLogEntry) -> Cursor {
+        Cursor { value: self.sort.value_of(row).to_cursor(), id: row.id }
+    }
+}
+
+/// Like `IpNet::contains`, but also matches IPv4 addresses stored in IPv4-mapped form.
+fn contains(net: &IpNet, ip: IpAddr) -> bool {
+    net.contains(&ip) || net.contains(&IpAddr::V6(to_ipv6(ip)))
+}
+
+fn push_where(sql: &mut String, conditions: &[String]) {
+    if !conditions.is_empty() {
+        sql.push_str(" WHERE ");
+        sql.push_str(&conditions.join(" AND "));
+    }
+}
+
+/// One page of logs and the cursor for the next page, if there is one.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogPage {
+    pub logs: Vec<DbLogEntry>,
+    pub next_cursor: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::ActionTaken;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_default_query_sorts_newest_first() {
+        let (sql, params) = LogQuery::default().to_sql("logs");
+        assert_eq!(sql, format!("SELECT {} FROM logs ORDER BY timestamp DESC, id DESC LIMIT ?", LOG_COLUMNS));
+        assert_eq!(params, vec![Param::Int(51)]);
+    }
+
+    #[test]
+    fn test_filters_are_bound_not_formatted() {
+        let query = LogQuery {
+            from: Some(Utc.with_ymd_and_hms(2025, 3, 9, 0, 0, 0).unwrap()),
+            source_ip: Some("10.0.0.0/8".parse().unwrap()),
+            event_type: Some(EventType::from("SQL Injection' OR 1=1 --")),
+            search: Some("wp-admin".to_string()),
+            limit: Some(10),
+            ..Default::default()
+        };
+        let (sql, params) = query.to_sql("logs");
+
+        assert!(!sql.contains("OR 1=1"));
+        assert!(sql.contains(
+            "WHERE timestamp >= toDateTime(?) AND source_ip BETWEEN toIPv6(?) AND toIPv6(?) AND event_type = ?"
+        ));
+        assert_eq!(params[0], Param::Int(1741478400));
+        assert_eq!(params[1], Param::Str("10.0.0.0".to_string()));
+        assert_eq!(params[2], Param::Str("10.255.255.255".to_string()));
+        assert_eq!(params[3], Param::Str("SQL Injection' OR 1=1 --".to_string()));
+        assert_eq!(params.len(), 4 + 3 + 1);
+        assert_eq!(params.last(), Some(&Param::Int(11)));
+    }
+
+    #[test]
+    fn test_cursor_continues_after_last_row() {
+        let cursor = Cursor { value: "185.93.89.118".to_string(), id: "1f45a2d3-8912-4c3e-b76f-12a8fd2f6e19".parse().unwrap() };
+        let query = LogQuery {
+            sort: SortField::SourceIp,
+            order: SortOrder::Asc,
+            cursor: Some(Cursor::decode(&cursor.encode()).unwrap()),
+            ..Default::default()
+        };
+        let (sql, params) = query.to_sql("logs");
+
+        assert!(sql.contains("WHERE (source_ip, id) > (toIPv6(?), toUUID(?)) ORDER BY source_ip ASC, id ASC"));
+        assert_eq!(params[0], Param::Str(cursor.value));
+        assert_eq!(params[1], Param::Str(cursor.id.to_string()));
+    }
+
+    #[test]
+    fn test_cursor_must_fit_sort_field() {
+        let cursor = Cursor { value: "185.93.89.118".to_string(), id: Uuid::nil() };
+        assert!(cursor.fits(SortField::SourceIp));
+        assert!(!cursor.fits(SortField::Timestamp));
+        assert!(!cursor.fits(SortField::ThreatLevel));
+    }
+
+    fn row(id: u128, source_ip: &str, threat_level: ThreatLevel) -> DbLogEntry {
+        DbLogEntry {
+            id: Uuid::from_u128(id),
+            timestamp: Utc.with_ymd_and_hms(2025, 3, 9, 12, 0, 0).unwrap(),
+            source_ip: source_ip.parse().unwrap(),
+            country: String::new(),
+            asn: 0,
+            event_type: EventType::SshFailedLogin,
+            targeted_service: "SSH".to_string(),
+            targeted_endpoint: "user root".to_string(),
+            request: "Failed password for root".to_string(),
+            status: 0,
+            action_taken: ActionTaken::Logged,
+            threat_level,
+            host: String::new(),
+            agent_id: String::new(),
+            environment: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_paginate_orders_by_typed_values() {
+        let rows = vec![
+            row(1, "10.0.0.9", ThreatLevel::Critical),
+            row(2, "10.0.0.10", ThreatLevel::Low),
+            row(3, "2001:db8::1", ThreatLevel::High),
+        ];
+
+        // Numeric, not lexicographic, address order, and IPv4 before IPv6 as in the column.
+        let query = LogQuery { sort: SortField::SourceIp, order: SortOrder::Asc, ..Default::default() };
+        let ids: Vec<u128> = query.paginate(rows.clone()).logs.iter().map(|row| row.id.as_u128()).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+
+        // Severity, not alphabetical, order.
+        let query = LogQuery { sort: SortField::ThreatLevel, limit: Some(2), ..Default::default() };
+        let page = query.paginate(rows.clone());
+        let ids: Vec<u128> = page.logs.iter().map(|row| row.id.as_u128()).collect();
+        assert_eq!(ids, vec![1, 3]);
+
+        let query = LogQuery { cursor: Cursor::decode(&page.next_cursor.unwrap()), ..query };
+        let ids: Vec<u128> = query.paginate(rows).logs.iter().map(|row| row.id.as_u128()).collect();
+        assert_eq!(ids, vec![2]);
+    }
+
+    #[test]
+    fn test_matches_ip_ranges() {
+        let query = LogQuery { source_ip: Some("10.0.0.0/28".parse().unwrap()), ..Default::default() };
+        assert!(query.matches(&row(1, "10.0.0.9", ThreatLevel::Low)));
+        assert!(!query.matches(&row(2, "10.0.0.16", ThreatLevel::Low)));
+        assert!(!query.matches(&row(3, "2001:db8::1", ThreatLevel::Low)));
+    }
+
+    #[test]
+    fn test_invalid_cursor_is_rejected() {
+        assert_eq!(Cursor::decode("not a cursor"), None);
+    }
+
+    #[test]
+    fn test_count_and_delete_share_filters() {
+        let query = LogQuery { threat_level: Some(ThreatLevel::High), limit: Some(5), ..Default::default() };
+
+        let (sql, params) = query.count_sql("logs");
+        assert_eq!(sql, "SELECT count() FROM logs WHERE threat_level = ?");
+        assert_eq!(params, vec![Param::Str("High".to_string())]);
+
+        let (sql, _) = query.delete_sql("logs");
+        assert_eq!(sql, "DELETE FROM logs WHERE threat_level = ?");
+        assert_eq!(LogQuery::default().delete_sql("logs").0, "DELETE FROM logs WHERE 1");
+    }
+
+    #[test]
+    fn test_filters_by_host_and_environment() {
+        let query = LogQuery { host: Some("web1".to_string()), environment: Some("production".to_string()), ..Default::default() };
+        let (sql, params) = query.count_sql("logs");
+        assert_eq!(sql, "SELECT count() FROM logs WHERE host = ? AND environment = ?");
+        assert_eq!(params, vec![Param::Str("web1".to_string()), Param::Str("production".to_string())]);
+
+        let mut web1 = row(1, "10.0.0.9", ThreatLevel::Low);
+        web1.host = "web1".to_string();
+        web1.environment = "production".to_string();
+        assert!(query.matches(&web1));
+        assert!(!query.matches(&row(2, "10.0.0.9", ThreatLevel::Low)));
+    }
+
+    #[test]
+    fn test_limit_is_clamped() {
+        assert_eq!(LogQuery { limit: Some(0), ..Default::default() }.limit(), 1);
+        assert_eq!(LogQuery { limit: Some(100_000), ..Default::default() }.limit(), MAX_LIMIT);
+    }
+}
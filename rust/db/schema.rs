@@ -1,43 +1,326 @@
-use clickhouse::{Client, Row};
+use std::fmt;
+use std::net::{IpAddr, Ipv6Addr};
+use std::str::FromStr;
+
+use chrono::{DateTime, Utc};
+use clickhouse::Row;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use uuid::Uuid;
-use serde::{Deserialize, Serialize};
 
 
+/// One classified event, as stored in the `logs` table.
+///
+/// Fields are in column order. JSON keeps the API's string forms, while the
+/// RowBinary encoding uses the native ClickHouse types.
 #[derive(Debug, Serialize, Deserialize, Clone, Row)]
 pub struct DbLogEntry {
-    pub id: String,
-    pub timestamp: String,
-    pub source_ip: String,
-    pub event_type: String,
+    #[serde(with = "clickhouse::serde::uuid")]
+    pub id: Uuid,
+    #[serde(with = "timestamp")]
+    pub timestamp: DateTime<Utc>,
+    /// Stored as `IPv6`, IPv4 addresses as `::ffff:a.b.c.d`.
+    #[serde(with = "ipv6")]
+    pub source_ip: IpAddr,
+    /// ISO 3166 country code of `source_ip`, empty when unknown.
+    #[serde(default)]
+    pub country: String,
+    /// Autonomous system number of `source_ip`, 0 when unknown.
+    #[serde(default)]
+    pub asn: u32,
+    pub event_type: EventType,
     pub targeted_service: String,
     pub targeted_endpoint: String,
     pub request: String,
-    pub status: String,
-    pub action_taken: String,
-    pub threat_level: String,
-}
-
-
-pub async fn setup_schema(client: &Client) -> Result<(), Box<dyn std::error::Error>> {
-    let query = r#"
-        CREATE TABLE IF NOT EXISTS logs (
-            id UUID DEFAULT generateUUIDv4(),
-            timestamp DateTime default now(),
-            source_ip String,
-            event_type LowCardinality(String),
-            targeted_service String,
-            targeted_endpoint String,
-            request String,
-            status String,
-            action_taken String,
-            threat_level String
-        ) ENGINE = MergeTree()
-        ORDER BY (timestamp, source_ip, event_type)
-        PARTITION BY toYYYYMM(timestamp)
-        TTL timestamp + INTERVAL 90 DAY;
-    "#;
-
-    client.query(query).execute().await?;
-    println!("ClickHouse logs table ensured!");
-    Ok(())
+    /// HTTP status code, 0 for events without one.
+    pub status: u16,
+    pub action_taken: ActionTaken,
+    pub threat_level: ThreatLevel,
+    /// Machine the event was logged on, empty when unknown.
+    #[serde(default)]
+    pub host: String,
+    /// Agent that ingested the event.
+    #[serde(default)]
+    pub agent_id: String,
+    /// Deployment label of the host, e.g. `production`.
+    #[serde(default)]
+    pub environment: String,
+}
+
+/// Defines an enum stored as a `LowCardinality(String)` label. Labels this
+/// version does not know, e.g. from rows written by other tools, are kept in
+/// `Other` so reading them never fails.
+macro_rules! label_enum {
+    ($(#[$meta:meta])* $name:ident { $($variant:ident => $label:literal,)* }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        #[serde(from = "String", into = "String")]
+        pub enum $name {
+            $($variant,)*
+            Other(String),
+        }
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $($name::$variant => $label,)*
+                    $name::Other(label) => label,
+                }
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(label: String) -> Self {
+                match label.as_str() {
+                    $($label => $name::$variant,)*
+                    _ => $name::Other(label),
+                }
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(label: &str) -> Self {
+                Self::from(label.to_string())
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> Self {
+                match value {
+                    $name::Other(label) => label,
+                    known => known.as_str().to_string(),
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+    };
+}
+
+label_enum! {
+    /// What an event is, e.g. `HTTP Not Found` or `SSH Failed Login`.
+    EventType {
+        HttpRequest => "HTTP Request",
+        HttpNotFound => "HTTP Not Found",
+        HttpAccessDenied => "HTTP Access Denied",
+        HttpClientError => "HTTP Client Error",
+        HttpServerError => "HTTP Server Error",
+        SshLogin => "SSH Login",
+        SshFailedLogin => "SSH Failed Login",
+        SshSession => "SSH Session",
+        SshBruteForce => "SSH Brute Force",
+        PasswordSpray => "Password Spray",
+        UserEnumeration => "User Enumeration",
+        DistributedBruteForce => "Distributed Brute Force",
+        LoginAfterBruteForce => "Login After Brute Force",
+        SqlInjection => "SQL Injection",
+        PathTraversal => "Path Traversal",
+        CrossSiteScripting => "Cross-Site Scripting",
+        CommandInjection => "Command Injection",
+        ScannerProbe => "Scanner Probe",
+        ExploitAttempt => "Exploit Attempt",
+    }
+}
+
+label_enum! {
+    /// What cephalog did about an event.
+    ActionTaken {
+        Logged => "Logged",
+        Alerted => "Alerted",
+        Blocked => "Blocked",
+    }
+}
+
+/// Severity of an event. Stored as an `Enum8`, so ClickHouse sorts it by
+/// severity rather than alphabetically.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ThreatLevel {
+    #[default]
+    Info,
+    Low,
+    Medium,
+    High,
+    Critical,
+}
+
+impl ThreatLevel {
+    pub const ALL: [ThreatLevel; 5] =
+        [ThreatLevel::Info, ThreatLevel::Low, ThreatLevel::Medium, ThreatLevel::High, ThreatLevel::Critical];
+
+    /// The ClickHouse column type, values numbered in severity order.
+    pub const COLUMN_TYPE: &'static str =
+        "Enum8('Info' = 0, 'Low' = 1, 'Medium' = 2, 'High' = 3, 'Critical' = 4)";
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ThreatLevel::Info => "Info",
+            ThreatLevel::Low => "Low",
+            ThreatLevel::Medium => "Medium",
+            ThreatLevel::High => "High",
+            ThreatLevel::Critical => "Critical",
+        }
+    }
+}
+
+impl FromStr for ThreatLevel {
+    type Err = String;
+
+    /// Case-insensitive, so `high` and `High` are the same level.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ThreatLevel::ALL
+            .into_iter()
+            .find(|level| level.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| format!("unknown threat level: {}", s))
+    }
+}
+
+impl fmt::Display for ThreatLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for ThreatLevel {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(self.as_str())
+        } else {
+            serializer.serialize_i8(*self as i8)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ThreatLevel {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error;
+
+        if deserializer.is_human_readable() {
+            let label = String::deserialize(deserializer)?;
+            label.parse().map_err(D::Error::custom)
+        } else {
+            let value = i8::deserialize(deserializer)?;
+            ThreatLevel::ALL
+                .get(value as usize)
+                .copied()
+                .ok_or_else(|| D::Error::custom(format!("unknown threat level value: {}", value)))
+        }
+    }
+}
+
+/// The address as stored in an `IPv6` column, IPv4 mapped into `::ffff:0:0/96`.
+pub fn to_ipv6(ip: IpAddr) -> Ipv6Addr {
+    match ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    }
+}
+
+/// `DateTime<Utc>` as `YYYY-MM-DD HH:MM:SS` in JSON and a `DateTime` column in RowBinary.
+mod timestamp {
+    use chrono::{DateTime, NaiveDateTime, Utc};
+    use serde::de::Error as _;
+    use serde::ser::Error as _;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    const FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+    pub fn serialize<S: Serializer>(timestamp: &DateTime<Utc>, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.collect_str(&timestamp.format(FORMAT))
+        } else {
+            u32::try_from(timestamp.timestamp())
+                .map_err(|_| S::Error::custom(format!("{} is out of range for DateTime", timestamp)))?
+                .serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<DateTime<Utc>, D::Error> {
+        if deserializer.is_human_readable() {
+            let value = String::deserialize(deserializer)?;
+            NaiveDateTime::parse_from_str(&value, FORMAT)
+                .map(|dt| dt.and_utc())
+                .or_else(|_| DateTime::parse_from_rfc3339(&value).map(|dt| dt.to_utc()))
+                .map_err(|_| D::Error::custom(format!("invalid timestamp: {}", value)))
+        } else {
+            let seconds = u32::deserialize(deserializer)?;
+            DateTime::from_timestamp(i64::from(seconds), 0)
+                .ok_or_else(|| D::Error::custom(format!("invalid timestamp: {}", seconds)))
+        }
+    }
+}
+
+/// `IpAddr` as a string in JSON and an `IPv6` column in RowBinary.
+mod ipv6 {
+    use std::net::{IpAddr, Ipv6Addr};
+
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::to_ipv6;
+
+    pub fn serialize<S: Serializer>(ip: &IpAddr, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            ip.serialize(serializer)
+        } else {
+            to_ipv6(*ip).octets().serialize(serializer)
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<IpAddr, D::Error> {
+        if deserializer.is_human_readable() {
+            IpAddr::deserialize(deserializer)
+        } else {
+            let octets = <[u8; 16]>::deserialize(deserializer)?;
+            Ok(Ipv6Addr::from(octets).to_canonical())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_labels_round_trip() {
+        assert_eq!(EventType::from("SSH Failed Login"), EventType::SshFailedLogin);
+        assert_eq!(EventType::from("Port Scanning"), EventType::Other("Port Scanning".to_string()));
+        assert_eq!(String::from(EventType::HttpNotFound), "HTTP Not Found");
+        assert_eq!(ActionTaken::Other("Banned (Fail2Ban)".to_string()).to_string(), "Banned (Fail2Ban)");
+    }
+
+    #[test]
+    fn test_threat_levels_order_by_severity() {
+        assert!(ThreatLevel::Critical > ThreatLevel::High);
+        assert!(ThreatLevel::Low > ThreatLevel::Info);
+        assert_eq!("high".parse::<ThreatLevel>(), Ok(ThreatLevel::High));
+        assert!("severe".parse::<ThreatLevel>().is_err());
+    }
+
+    #[test]
+    fn test_json_uses_readable_forms() {
+        let json = r#"{
+            "id": "1f45a2d3-8912-4c3e-b76f-12a8fd2f6e19",
+            "timestamp": "2025-03-09 12:34:56",
+            "source_ip": "185.93.89.118",
+            "event_type": "SSH Failed Login",
+            "targeted_service": "SSH",
+            "targeted_endpoint": "user root",
+            "request": "Failed password for root",
+            "status": 0,
+            "action_taken": "Logged",
+            "threat_level": "High"
+        }"#;
+        let entry: DbLogEntry = serde_json::from_str(json).unwrap();
+        assert_eq!(entry.timestamp.timestamp(), 1741523696);
+        assert_eq!(entry.source_ip, "185.93.89.118".parse::<IpAddr>().unwrap());
+        assert_eq!(entry.event_type, EventType::SshFailedLogin);
+        assert_eq!(entry.threat_level, ThreatLevel::High);
+
+        let value = serde_json::to_value(&entry).unwrap();
+        assert_eq!(value["timestamp"], "2025-03-09 12:34:56");
+        assert_eq!(value["source_ip"], "185.93.89.118");
+        assert_eq!(value["threat_level"], "High");
+    }
 }
@@ -0,0 +1,593 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use serde::Serialize;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep, MissedTickBehavior};
+use tracing::{error, warn};
+
+use crate::database::{BatchInsert, Database, DbResult};
+use crate::error::DbError;
+use crate::schema::DbLogEntry;
+
+/// When the writer flushes and how it retries failed batches.
+#[derive(Debug, Clone)]
+pub struct WriterConfig {
+    /// Rows buffered before a flush is forced.
+    pub max_rows: usize,
+    /// Approximate RowBinary bytes buffered before a flush is forced.
+    pub max_bytes: u64,
+    /// Longest a row waits in the buffer before it is written.
+    pub period: Duration,
+    /// Rows queued between producers and the writer. `LogWriter::write` waits
+    /// while the queue is full, which slows ingestion down instead of growing memory.
+    pub queue: usize,
+    /// Tries per batch, including the first, before the batch is dropped once
+    /// the writer is shutting down. Until then transient failures are retried
+    /// for as long as they last, holding producers up through the queue.
+    pub max_attempts: u32,
+    /// Wait before the first retry, doubled after each failure.
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for WriterConfig {
+    fn default() -> Self {
+        Self {
+            max_rows: 500,
+            max_bytes: 1024 * 1024,
+            period: Duration::from_secs(1),
+            queue: 10_000,
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Totals since the writer started: rows and bytes committed, rows dropped
+/// after their last attempt, and the error that made the writer drop its most
+/// recent failed batch.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct InsertResult {
+    pub rows: u64,
+    pub bytes: u64,
+    pub dropped: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Default)]
+struct Metrics {
+    rows: AtomicU64,
+    bytes: AtomicU64,
+    dropped: AtomicU64,
+    error: Mutex<Option<String>>,
+}
+
+/// Read access to a writer's metrics that, unlike `LogWriter`, does not keep the writer running.
+#[derive(Clone, Default)]
+pub struct WriterStats(Arc<Metrics>);
+
+impl WriterStats {
+    pub fn snapshot(&self) -> InsertResult {
+        InsertResult {
+            rows: self.0.rows.load(Ordering::Relaxed),
+            bytes: self.0.bytes.load(Ordering::Relaxed),
+            dropped: self.0.dropped.load(Ordering::Relaxed),
+            error: self.0.error.lock().unwrap().clone(),
+        }
+    }
+}
+
+/// Called once the rows queued before it have been committed.
+type OnCommit = Box<dyn FnOnce() + Send>;
+
+// Nearly everything queued is a row, boxing them would cost an allocation each.
+#[allow(clippy::large_enum_variant)]
+enum Queued {
+    Row(DbLogEntry),
+    OnCommit(OnCommit),
+}
+
+/// Handle to a background task that writes rows in batches.
+///
+/// Batches go through the database's `BatchInsert`, for ClickHouse an
+/// `Inserter`. Rows are also kept here until committed, so a batch that
+/// fails can be sent again. Dropping every handle flushes the buffer and
+/// stops the task.
+#[derive(Clone)]
+pub struct LogWriter {
+    sender: mpsc::Sender<Queued>,
+    metrics: Arc<Metrics>,
+}
+
+impl LogWriter {
+    /// Starts the writer task. The returned handle resolves once the last
+    /// `LogWriter` is dropped and the final batch has been written.
+    pub fn spawn(db: Arc<dyn Database>, config: WriterConfig) -> (Self, JoinHandle<()>) {
+        let (sender, receiver) = mpsc::channel(config.queue.max(1));
+        let metrics = Arc::new(Metrics::default());
+        let batcher = Batcher {
+            inserter: db.batch_insert(),
+            metrics: metrics.clone(),
+            rows: Vec::with_capacity(config.max_rows),
+            bytes: 0,
+            on_commit: Vec::new(),
+            dropped: false,
+            config,
+        };
+
+        (Self { sender, metrics }, tokio::spawn(batcher.run(receiver)))
+    }
+
+    /// Queues `row`, waiting for room if the queue is full.
+    pub async fn write(&self, row: DbLogEntry) -> Result<(), DbError> {
+        self.send(Queued::Row(row)).await
+    }
+
+    /// Queues `on_commit` to run on the writer task once every row queued
+    /// before it has been committed, e.g. to save how far a source was read.
+    /// It is discarded if any rows of the batch it waits for are dropped, so
+    /// the source reads them again after a restart. Callbacks queued after
+    /// that batch run as usual.
+    pub async fn on_commit(&self, on_commit: impl FnOnce() + Send + 'static) -> Result<(), DbError> {
+        self.send(Queued::OnCommit(Box::new(on_commit))).await
+    }
+
+    async fn send(&self, queued: Queued) -> Result<(), DbError> {
+        self.sender
+            .send(queued)
+            .await
+            .map_err(|_| DbError::Connection("log writer has stopped".to_string()))
+    }
+
+    pub fn stats(&self) -> WriterStats {
+        WriterStats(self.metrics.clone())
+    }
+}
+
+/// Sends every batch with `Database::insert_logs`.
+pub(crate) struct InsertLogs<D: ?Sized>(pub(crate) Arc<D>);
+
+#[async_trait::async_trait]
+impl<D: Database + ?Sized> BatchInsert for InsertLogs<D> {
+    async fn insert(&mut self, rows: &[DbLogEntry]) -> DbResult<u64> {
+        self.0.insert_logs(rows.to_vec()).await?;
+        Ok(rows.iter().map(encoded_len).sum())
+    }
+}
+
+struct Batcher {
+    inserter: Box<dyn BatchInsert>,
+    config: WriterConfig,
+    metrics: Arc<Metrics>,
+    rows: Vec<DbLogEntry>,
+    bytes: u64,
+    /// Callbacks waiting for the buffered rows to be committed, with the
+    /// number of rows buffered before each.
+    on_commit: Vec<(usize, OnCommit)>,
+    /// Set when rows queued since the last callback were dropped, so the next
+    /// callback is discarded as well.
+    dropped: bool,
+}
+
+impl Batcher {
+    async fn run(mut self, mut receiver: mpsc::Receiver<Queued>) {
+        let mut ticker = interval(self.config.period);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+        loop {
+            tokio::select! {
+                queued = receiver.recv() => match queued {
+                    Some(Queued::Row(row)) => {
+                        self.bytes += encoded_len(&row);
+                        self.rows.push(row);
+                        if self.rows.len() >= self.config.max_rows || self.bytes >= self.config.max_bytes {
+                            self.flush(&receiver).await;
+                        }
+                    }
+                    Some(Queued::OnCommit(on_commit)) => {
+                        if std::mem::take(&mut self.dropped) {
+                            continue;
+                        }
+                        self.on_commit.push((self.rows.len(), on_commit));
+                        if self.rows.is_empty() {
+                            self.committed(0);
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => self.flush(&receiver).await,
+            }
+        }
+
+        self.flush(&receiver).await;
+    }
+
+    /// Writes the buffered rows. New rows wait in the queue meanwhile, and so
+    /// do producers once it is full. A batch rejected because of some of its
+    /// rows is split in halves until only those rows are left to drop.
+    async fn flush(&mut self, receiver: &mpsc::Receiver<Queued>) {
+        if self.rows.is_empty() {
+            return;
+        }
+
+        let rows = std::mem::replace(&mut self.rows, Vec::with_capacity(self.config.max_rows));
+        self.bytes = 0;
+        // Indexes of the first and last dropped rows.
+        let mut lost: Option<(usize, usize)> = None;
+        // Parts still to insert with the index of their first row, next one last.
+        let mut parts = vec![(0, rows.as_slice())];
+
+        while let Some((start, part)) = parts.pop() {
+            match self.insert(part, receiver).await {
+                Ok(()) => {}
+                Err(e) if e.is_row_error() && part.len() > 1 => {
+                    let (left, right) = part.split_at(part.len() / 2);
+                    parts.push((start + left.len(), right));
+                    parts.push((start, left));
+                }
+                Err(e) => {
+                    error!("Dropping {} log rows: {}", part.len(), e);
+                    self.metrics.dropped.fetch_add(part.len() as u64, Ordering::Relaxed);
+                    *self.metrics.error.lock().unwrap() = Some(e.to_string());
+                    let end = start + part.len() - 1;
+                    lost = Some(lost.map_or((start, end), |(first, _)| (first, end)));
+                }
+            }
+        }
+
+        match lost {
+            None => self.committed(rows.len()),
+            Some((first, last)) => {
+                // Rows after the last callback are waited for by the next one.
+                self.dropped = self.on_commit.last().is_none_or(|(before, _)| *before <= last);
+                self.committed(first);
+                self.on_commit.clear();
+            }
+        }
+    }
+
+    /// Inserts `rows`, retrying transient failures with exponential backoff.
+    async fn insert(&mut self, rows: &[DbLogEntry], receiver: &mpsc::Receiver<Queued>) -> Result<(), DbError> {
+        let mut backoff = self.config.initial_backoff;
+        let mut attempt = 1;
+
+        loop {
+            match self.inserter.insert(rows).await {
+                Ok(bytes) => {
+                    self.metrics.rows.fetch_add(rows.len() as u64, Ordering::Relaxed);
+                    self.metrics.bytes.fetch_add(bytes, Ordering::Relaxed);
+                    return Ok(());
+                }
+                // Only give up once nothing will be queued anymore, i.e. on shutdown.
+                Err(e) if e.is_transient() && (attempt < self.config.max_attempts || !receiver.is_closed()) => {
+                    warn!("Insert of {} rows failed (attempt {}), retrying in {:?}: {}", rows.len(), attempt, backoff, e);
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(self.config.max_backoff);
+                    attempt += 1;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Runs the callbacks that wait for no more than the first `rows` buffered rows.
+    fn committed(&mut self, rows: usize) {
+        let ready = self.on_commit.partition_point(|(before, _)| *before <= rows);
+        for (_, on_commit) in self.on_commit.drain(..ready) {
+            on_commit();
+        }
+    }
+}
+
+/// Size of `row` in RowBinary, the format rows are sent in.
+fn encoded_len(row: &DbLogEntry) -> u64 {
+    // id, timestamp, source_ip, asn, status and threat_level. Every other
+    // column is a string.
+    const FIXED: u64 = 16 + 4 + 16 + 4 + 2 + 1;
+
+    let strings = [
+        row.country.as_str(),
+        row.event_type.as_str(),
+        row.targeted_service.as_str(),
+        row.targeted_endpoint.as_str(),
+        row.request.as_str(),
+        row.action_taken.as_str(),
+        row.host.as_str(),
+        row.agent_id.as_str(),
+        row.environment.as_str(),
+    ];
+    FIXED + strings.iter().map(|s| leb128_len(s.len() as u64) + s.len() as u64).sum::<u64>()
+}
+
+/// Bytes of the LEB128 length prefix written before a string.
+fn leb128_len(mut value: u64) -> u64 {
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicU32;
+
+    use chrono::Utc;
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::database::DbResult;
+    use crate::mock::database::MockDB;
+    use crate::query::{LogPage, LogQuery};
+    use crate::schema::{ActionTaken, EventType, ThreatLevel};
+
+    /// Fails the first `failures` inserts with `error`, then behaves like `MockDB`.
+    struct FlakyDB {
+        inner: MockDB,
+        failures: AtomicU32,
+        error: fn() -> DbError,
+    }
+
+    impl FlakyDB {
+        fn new(failures: u32, error: fn() -> DbError) -> Arc<Self> {
+            Arc::new(Self { inner: MockDB::new(), failures: AtomicU32::new(failures), error })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Database for FlakyDB {
+        async fn insert_log(&self, log: DbLogEntry) -> DbResult<()> {
+            self.insert_logs(vec![log]).await
+        }
+
+        async fn insert_logs(&self, logs: Vec<DbLogEntry>) -> DbResult<()> {
+            let remaining = self.failures.load(Ordering::SeqCst);
+            if remaining > 0 {
+                self.failures.store(remaining - 1, Ordering::SeqCst);
+                return Err((self.error)());
+            }
+            self.inner.insert_logs(logs).await
+        }
+
+        async fn query_logs(&self, query: &LogQuery) -> DbResult<LogPage> {
+            self.inner.query_logs(query).await
+        }
+
+        async fn count_logs(&self, query: &LogQuery) -> DbResult<u64> {
+            self.inner.count_logs(query).await
+        }
+
+        async fn delete_logs(&self, query: &LogQuery) -> DbResult<()> {
+            self.inner.delete_logs(query).await
+        }
+    }
+
+    /// Fails to encode any insert that contains a row whose request is `poison`.
+    #[derive(Default)]
+    struct PoisonedDB {
+        inner: MockDB,
+        inserts: AtomicU32,
+    }
+
+    #[async_trait::async_trait]
+    impl Database for PoisonedDB {
+        async fn insert_log(&self, log: DbLogEntry) -> DbResult<()> {
+            self.insert_logs(vec![log]).await
+        }
+
+        async fn insert_logs(&self, logs: Vec<DbLogEntry>) -> DbResult<()> {
+            self.inserts.fetch_add(1, Ordering::SeqCst);
+            if logs.iter().any(|log| log.request == "poison") {
+                return Err(DbError::Serialization("timestamp out of range".to_string()));
+            }
+            self.inner.insert_logs(logs).await
+        }
+
+        async fn query_logs(&self, query: &LogQuery) -> DbResult<LogPage> {
+            self.inner.query_logs(query).await
+        }
+
+        async fn count_logs(&self, query: &LogQuery) -> DbResult<u64> {
+            self.inner.count_logs(query).await
+        }
+
+        async fn delete_logs(&self, query: &LogQuery) -> DbResult<()> {
+            self.inner.delete_logs(query).await
+        }
+    }
+
+    fn config() -> WriterConfig {
+        WriterConfig {
+            max_rows: 2,
+            period: Duration::from_secs(3600),
+            initial_backoff: Duration::from_millis(1),
+            max_attempts: 3,
+            ..Default::default()
+        }
+    }
+
+    fn row() -> DbLogEntry {
+        DbLogEntry {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            source_ip: "10.0.0.1".parse().unwrap(),
+            country: String::new(),
+            asn: 0,
+            event_type: EventType::SshFailedLogin,
+            targeted_service: "SSH".to_string(),
+            targeted_endpoint: "user root".to_string(),
+            request: "Failed password for root".to_string(),
+            status: 0,
+            action_taken: ActionTaken::Logged,
+            threat_level: ThreatLevel::Low,
+            host: String::new(),
+            agent_id: String::new(),
+            environment: String::new(),
+        }
+    }
+
+    async fn count(db: &dyn Database) -> u64 {
+        db.count_logs(&LogQuery::default()).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_flushes_when_batch_is_full() {
+        let db = Arc::new(MockDB::new());
+        let (writer, _task) = LogWriter::spawn(db.clone(), config());
+
+        writer.write(row()).await.unwrap();
+        writer.write(row()).await.unwrap();
+        for _ in 0..100 {
+            if count(db.as_ref()).await == 2 {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(count(db.as_ref()).await, 2);
+        assert_eq!(writer.stats().snapshot().rows, 2);
+        assert_eq!(writer.stats().snapshot().bytes, 2 * encoded_len(&row()));
+    }
+
+    #[tokio::test]
+    async fn test_remaining_rows_are_written_on_shutdown() {
+        let db = Arc::new(MockDB::new());
+        let (writer, task) = LogWriter::spawn(db.clone(), config());
+
+        writer.write(row()).await.unwrap();
+        drop(writer);
+        task.await.unwrap();
+        assert_eq!(count(db.as_ref()).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_are_retried() {
+        let db = FlakyDB::new(2, || DbError::Connection("connection refused".to_string()));
+        let (writer, task) = LogWriter::spawn(db.clone(), config());
+
+        writer.write(row()).await.unwrap();
+        let stats = writer.stats();
+        drop(writer);
+        task.await.unwrap();
+
+        assert_eq!(count(db.as_ref()).await, 1);
+        assert_eq!(stats.snapshot().rows, 1);
+        assert_eq!(stats.snapshot().error, None);
+    }
+
+    #[tokio::test]
+    async fn test_batch_is_dropped_after_last_attempt() {
+        let db = FlakyDB::new(10, || DbError::Connection("connection refused".to_string()));
+        let (writer, task) = LogWriter::spawn(db.clone(), config());
+
+        writer.write(row()).await.unwrap();
+        let stats = writer.stats();
+        drop(writer);
+        task.await.unwrap();
+
+        assert_eq!(count(db.as_ref()).await, 0);
+        assert_eq!(stats.snapshot().rows, 0);
+        assert_eq!(stats.snapshot().dropped, 1);
+        assert!(stats.snapshot().error.unwrap().contains("connection refused"));
+        // Three attempts were made, the rest of the failures are still queued up.
+        assert_eq!(db.failures.load(Ordering::SeqCst), 7);
+    }
+
+    #[tokio::test]
+    async fn test_transient_failures_hold_up_callbacks_until_committed() {
+        let db = FlakyDB::new(5, || DbError::Connection("connection refused".to_string()));
+        let (writer, _task) = LogWriter::spawn(db.clone(), config());
+        let (committed, mut on_commit) = tokio::sync::oneshot::channel();
+
+        writer.write(row()).await.unwrap();
+        writer.write(row()).await.unwrap();
+        writer.on_commit(move || committed.send(()).unwrap()).await.unwrap();
+        assert!(on_commit.try_recv().is_err());
+
+        // More failures than `max_attempts`, but the writer is still running.
+        on_commit.await.unwrap();
+        assert_eq!(count(db.as_ref()).await, 2);
+        assert_eq!(writer.stats().snapshot().dropped, 0);
+    }
+
+    #[tokio::test]
+    async fn test_only_callbacks_of_a_dropped_batch_are_discarded() {
+        let db = FlakyDB::new(1, || DbError::SchemaMismatch("no such column".to_string()));
+        let (writer, task) = LogWriter::spawn(db.clone(), config());
+        let runs = Arc::new(AtomicU32::new(0));
+
+        for _ in 0..2 {
+            writer.write(row()).await.unwrap();
+            writer.write(row()).await.unwrap();
+            let runs = runs.clone();
+            writer.on_commit(move || { runs.fetch_add(1, Ordering::SeqCst); }).await.unwrap();
+        }
+        drop(writer);
+        task.await.unwrap();
+
+        // The first batch is lost, so only the second one's callback runs.
+        assert_eq!(count(db.as_ref()).await, 2);
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_only_poisoned_rows_of_a_batch_are_dropped() {
+        let db = Arc::new(PoisonedDB::default());
+        let (writer, task) = LogWriter::spawn(db.clone(), WriterConfig { max_rows: 500, ..config() });
+        let runs = Arc::new(AtomicU32::new(0));
+
+        for i in 0..500 {
+            let request = if i == 300 { "poison" } else { "Failed password for root" };
+            writer.write(DbLogEntry { request: request.to_string(), ..row() }).await.unwrap();
+            if i == 299 || i == 300 {
+                let runs = runs.clone();
+                writer.on_commit(move || { runs.fetch_add(1, Ordering::SeqCst); }).await.unwrap();
+            }
+        }
+        let stats = writer.stats();
+        drop(writer);
+        task.await.unwrap();
+
+        assert_eq!(count(db.as_ref()).await, 499);
+        assert_eq!(stats.snapshot().rows, 499);
+        assert_eq!(stats.snapshot().dropped, 1);
+        // Split in halves down to the poisoned row, not retried row by row.
+        assert!(db.inserts.load(Ordering::SeqCst) < 30);
+        // Only the callback queued before the poisoned row runs.
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_permanent_failures_are_not_retried() {
+        let db = FlakyDB::new(10, || DbError::SchemaMismatch("no such column".to_string()));
+        let (writer, task) = LogWriter::spawn(db.clone(), config());
+
+        writer.write(row()).await.unwrap();
+        drop(writer);
+        task.await.unwrap();
+        assert_eq!(db.failures.load(Ordering::SeqCst), 9);
+    }
+
+    #[test]
+    fn test_encoded_len_counts_identity_columns() {
+        let labelled = DbLogEntry {
+            host: "web1".to_string(),
+            agent_id: "agent-1".to_string(),
+            environment: "production".to_string(),
+            ..row()
+        };
+        assert_eq!(encoded_len(&labelled), encoded_len(&row()) + 4 + 7 + 10);
+    }
+
+    #[test]
+    fn test_leb128_len() {
+        assert_eq!(leb128_len(0), 1);
+        assert_eq!(leb128_len(127), 1);
+        assert_eq!(leb128_len(128), 2);
+        assert_eq!(leb128_len(16_384), 3);
+    }
+}
@@ -0,0 +1,350 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use reqwest::header::{CONTENT_ENCODING, CONTENT_TYPE};
+use reqwest::{Certificate, Client, Identity, StatusCode};
+use tokio::sync::{mpsc, Notify};
+use tokio::time::{interval_at, sleep, Instant, MissedTickBehavior};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+use crate::agent::spool::Spool;
+use crate::ingest::checkpoint::{Ingested, PendingCheckpoint};
+use crate::ingest::identity::AgentIdentity;
+use crate::models::batch::{IngestBatch, IngestResponse};
+use crate::models::log::LogEntry;
+
+/// Entries per batch. Larger batches are cheaper to insert but wait longer to fill.
+const MAX_BATCH: usize = 1000;
+/// Longest an entry waits before its batch is spooled. Sources are not
+/// checkpointed past it until then, so a crash re-reads it instead of losing it.
+const BATCH_PERIOD: Duration = Duration::from_secs(2);
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// Where an agent sends its logs and how it authenticates.
+#[derive(Debug, Clone)]
+pub struct AgentConfig {
+    /// Base URL of the central server, e.g. `https://cephalog.internal:3000`.
+    pub server_url: String,
+    pub token: Option<String>,
+    /// Client certificate and PKCS#8 key, both PEM, for servers behind an mTLS proxy.
+    pub client_cert: Option<(PathBuf, PathBuf)>,
+    /// CA to trust for the server's certificate, in addition to the system's.
+    pub ca_cert: Option<PathBuf>,
+    pub spool_dir: PathBuf,
+    pub spool_max_bytes: u64,
+}
+
+impl AgentConfig {
+    /// Reads `AGENT_SERVER_URL`, `AGENT_TOKEN`, `AGENT_CLIENT_CERT` with
+    /// `AGENT_CLIENT_KEY`, `AGENT_CA_CERT`, `SPOOL_DIR` and `SPOOL_MAX_BYTES`
+    /// (default 1 GiB).
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.trim().is_empty());
+
+        let server_url = var("AGENT_SERVER_URL").ok_or("AGENT_SERVER_URL is required in agent mode")?;
+        let client_cert = match (var("AGENT_CLIENT_CERT"), var("AGENT_CLIENT_KEY")) {
+            (Some(cert), Some(key)) => Some((cert.into(), key.into())),
+            (None, None) => None,
+            _ => return Err("AGENT_CLIENT_CERT and AGENT_CLIENT_KEY must be set together".to_string()),
+        };
+        let spool_max_bytes = match var("SPOOL_MAX_BYTES") {
+            Some(bytes) => bytes.parse().map_err(|_| format!("invalid SPOOL_MAX_BYTES: {}", bytes))?,
+            None => 1024 * 1024 * 1024,
+        };
+
+        Ok(Self {
+            server_url,
+            token: var("AGENT_TOKEN"),
+            client_cert,
+            ca_cert: var("AGENT_CA_CERT").map(PathBuf::from),
+            spool_dir: var("SPOOL_DIR").unwrap_or_else(|| "cephalog-spool".to_string()).into(),
+            spool_max_bytes,
+        })
+    }
+}
+
+/// Batches entries into the spool and pushes spooled batches to the server's
+/// `POST /api/v1/ingest`, at least once each.
+pub struct Forwarder {
+    client: Client,
+    url: String,
+    token: Option<String>,
+    spool: Spool,
+    identity: AgentIdentity,
+}
+
+impl Forwarder {
+    pub fn new(config: &AgentConfig, identity: AgentIdentity) -> Result<Self, String> {
+        let mut client = Client::builder().timeout(REQUEST_TIMEOUT);
+        if let Some((cert, key)) = &config.client_cert {
+            let cert = fs::read(cert).map_err(|e| format!("cannot read {}: {}", cert.display(), e))?;
+            let key = fs::read(key).map_err(|e| format!("cannot read {}: {}", key.display(), e))?;
+            let identity = Identity::from_pkcs8_pem(&cert, &key).map_err(|e| format!("invalid client certificate: {}", e))?;
+            client = client.identity(identity);
+        }
+        if let Some(ca) = &config.ca_cert {
+            let pem = fs::read(ca).map_err(|e| format!("cannot read {}: {}", ca.display(), e))?;
+            let ca = Certificate::from_pem(&pem).map_err(|e| format!("invalid CA certificate: {}", e))?;
+            client = client.add_root_certificate(ca);
+        }
+
+        let spool = Spool::open(&config.spool_dir, config.spool_max_bytes)
+            .map_err(|e| format!("cannot open spool {}: {}", config.spool_dir.display(), e))?;
+        Ok(Self {
+            client: client.build().map_err(|e| e.to_string())?,
+            url: format!("{}/api/v1/ingest", config.server_url.trim_end_matches('/')),
+            token: config.token.clone(),
+            spool,
+            identity,
+        })
+    }
+
+    /// Spools entries from `entries` until every sender is dropped, while a
+    /// background task delivers spooled batches. Source checkpoints are saved
+    /// once the batch holding the entries before them is on disk.
+    pub async fn run(self: Arc<Self>, mut entries: mpsc::Receiver<Ingested>) {
+        info!("Forwarding logs to {}", self.url);
+        let ready = Arc::new(Notify::new());
+        tokio::spawn(self.clone().deliver_forever(ready.clone()));
+
+        let mut batch = Vec::new();
+        let mut checkpoints = Vec::new();
+        let mut ticker = interval_at(Instant::now() + BATCH_PERIOD, BATCH_PERIOD);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        loop {
+            tokio::select! {
+                entry = entries.recv() => match entry {
+                    Some(Ingested::Checkpoint(checkpoint)) => {
+                        checkpoints.push(checkpoint);
+                        if batch.is_empty() {
+                            self.spool_batch(&mut batch, &mut checkpoints, &ready).await;
+                        }
+                    }
+                    Some(Ingested::Entry(mut entry)) => {
+                        self.identity.stamp(&mut entry);
+                        batch.push(entry);
+                        if batch.len() >= MAX_BATCH {
+                            self.spool_batch(&mut batch, &mut checkpoints, &ready).await;
+                        }
+                    }
+                    None => break,
+                },
+                _ = ticker.tick() => self.spool_batch(&mut batch, &mut checkpoints, &ready).await,
+            }
+        }
+        self.spool_batch(&mut batch, &mut checkpoints, &ready).await;
+    }
+
+    /// Writes `entries` to the spool, retrying until it succeeds, then saves
+    /// `checkpoints`. Sources are held up through the queue meanwhile.
+    async fn spool_batch(&self, entries: &mut Vec<LogEntry>, checkpoints: &mut Vec<PendingCheckpoint>, ready: &Notify) {
+        if !entries.is_empty() {
+            let batch = IngestBatch { id: Uuid::new_v4(), agent_id: self.identity.agent_id.clone(), entries: std::mem::take(entries) };
+            let mut backoff = INITIAL_BACKOFF;
+            while let Err(e) = self.spool.push(&batch) {
+                error!("Failed to spool {} entries, retrying in {:?}: {}", batch.entries.len(), backoff, e);
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(MAX_BACKOFF);
+            }
+            ready.notify_one();
+        }
+
+        for checkpoint in checkpoints.drain(..) {
+            if let Err(e) = checkpoint.save() {
+                warn!("Failed to save checkpoint: {}", e);
+            }
+        }
+    }
+
+    async fn deliver_forever(self: Arc<Self>, ready: Arc<Notify>) {
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            match self.deliver_pending().await {
+                Ok(_) => {
+                    backoff = INITIAL_BACKOFF;
+                    ready.notified().await;
+                }
+                Err(e) => {
+                    warn!("Failed to deliver spooled batches, retrying in {:?}: {}", backoff, e);
+                    sleep(backoff).await;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                }
+            }
+        }
+    }
+
+    /// Sends every spooled batch, oldest first, removing those the server stored.
+    /// Stops at the first batch that can be retried. Returns the number delivered.
+    pub async fn deliver_pending(&self) -> Result<usize, String> {
+        let mut delivered = 0;
+        for path in self.spool.pending().map_err(|e| e.to_string())? {
+            let body = self.spool.read(&path).map_err(|e| e.to_string())?;
+            let mut request = self
+                .client
+                .post(&self.url)
+                .header(CONTENT_TYPE, "application/json")
+                .header(CONTENT_ENCODING, "gzip")
+                .body(body);
+            if let Some(token) = &self.token {
+                request = request.bearer_auth(token);
+            }
+
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            let status = response.status();
+            if status.is_success() {
+                if let Ok(reply) = response.json::<IngestResponse>().await {
+                    for invalid in reply.invalid {
+                        warn!("Entry {} of {} was invalid: {}", invalid.index, path.display(), invalid.reason);
+                    }
+                }
+                self.spool.remove(&path).map_err(|e| e.to_string())?;
+                delivered += 1;
+            } else if status.is_client_error() && !is_retryable(status) {
+                // Sending it again cannot succeed, so it must not hold up the batches behind it.
+                let reason = response.text().await.unwrap_or_default();
+                error!("Server rejected {} with {}: {}", path.display(), status, reason);
+                self.spool.reject(&path).map_err(|e| e.to_string())?;
+            } else {
+                return Err(format!("server replied {}", status));
+            }
+        }
+        Ok(delivered)
+    }
+}
+
+/// Client errors that go away without changing the batch, e.g. once a token
+/// is fixed or an earlier copy of the batch has been stored.
+fn is_retryable(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::UNAUTHORIZED
+            | StatusCode::FORBIDDEN
+            | StatusCode::REQUEST_TIMEOUT
+            | StatusCode::CONFLICT
+            | StatusCode::TOO_MANY_REQUESTS
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use db::database::Database;
+    use db::mock::database::MockDB;
+
+    use crate::handlers::ingest::IngestState;
+    use crate::ingest::checkpoint::CheckpointStore;
+    use crate::ingest::hub::LogHub;
+    use crate::middleware::auth::IngestAuth;
+    use crate::models::log::LogSource;
+    use crate::routes::configure_routes;
+    use crate::server::state::AppState;
+
+    fn identity() -> AgentIdentity {
+        AgentIdentity { hostname: "web1".to_string(), agent_id: "agent-1".to_string(), environment: None }
+    }
+
+    async fn server(db: Arc<MockDB>) -> String {
+        let auth = IngestAuth::new(vec![("agent-1".to_string(), "s3cret".to_string())], None);
+        let app = configure_routes(AppState {
+            hub: Arc::new(LogHub::new(8)),
+            db,
+            ingest: Arc::new(IngestState::new(auth)),
+            detector: Arc::default(),
+            writer: Default::default(),
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    fn config(server_url: String, token: &str, spool_dir: &std::path::Path) -> AgentConfig {
+        AgentConfig {
+            server_url,
+            token: Some(token.to_string()),
+            client_cert: None,
+            ca_cert: None,
+            spool_dir: spool_dir.to_path_buf(),
+            spool_max_bytes: u64::MAX,
+        }
+    }
+
+    async fn spool_entry(forwarder: &Forwarder, ip: &str) {
+        let mut entry = LogEntry::new(LogSource::AuthLog, Utc::now(), "Failed password for root");
+        entry.ip_address = Some(ip.to_string());
+        forwarder.identity.stamp(&mut entry);
+        forwarder.spool_batch(&mut vec![entry], &mut Vec::new(), &Notify::new()).await;
+    }
+
+    #[tokio::test]
+    async fn test_spooled_batches_are_delivered_and_removed() {
+        let db = Arc::new(MockDB::new());
+        let url = server(db.clone()).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        // A wrong token is retryable, the batch stays spooled.
+        let forwarder = Forwarder::new(&config(url.clone(), "wrong", dir.path()), identity()).unwrap();
+        spool_entry(&forwarder, "10.0.0.7").await;
+        assert!(forwarder.deliver_pending().await.is_err());
+        assert_eq!(forwarder.spool.pending().unwrap().len(), 1);
+
+        let forwarder = Forwarder::new(&config(url, "s3cret", dir.path()), identity()).unwrap();
+        assert_eq!(forwarder.deliver_pending().await, Ok(1));
+        assert!(forwarder.spool.pending().unwrap().is_empty());
+
+        let rows = db.fetch_logs(None).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].host, "web1");
+        assert_eq!(rows[0].agent_id, "agent-1");
+    }
+
+    #[tokio::test]
+    async fn test_invalid_batches_are_set_aside() {
+        let db = Arc::new(MockDB::new());
+        let url = server(db.clone()).await;
+        let dir = tempfile::tempdir().unwrap();
+
+        // A batch without an agent ID is refused as a whole.
+        let nameless = AgentIdentity { agent_id: String::new(), ..identity() };
+        let forwarder = Forwarder::new(&config(url.clone(), "s3cret", dir.path()), nameless).unwrap();
+        spool_entry(&forwarder, "10.0.0.7").await;
+
+        let forwarder = Forwarder::new(&config(url, "s3cret", dir.path()), identity()).unwrap();
+        spool_entry(&forwarder, "10.0.0.8").await;
+        assert_eq!(forwarder.deliver_pending().await, Ok(1));
+        assert!(forwarder.spool.pending().unwrap().is_empty());
+        assert_eq!(db.fetch_logs(None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoints_are_saved_once_their_batch_is_spooled() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoints = Arc::new(CheckpointStore::load(dir.path().join("checkpoints.json")));
+        // Nothing listens there, delivery keeps failing in the background.
+        let config = config("http://127.0.0.1:9".to_string(), "s3cret", &dir.path().join("spool"));
+        let forwarder = Arc::new(Forwarder::new(&config, identity()).unwrap());
+
+        let (sender, receiver) = mpsc::channel(8);
+        let entry = LogEntry::new(LogSource::AuthLog, Utc::now(), "Failed password for root");
+        sender.send(Ingested::Entry(entry)).await.unwrap();
+        sender.send(Ingested::Checkpoint(PendingCheckpoint::cursor(checkpoints.clone(), "stdin", "s=1;i=1"))).await.unwrap();
+        let running = tokio::spawn(forwarder.clone().run(receiver));
+
+        // The batch is not full, so neither it nor the checkpoint is written before the period ends.
+        sleep(BATCH_PERIOD / 4).await;
+        assert_eq!(checkpoints.cursor("stdin"), None);
+        assert!(forwarder.spool.pending().unwrap().is_empty());
+
+        drop(sender);
+        running.await.unwrap();
+        assert_eq!(forwarder.spool.pending().unwrap().len(), 1);
+        assert_eq!(checkpoints.cursor("stdin").as_deref(), Some("s=1;i=1"));
+    }
+}
@@ -0,0 +1,33 @@
+use std::sync::Arc;
+
+use tokio::sync::mpsc;
+use tracing::error;
+
+use crate::agent::forwarder::{AgentConfig, Forwarder};
+use crate::ingest::identity::AgentIdentity;
+use crate::server::server::{init_tracing, start_sources};
+
+pub mod forwarder;
+pub mod spool;
+
+/// Entries queued between the sources and the forwarder.
+const AGENT_QUEUE: usize = 10_000;
+
+/// Entry point of the `agent` subcommand: reads the configured sources like
+/// the server does, but pushes the entries to a central server instead of
+/// storing them.
+#[tokio::main]
+pub async fn run() {
+    init_tracing();
+
+    let forwarder = AgentConfig::from_env()
+        .and_then(|config| Forwarder::new(&config, AgentIdentity::from_env()))
+        .unwrap_or_else(|e| {
+            error!("Cannot start the agent: {}", e);
+            std::process::exit(1);
+        });
+
+    let (sender, receiver) = mpsc::channel(AGENT_QUEUE);
+    start_sources(sender).await;
+    Arc::new(forwarder).run(receiver).await;
+}
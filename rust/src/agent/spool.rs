@@ -0,0 +1,137 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use chrono::Utc;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use tracing::warn;
+
+use crate::models::batch::IngestBatch;
+
+const BATCH_EXTENSION: &str = "batch";
+const REJECTED_EXTENSION: &str = "rejected";
+
+/// Batches waiting to be delivered, one gzipped JSON file each.
+///
+/// A batch is written here before it is sent and removed once the server has
+/// stored it, so batches survive restarts and outages. Names start with the
+/// creation time, so sorting them gives the order to send them in.
+pub struct Spool {
+    dir: PathBuf,
+    /// Pending batches beyond this size are dropped, oldest first.
+    max_bytes: u64,
+    /// Orders batches written within the same millisecond.
+    sequence: AtomicU64,
+}
+
+impl Spool {
+    pub fn open(dir: impl Into<PathBuf>, max_bytes: u64) -> io::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, max_bytes, sequence: AtomicU64::new(0) })
+    }
+
+    /// Writes `batch` to disk, returning its path.
+    pub fn push(&self, batch: &IngestBatch) -> io::Result<PathBuf> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(batch)?)?;
+        let body = encoder.finish()?;
+
+        let sequence = self.sequence.fetch_add(1, Ordering::Relaxed);
+        let name = format!("{:013}-{:06}-{}", Utc::now().timestamp_millis(), sequence, batch.id);
+        let path = self.dir.join(&name).with_extension(BATCH_EXTENSION);
+        // Renamed into place so a crash never leaves half a batch to send.
+        let tmp = self.dir.join(name).with_extension("tmp");
+        fs::write(&tmp, body)?;
+        fs::rename(&tmp, &path)?;
+
+        self.enforce_limit()?;
+        Ok(path)
+    }
+
+    /// Pending batches, oldest first.
+    pub fn pending(&self) -> io::Result<Vec<PathBuf>> {
+        let mut paths: Vec<PathBuf> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.extension().is_some_and(|extension| extension == BATCH_EXTENSION))
+            .collect();
+        paths.sort();
+        Ok(paths)
+    }
+
+    /// The request body of a pending batch, still compressed.
+    pub fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    /// Drops a batch the server has stored.
+    pub fn remove(&self, path: &Path) -> io::Result<()> {
+        fs::remove_file(path)
+    }
+
+    /// Sets aside a batch the server will never accept, keeping it for inspection.
+    pub fn reject(&self, path: &Path) -> io::Result<()> {
+        fs::rename(path, path.with_extension(REJECTED_EXTENSION))
+    }
+
+    fn enforce_limit(&self) -> io::Result<()> {
+        let pending = self.pending()?;
+        let sizes = pending
+            .iter()
+            .map(|path| fs::metadata(path).map(|meta| meta.len()))
+            .collect::<io::Result<Vec<u64>>>()?;
+
+        let mut total: u64 = sizes.iter().sum();
+        // The newest batch is always kept.
+        for (path, size) in pending.iter().zip(sizes).take(pending.len().saturating_sub(1)) {
+            if total <= self.max_bytes {
+                break;
+            }
+            warn!("Spool is over {} bytes, dropping undelivered batch {}", self.max_bytes, path.display());
+            fs::remove_file(path)?;
+            total -= size;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    use crate::models::log::{LogEntry, LogSource};
+
+    fn batch(id: u128) -> IngestBatch {
+        let entry = LogEntry::new(LogSource::AuthLog, Utc::now(), "Failed password for root");
+        IngestBatch { id: Uuid::from_u128(id), agent_id: "agent-1".to_string(), entries: vec![entry] }
+    }
+
+    #[test]
+    fn test_batches_are_kept_in_order_until_removed() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Spool::open(dir.path(), u64::MAX).unwrap();
+        let first = spool.push(&batch(1)).unwrap();
+        let second = spool.push(&batch(2)).unwrap();
+
+        // Reopening finds what is still pending.
+        let spool = Spool::open(dir.path(), u64::MAX).unwrap();
+        assert_eq!(spool.pending().unwrap(), vec![first.clone(), second.clone()]);
+
+        spool.remove(&first).unwrap();
+        spool.reject(&second).unwrap();
+        assert!(spool.pending().unwrap().is_empty());
+        assert!(second.with_extension(REJECTED_EXTENSION).exists());
+    }
+
+    #[test]
+    fn test_oldest_batches_are_dropped_over_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let spool = Spool::open(dir.path(), 1).unwrap();
+        spool.push(&batch(1)).unwrap();
+        let newest = spool.push(&batch(2)).unwrap();
+        assert_eq!(spool.pending().unwrap(), vec![newest]);
+    }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::net::IpAddr;
+
+use db::schema::DbLogEntry;
+use ipnet::IpNet;
+
+/// The autonomous systems announcing address prefixes, used to fill in the
+/// `asn` of rows so failed logins can be correlated per network operator.
+#[derive(Debug, Clone, Default)]
+pub struct AsnTable {
+    /// ASNs by prefix, one map per prefix length, longest first.
+    prefixes: Vec<(u8, HashMap<IpNet, u32>)>,
+}
+
+impl AsnTable {
+    /// Parses lines of a prefix and the ASN announcing it, e.g.
+    /// `203.0.113.0/24 64500` or `2001:db8::/32 AS64501`. Blank lines and
+    /// lines starting with `#` are skipped.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut by_length: HashMap<u8, HashMap<IpNet, u32>> = HashMap::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = || format!("invalid ASN prefix on line {}: {}", number + 1, line);
+            let mut fields = line.split_whitespace();
+            let prefix: IpNet = fields.next().and_then(|prefix| prefix.parse().ok()).ok_or_else(invalid)?;
+            let asn = fields.next().ok_or_else(invalid)?;
+            let asn: u32 = asn.strip_prefix("AS").unwrap_or(asn).parse().map_err(|_| invalid())?;
+            if fields.next().is_some() {
+                return Err(invalid());
+            }
+            by_length.entry(prefix.prefix_len()).or_default().insert(prefix.trunc(), asn);
+        }
+
+        let mut prefixes: Vec<_> = by_length.into_iter().collect();
+        prefixes.sort_by(|(a, _), (b, _)| b.cmp(a));
+        Ok(Self { prefixes })
+    }
+
+    /// The table in the file named by `ASN_PREFIXES`, empty if it is unset.
+    pub fn from_env() -> Result<Self, String> {
+        match env::var("ASN_PREFIXES") {
+            Ok(path) => {
+                let text = fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+                Self::parse(&text).map_err(|e| format!("{}: {}", path, e))
+            }
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// The ASN of the most specific prefix containing `ip`.
+    pub fn lookup(&self, ip: IpAddr) -> Option<u32> {
+        self.prefixes.iter().find_map(|(length, asns)| {
+            let network = IpNet::new(ip, *length).ok()?.trunc();
+            asns.get(&network).copied()
+        })
+    }
+
+    /// Sets the `asn` of `row` from its source address, unless it has one.
+    pub fn apply(&self, row: &mut DbLogEntry) {
+        if row.asn == 0 {
+            row.asn = self.lookup(row.source_ip).unwrap_or(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_most_specific_prefix_wins() {
+        let table = AsnTable::parse(
+            "# prefix asn\n\
+             203.0.113.0/24 64500\n\
+             203.0.113.128/25 AS64501\n\
+             \n\
+             2001:db8::/32 64502\n",
+        )
+        .unwrap();
+
+        assert_eq!(table.lookup("203.0.113.7".parse().unwrap()), Some(64500));
+        assert_eq!(table.lookup("203.0.113.200".parse().unwrap()), Some(64501));
+        assert_eq!(table.lookup("2001:db8:1::9".parse().unwrap()), Some(64502));
+        assert_eq!(table.lookup("198.51.100.1".parse().unwrap()), None);
+    }
+
+    #[test]
+    fn test_invalid_lines_are_rejected() {
+        for invalid in ["203.0.113.0/24", "203.0.113.0/33 64500", "203.0.113.0/24 ASx", "203.0.113.0/24 64500 extra"] {
+            assert!(AsnTable::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashMap;
+use std::env;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use db::schema::{ActionTaken, DbLogEntry, EventType, ThreatLevel};
+use uuid::Uuid;
+
+use crate::detect::clock::Clock;
+use crate::detect::Detection;
+use crate::models::failed_login::{FailedLogins, Threshold};
+use crate::models::log::{LogEntry, LogSource};
+
+const THRESHOLDS: &str = "5/10s,10/1m";
+/// Addresses whose attempts are remembered at once.
+const MAX_TRACKED: usize = 100_000;
+/// How long an address that was reported is not reported again.
+const COOLDOWN: Duration = Duration::minutes(5);
+
+/// Reports addresses with too many failed SSH logins within any of the configured windows.
+pub struct BruteForce {
+    failed: FailedLogins,
+    /// When each address was last reported.
+    reported: HashMap<IpAddr, DateTime<Utc>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl BruteForce {
+    pub fn new(thresholds: Vec<Threshold>, max_tracked: usize, clock: Arc<dyn Clock>) -> Self {
+        Self {
+            failed: FailedLogins::new(thresholds, max_tracked, clock.clone()),
+            reported: HashMap::new(),
+            clock,
+        }
+    }
+
+    /// Reads `BRUTE_FORCE_THRESHOLDS`, the failed logins that trigger a report
+    /// such as `5/10s,20/5m,100/1h` (default `5/10s,10/1m`), and
+    /// `BRUTE_FORCE_MAX_TRACKED`, the addresses remembered (default 100000).
+    pub fn from_env(clock: Arc<dyn Clock>) -> Self {
+        let thresholds = env::var("BRUTE_FORCE_THRESHOLDS")
+            .ok()
+            .and_then(|value| {
+                Threshold::parse_list(&value)
+                    .map_err(|e| tracing::warn!("Ignoring invalid BRUTE_FORCE_THRESHOLDS: {}", e))
+                    .ok()
+            })
+            .unwrap_or_else(|| Threshold::parse_list(THRESHOLDS).expect("default thresholds are valid"));
+        let max_tracked = match env::var("BRUTE_FORCE_MAX_TRACKED") {
+            Ok(value) => value.parse().unwrap_or_else(|_| {
+                tracing::warn!("Ignoring invalid BRUTE_FORCE_MAX_TRACKED: {}", value);
+                MAX_TRACKED
+            }),
+            Err(_) => MAX_TRACKED,
+        };
+        Self::new(thresholds, max_tracked, clock)
+    }
+}
+
+impl Detection for BruteForce {
+    fn observe(&mut self, entry: &LogEntry, row: &DbLogEntry) -> Vec<DbLogEntry> {
+        if entry.source != LogSource::AuthLog || entry.success != Some(false) {
+            return Vec::new();
+        }
+        let Some(ip) = entry.ip_address.as_deref().and_then(|ip| ip.parse::<IpAddr>().ok()) else {
+            return Vec::new();
+        };
+        self.clock.observe(entry.timestamp);
+        let Some(threshold) = self.failed.register_attempt(ip) else {
+            return Vec::new();
+        };
+
+        let now = self.clock.now();
+        if self.reported.get(&ip).is_some_and(|reported| now - *reported < COOLDOWN) {
+            return Vec::new();
+        }
+        self.reported.retain(|_, reported| now - *reported < COOLDOWN);
+        self.reported.insert(ip, now);
+
+        vec![DbLogEntry {
+            id: Uuid::new_v4(),
+            event_type: EventType::SshBruteForce,
+            targeted_service: "SSH".to_string(),
+            request: format!("Failed SSH logins from {}: {}", ip, threshold),
+            status: 0,
+            action_taken: ActionTaken::Alerted,
+            threat_level: ThreatLevel::High,
+            ..row.clone()
+        }]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::clock::{EventClock, SystemClock};
+    use crate::detect::fixtures::{failed_login, observe};
+
+    fn thresholds(list: &str) -> Vec<Threshold> {
+        Threshold::parse_list(list).unwrap()
+    }
+
+    #[test]
+    fn test_burst_is_reported_once() {
+        let mut detection = BruteForce::new(thresholds("3/10s"), 100, Arc::new(SystemClock));
+        let entry = failed_login("203.0.113.9", "root");
+
+        assert!(observe(&mut detection, &entry).is_empty());
+        assert!(observe(&mut detection, &entry).is_empty());
+        let detections = observe(&mut detection, &entry);
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].event_type, EventType::SshBruteForce);
+        assert_eq!(detections[0].threat_level, ThreatLevel::High);
+        assert_eq!(detections[0].action_taken, ActionTaken::Alerted);
+        assert_eq!(detections[0].source_ip.to_string(), "203.0.113.9");
+        assert_eq!(detections[0].targeted_endpoint, "user root");
+        assert_eq!(detections[0].request, "Failed SSH logins from 203.0.113.9: 3 in 10s");
+
+        // Further attempts in the cooldown are not reported again.
+        assert!(observe(&mut detection, &entry).is_empty());
+    }
+
+    #[test]
+    fn test_other_entries_are_not_counted() {
+        let mut detection = BruteForce::new(thresholds("1/10s"), 100, Arc::new(SystemClock));
+        let mut accepted = failed_login("203.0.113.9", "root");
+        accepted.success = Some(true);
+        assert!(observe(&mut detection, &accepted).is_empty());
+
+        let mut http = failed_login("203.0.113.9", "root");
+        http.source = LogSource::NginxAccess;
+        assert!(observe(&mut detection, &http).is_empty());
+
+        let mut no_ip = failed_login("203.0.113.9", "root");
+        no_ip.ip_address = None;
+        assert!(observe(&mut detection, &no_ip).is_empty());
+    }
+
+    #[test]
+    fn test_replayed_logs_are_judged_by_their_timestamps() {
+        let mut detection = BruteForce::new(thresholds("3/10s"), 100, Arc::new(EventClock::new()));
+        let mut entry = failed_login("203.0.113.9", "root");
+        entry.timestamp = Utc::now() - Duration::days(30);
+
+        // Three attempts a minute apart, read back to back, are not a burst.
+        for _ in 0..3 {
+            assert!(observe(&mut detection, &entry).is_empty());
+            entry.timestamp += Duration::minutes(1);
+        }
+        assert!(observe(&mut detection, &entry).is_empty());
+        assert!(observe(&mut detection, &entry).is_empty());
+        assert_eq!(observe(&mut detection, &entry).len(), 1);
+    }
+}
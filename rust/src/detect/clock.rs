@@ -0,0 +1,101 @@
+use std::env;
+use std::sync::{Arc, Mutex};
+
+use chrono::{DateTime, Utc};
+
+/// Where time-windowed detections take the current time from.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+
+    /// Called with each entry's timestamp before it is counted. Only clocks
+    /// that follow the logs rather than the wall clock use it.
+    fn observe(&self, _timestamp: DateTime<Utc>) {}
+}
+
+/// The wall clock: windows cover when entries are processed.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+/// Event time: the latest timestamp seen in the logs, so replaying old logs
+/// detects what happened when they were written. It never goes back, so an
+/// entry that arrives out of order is counted at the latest time instead.
+pub struct EventClock {
+    latest: Mutex<DateTime<Utc>>,
+}
+
+impl EventClock {
+    pub fn new() -> Self {
+        Self { latest: Mutex::new(DateTime::<Utc>::MIN_UTC) }
+    }
+}
+
+impl Clock for EventClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.latest.lock().unwrap()
+    }
+
+    fn observe(&self, timestamp: DateTime<Utc>) {
+        let mut latest = self.latest.lock().unwrap();
+        *latest = (*latest).max(timestamp);
+    }
+}
+
+/// The clock selected by `DETECTION_TIME`: `processing` (the default) or `event`.
+pub fn from_env() -> Arc<dyn Clock> {
+    match env::var("DETECTION_TIME").as_deref() {
+        Ok("event") => Arc::new(EventClock::new()),
+        Ok("processing") | Err(_) => Arc::new(SystemClock),
+        Ok(other) => {
+            tracing::warn!("Ignoring invalid DETECTION_TIME: {}", other);
+            Arc::new(SystemClock)
+        }
+    }
+}
+
+/// A clock that only moves when told to.
+#[cfg(test)]
+pub struct ManualClock {
+    now: Mutex<DateTime<Utc>>,
+}
+
+#[cfg(test)]
+impl ManualClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self { now: Mutex::new(now) }
+    }
+
+    pub fn advance(&self, by: chrono::Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+#[cfg(test)]
+impl Clock for ManualClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.now.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    #[test]
+    fn test_event_clock_never_goes_back() {
+        let clock = EventClock::new();
+        let start = Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+
+        clock.observe(start);
+        assert_eq!(clock.now(), start);
+        clock.observe(start - Duration::seconds(30));
+        assert_eq!(clock.now(), start);
+        clock.observe(start + Duration::seconds(5));
+        assert_eq!(clock.now(), start + Duration::seconds(5));
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::env;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use db::schema::{ActionTaken, DbLogEntry, EventType, ThreatLevel};
+use uuid::Uuid;
+
+use crate::detect::clock::Clock;
+use crate::detect::lru::LruMap;
+use crate::detect::Detection;
+use crate::models::failed_login::Threshold;
+use crate::models::log::{LogEntry, LogSource};
+
+const THRESHOLD: &str = "5/10m";
+/// Addresses and users whose failures are remembered at once, each.
+const MAX_TRACKED: usize = 100_000;
+/// Failures remembered per address or user, the oldest are dropped first.
+const MAX_FAILURES: usize = 100;
+
+#[derive(Debug, Clone)]
+struct Failure {
+    /// Order the failures were seen in, to merge the two chains.
+    seq: u64,
+    /// Clock time, for the window.
+    at: DateTime<Utc>,
+    /// Logged time, for the chain.
+    timestamp: DateTime<Utc>,
+    ip: IpAddr,
+    user: Option<String>,
+}
+
+/// Reports an accepted SSH login from an address, or for a user, that failed
+/// to log in at least `threshold` times just before: a likely compromise.
+pub struct SuccessAfterFailures {
+    threshold: Threshold,
+    by_ip: LruMap<IpAddr, VecDeque<Failure>>,
+    by_user: LruMap<String, VecDeque<Failure>>,
+    seq: u64,
+    clock: Arc<dyn Clock>,
+}
+
+impl SuccessAfterFailures {
+    pub fn new(threshold: Threshold, max_tracked: usize, clock: Arc<dyn Clock>) -> Self {
+        Self { threshold, by_ip: LruMap::new(max_tracked), by_user: LruMap::new(max_tracked), seq: 0, clock }
+    }
+
+    /// Reads `COMPROMISE_THRESHOLD`, the failures before an accepted login
+    /// that trigger a report (default `5/10m`). `off` disables the rule.
+    pub fn from_env(clock: Arc<dyn Clock>) -> Option<Self> {
+        let value = env::var("COMPROMISE_THRESHOLD").unwrap_or_else(|_| THRESHOLD.to_string());
+        if value == "off" {
+            return None;
+        }
+        let threshold = value.parse().unwrap_or_else(|e| {
+            tracing::warn!("Ignoring invalid COMPROMISE_THRESHOLD: {}", e);
+            THRESHOLD.parse().expect("default threshold is valid")
+        });
+        Some(Self::new(threshold, MAX_TRACKED, clock))
+    }
+
+    fn fail(&mut self, failure: Failure) {
+        let window = self.threshold.window;
+        let now = failure.at;
+        let remember = |failures: &mut VecDeque<Failure>| {
+            failures.push_back(failure.clone());
+            while failures.len() > MAX_FAILURES || failures.front().is_some_and(|failure| now - failure.at >= window) {
+                failures.pop_front();
+            }
+        };
+        remember(self.by_ip.touch(failure.ip));
+        if let Some(user) = &failure.user {
+            remember(self.by_user.touch(user.clone()));
+        }
+    }
+
+    /// The failures within the window before a login from `ip` as `user`, in
+    /// order, if either reaches the threshold. The login settles them either
+    /// way, so each chain is reported at most once.
+    fn chain(&mut self, now: DateTime<Utc>, ip: IpAddr, user: Option<&str>) -> Option<Vec<Failure>> {
+        let window = self.threshold.window;
+        let recent = |failures: Option<&mut VecDeque<Failure>>| -> Vec<Failure> {
+            failures.map_or_else(Vec::new, |failures| {
+                failures.retain(|failure| now - failure.at < window);
+                failures.drain(..).collect()
+            })
+        };
+        let by_ip = recent(self.by_ip.get_mut(&ip));
+        let by_user = recent(user.and_then(|user| self.by_user.get_mut(&user.to_string())));
+        if by_ip.len().max(by_user.len()) < self.threshold.attempts {
+            return None;
+        }
+
+        let chain: BTreeMap<u64, Failure> = by_ip.into_iter().chain(by_user).map(|failure| (failure.seq, failure)).collect();
+        Some(chain.into_values().collect())
+    }
+}
+
+impl Detection for SuccessAfterFailures {
+    fn observe(&mut self, entry: &LogEntry, row: &DbLogEntry) -> Vec<DbLogEntry> {
+        if entry.source != LogSource::AuthLog {
+            return Vec::new();
+        }
+        let Some(ip) = entry.ip_address.as_deref().and_then(|ip| ip.parse::<IpAddr>().ok()) else {
+            return Vec::new();
+        };
+        self.clock.observe(entry.timestamp);
+        let now = self.clock.now();
+        let user = entry.user.as_deref().filter(|user| !user.is_empty());
+
+        match entry.success {
+            Some(false) => {
+                self.seq += 1;
+                let failure = Failure { seq: self.seq, at: now, timestamp: entry.timestamp, ip, user: user.map(str::to_string) };
+                self.fail(failure);
+                Vec::new()
+            }
+            Some(true) => {
+                let Some(chain) = self.chain(now, ip, user) else {
+                    return Vec::new();
+                };
+                let events: Vec<String> = chain
+                    .iter()
+                    .map(|failure| event(failure.timestamp, failure.ip, failure.user.as_deref(), "failed"))
+                    .chain([event(entry.timestamp, ip, user, "accepted")])
+                    .collect();
+                vec![DbLogEntry {
+                    id: Uuid::new_v4(),
+                    event_type: EventType::LoginAfterBruteForce,
+                    targeted_service: "SSH".to_string(),
+                    request: format!(
+                        "Accepted SSH login as {} from {} after {} failed logins in {}. Chain: {}",
+                        user.unwrap_or("-"),
+                        ip,
+                        chain.len(),
+                        self.threshold.window_label(),
+                        events.join("; ")
+                    ),
+                    status: 0,
+                    action_taken: ActionTaken::Alerted,
+                    threat_level: ThreatLevel::Critical,
+                    ..row.clone()
+                }]
+            }
+            None => Vec::new(),
+        }
+    }
+}
+
+/// One link of a chain, e.g. `2024-03-01T12:00:01Z 198.51.100.1 root failed`.
+fn event(timestamp: DateTime<Utc>, ip: IpAddr, user: Option<&str>, outcome: &str) -> String {
+    format!("{} {} {} {}", timestamp.to_rfc3339_opts(SecondsFormat::Secs, true), ip, user.unwrap_or("-"), outcome)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    use crate::detect::clock::EventClock;
+    use crate::detect::fixtures::{login, observe};
+
+    fn detection(threshold: &str) -> SuccessAfterFailures {
+        SuccessAfterFailures::new(threshold.parse().unwrap(), 100, Arc::new(EventClock::new()))
+    }
+
+    /// `second` seconds into the test's timeline.
+    fn at(second: i64) -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap() + Duration::seconds(second)
+    }
+
+    #[test]
+    fn test_login_after_failures_from_the_same_address() {
+        let mut detection = detection("3/10m");
+        for (second, user) in [(0, "root"), (1, "admin"), (2, "root")] {
+            assert!(observe(&mut detection, &login(at(second), "203.0.113.9", user, false)).is_empty());
+        }
+
+        let detections = observe(&mut detection, &login(at(5), "203.0.113.9", "deploy", true));
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].event_type, EventType::LoginAfterBruteForce);
+        assert_eq!(detections[0].threat_level, ThreatLevel::Critical);
+        assert_eq!(detections[0].action_taken, ActionTaken::Alerted);
+        assert_eq!(
+            detections[0].request,
+            "Accepted SSH login as deploy from 203.0.113.9 after 3 failed logins in 10m. Chain: \
+             2024-03-01T12:00:00Z 203.0.113.9 root failed; \
+             2024-03-01T12:00:01Z 203.0.113.9 admin failed; \
+             2024-03-01T12:00:02Z 203.0.113.9 root failed; \
+             2024-03-01T12:00:05Z 203.0.113.9 deploy accepted"
+        );
+
+        // The chain is reported once.
+        assert!(observe(&mut detection, &login(at(6), "203.0.113.9", "deploy", true)).is_empty());
+    }
+
+    #[test]
+    fn test_login_after_failures_for_the_same_user() {
+        let mut detection = detection("3/10m");
+        for (second, ip) in [(0, "198.51.100.1"), (1, "198.51.100.2"), (2, "198.51.100.3")] {
+            assert!(observe(&mut detection, &login(at(second), ip, "root", false)).is_empty());
+        }
+
+        let detections = observe(&mut detection, &login(at(3), "192.0.2.1", "root", true));
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].source_ip.to_string(), "192.0.2.1");
+        assert!(detections[0].request.contains("198.51.100.2 root failed"));
+    }
+
+    #[test]
+    fn test_old_or_few_failures_are_ignored() {
+        let mut detection = detection("3/10m");
+        observe(&mut detection, &login(at(0), "203.0.113.9", "root", false));
+        observe(&mut detection, &login(at(1), "203.0.113.9", "root", false));
+        assert!(observe(&mut detection, &login(at(2), "203.0.113.9", "root", true)).is_empty());
+
+        let mut detection = self::detection("3/10m");
+        for second in 0..3 {
+            observe(&mut detection, &login(at(second), "203.0.113.9", "root", false));
+        }
+        assert!(observe(&mut detection, &login(at(601), "203.0.113.9", "root", true)).is_empty());
+    }
+}
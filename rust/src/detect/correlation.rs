@@ -0,0 +1,308 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::env;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Duration, Utc};
+use db::schema::{ActionTaken, DbLogEntry, EventType, ThreatLevel};
+use ipnet::IpNet;
+use uuid::Uuid;
+
+use crate::detect::clock::Clock;
+use crate::detect::lru::LruMap;
+use crate::detect::Detection;
+use crate::models::failed_login::Threshold;
+use crate::models::log::{LogEntry, LogSource};
+
+/// How long a group that was reported is not reported again.
+const COOLDOWN: Duration = Duration::minutes(15);
+/// Groups whose attempts are remembered at once.
+const MAX_TRACKED: usize = 10_000;
+/// Attempts remembered per group, the oldest are dropped first.
+const MAX_ATTEMPTS: usize = 1000;
+/// Contributors listed in an alert, the rest are only counted.
+const MAX_LISTED: usize = 20;
+
+/// What failed logins are grouped by, and what is counted in each group.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Correlate {
+    /// One user, many addresses: a password spray or credential stuffing.
+    Spray,
+    /// One address, many users: guessing which accounts exist.
+    Enumeration,
+    /// One /24 (/64 for IPv6) or autonomous system, many addresses. Systems
+    /// are known for the prefixes listed in `ASN_PREFIXES`.
+    Network,
+}
+
+impl Correlate {
+    /// The groups an attempt counts towards.
+    fn groups(self, attempt: &Attempt, row: &DbLogEntry) -> Vec<String> {
+        match self {
+            Self::Spray => attempt.user.iter().cloned().collect(),
+            Self::Enumeration if attempt.user.is_some() => vec![attempt.ip.to_string()],
+            Self::Enumeration => Vec::new(),
+            Self::Network => {
+                let prefix = if attempt.ip.is_ipv4() { 24 } else { 64 };
+                let network = IpNet::new(attempt.ip, prefix).expect("prefix is valid").trunc();
+                let mut groups = vec![network.to_string()];
+                if row.asn != 0 {
+                    groups.push(format!("AS{}", row.asn));
+                }
+                groups
+            }
+        }
+    }
+
+    /// The value counted in a group, distinct values reach the threshold.
+    fn counted(self, attempt: &Attempt) -> String {
+        match self {
+            Self::Spray | Self::Network => attempt.ip.to_string(),
+            Self::Enumeration => attempt.user.clone().unwrap_or_default(),
+        }
+    }
+
+    fn describe(self, group: &str, distinct: usize, window: &str) -> String {
+        match self {
+            Self::Spray => format!("Failed SSH logins as {} from {} addresses in {}", group, distinct, window),
+            Self::Enumeration => format!("Failed SSH logins from {} for {} users in {}", group, distinct, window),
+            Self::Network => format!("Failed SSH logins from {} addresses in {} in {}", distinct, group, window),
+        }
+    }
+
+    fn event_type(self) -> EventType {
+        match self {
+            Self::Spray => EventType::PasswordSpray,
+            Self::Enumeration => EventType::UserEnumeration,
+            Self::Network => EventType::DistributedBruteForce,
+        }
+    }
+
+    fn threat_level(self) -> ThreatLevel {
+        match self {
+            Self::Spray | Self::Network => ThreatLevel::High,
+            Self::Enumeration => ThreatLevel::Medium,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Attempt {
+    at: DateTime<Utc>,
+    ip: IpAddr,
+    user: Option<String>,
+}
+
+/// Reports failed SSH logins correlated across addresses or users, listing
+/// every address and user that took part.
+pub struct Correlation {
+    correlate: Correlate,
+    /// Distinct counted values within a window that trigger a report.
+    threshold: Threshold,
+    groups: LruMap<String, VecDeque<Attempt>>,
+    /// When each group was last reported.
+    reported: HashMap<String, DateTime<Utc>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Correlation {
+    pub fn new(correlate: Correlate, threshold: Threshold, max_tracked: usize, clock: Arc<dyn Clock>) -> Self {
+        Self { correlate, threshold, groups: LruMap::new(max_tracked), reported: HashMap::new(), clock }
+    }
+
+    /// The correlations configured by `SPRAY_THRESHOLD` (distinct addresses per
+    /// user, default `10/10m`), `ENUMERATION_THRESHOLD` (distinct users per
+    /// address, default `10/10m`) and `NETWORK_THRESHOLD` (distinct addresses
+    /// per network, default `20/10m`). A threshold of `off` disables one.
+    pub fn from_env(clock: Arc<dyn Clock>) -> Vec<Self> {
+        [
+            (Correlate::Spray, "SPRAY_THRESHOLD", "10/10m"),
+            (Correlate::Enumeration, "ENUMERATION_THRESHOLD", "10/10m"),
+            (Correlate::Network, "NETWORK_THRESHOLD", "20/10m"),
+        ]
+        .into_iter()
+        .filter_map(|(correlate, name, default)| {
+            let value = env::var(name).unwrap_or_else(|_| default.to_string());
+            if value == "off" {
+                return None;
+            }
+            let threshold = value.parse().unwrap_or_else(|e| {
+                tracing::warn!("Ignoring invalid {}: {}", name, e);
+                default.parse().expect("default threshold is valid")
+            });
+            Some(Self::new(correlate, threshold, MAX_TRACKED, clock.clone()))
+        })
+        .collect()
+    }
+
+    fn report(&self, group: &str, attempts: &VecDeque<Attempt>, distinct: usize, row: &DbLogEntry) -> DbLogEntry {
+        let ips: BTreeSet<String> = attempts.iter().map(|attempt| attempt.ip.to_string()).collect();
+        let users: BTreeSet<&str> = attempts.iter().filter_map(|attempt| attempt.user.as_deref()).collect();
+
+        DbLogEntry {
+            id: Uuid::new_v4(),
+            event_type: self.correlate.event_type(),
+            targeted_service: "SSH".to_string(),
+            targeted_endpoint: match self.correlate {
+                Correlate::Spray => format!("user {}", group),
+                Correlate::Enumeration => format!("{} users", users.len()),
+                Correlate::Network => group.to_string(),
+            },
+            request: format!(
+                "{}. Addresses: {}. Users: {}",
+                self.correlate.describe(group, distinct, &self.threshold.window_label()),
+                listed(ips.iter().map(String::as_str)),
+                listed(users.iter().copied())
+            ),
+            status: 0,
+            action_taken: ActionTaken::Alerted,
+            threat_level: self.correlate.threat_level(),
+            ..row.clone()
+        }
+    }
+}
+
+impl Detection for Correlation {
+    fn observe(&mut self, entry: &LogEntry, row: &DbLogEntry) -> Vec<DbLogEntry> {
+        if entry.source != LogSource::AuthLog || entry.success != Some(false) {
+            return Vec::new();
+        }
+        let Some(ip) = entry.ip_address.as_deref().and_then(|ip| ip.parse::<IpAddr>().ok()) else {
+            return Vec::new();
+        };
+        self.clock.observe(entry.timestamp);
+        let now = self.clock.now();
+        let attempt = Attempt { at: now, ip, user: entry.user.clone().filter(|user| !user.is_empty()) };
+
+        let mut detections = Vec::new();
+        for group in self.correlate.groups(&attempt, row) {
+            let attempts = self.groups.touch(group.clone());
+            attempts.push_back(attempt.clone());
+            while attempts.len() > MAX_ATTEMPTS
+                || attempts.front().is_some_and(|attempt| now - attempt.at >= self.threshold.window)
+            {
+                attempts.pop_front();
+            }
+
+            let attempts = self.groups.get(&group).expect("group was just tracked");
+            let distinct: BTreeSet<String> = attempts.iter().map(|attempt| self.correlate.counted(attempt)).collect();
+            if distinct.len() < self.threshold.attempts
+                || self.reported.get(&group).is_some_and(|reported| now - *reported < COOLDOWN)
+            {
+                continue;
+            }
+            detections.push(self.report(&group, attempts, distinct.len(), row));
+            self.reported.retain(|_, reported| now - *reported < COOLDOWN);
+            self.reported.insert(group, now);
+        }
+        detections
+    }
+}
+
+/// `values` separated by commas, with those past `MAX_LISTED` only counted.
+fn listed<'a>(values: impl ExactSizeIterator<Item = &'a str>) -> String {
+    let total = values.len();
+    let mut list = values.take(MAX_LISTED).collect::<Vec<_>>().join(", ");
+    if total > MAX_LISTED {
+        list.push_str(&format!(" and {} more", total - MAX_LISTED));
+    }
+    if list.is_empty() {
+        list.push('-');
+    }
+    list
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::detect::asn::AsnTable;
+    use crate::detect::clock::ManualClock;
+    use crate::detect::fixtures::{failed_login, observe};
+    use crate::detect::signatures::Signatures;
+    use crate::detect::Detector;
+    use crate::ingest::classify::classify;
+
+    fn correlation(correlate: Correlate, threshold: &str) -> (Correlation, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        (Correlation::new(correlate, threshold.parse().unwrap(), 100, clock.clone()), clock)
+    }
+
+    #[test]
+    fn test_spray_across_addresses() {
+        let (mut detection, _) = correlation(Correlate::Spray, "3/10m");
+
+        assert!(observe(&mut detection, &failed_login("198.51.100.1", "admin")).is_empty());
+        // Repeats from one address do not count twice.
+        assert!(observe(&mut detection, &failed_login("198.51.100.1", "admin")).is_empty());
+        assert!(observe(&mut detection, &failed_login("203.0.113.5", "root")).is_empty());
+        assert!(observe(&mut detection, &failed_login("203.0.113.5", "admin")).is_empty());
+
+        let detections = observe(&mut detection, &failed_login("192.0.2.77", "admin"));
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].event_type, EventType::PasswordSpray);
+        assert_eq!(detections[0].threat_level, ThreatLevel::High);
+        assert_eq!(detections[0].targeted_endpoint, "user admin");
+        assert_eq!(
+            detections[0].request,
+            "Failed SSH logins as admin from 3 addresses in 10m. \
+             Addresses: 192.0.2.77, 198.51.100.1, 203.0.113.5. Users: admin"
+        );
+
+        // Reported once per cooldown.
+        assert!(observe(&mut detection, &failed_login("192.0.2.78", "admin")).is_empty());
+    }
+
+    #[test]
+    fn test_enumeration_of_users() {
+        let (mut detection, clock) = correlation(Correlate::Enumeration, "3/1m");
+
+        assert!(observe(&mut detection, &failed_login("198.51.100.1", "alice")).is_empty());
+        assert!(observe(&mut detection, &failed_login("198.51.100.1", "bob")).is_empty());
+        // Users tried outside the window are forgotten.
+        clock.advance(Duration::minutes(2));
+        assert!(observe(&mut detection, &failed_login("198.51.100.1", "carol")).is_empty());
+        assert!(observe(&mut detection, &failed_login("198.51.100.1", "dave")).is_empty());
+
+        let detections = observe(&mut detection, &failed_login("198.51.100.1", "erin"));
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].event_type, EventType::UserEnumeration);
+        assert_eq!(detections[0].source_ip.to_string(), "198.51.100.1");
+        assert_eq!(detections[0].targeted_endpoint, "3 users");
+        assert!(detections[0].request.ends_with("Addresses: 198.51.100.1. Users: carol, dave, erin"));
+    }
+
+    #[test]
+    fn test_network_aggregates() {
+        let (mut detection, _) = correlation(Correlate::Network, "3/10m");
+
+        assert!(observe(&mut detection, &failed_login("203.0.113.1", "root")).is_empty());
+        assert!(observe(&mut detection, &failed_login("203.0.114.2", "root")).is_empty());
+        assert!(observe(&mut detection, &failed_login("203.0.113.2", "admin")).is_empty());
+        let detections = observe(&mut detection, &failed_login("203.0.113.3", "oracle"));
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].event_type, EventType::DistributedBruteForce);
+        assert_eq!(detections[0].targeted_endpoint, "203.0.113.0/24");
+        assert!(detections[0].request.ends_with("Users: admin, oracle, root"));
+
+        // Addresses in different networks of one autonomous system, looked up
+        // by the detector as it observes them.
+        let (detection, _) = correlation(Correlate::Network, "3/10m");
+        let asns = AsnTable::parse("198.51.100.0/22 AS64500").unwrap();
+        let detector = Detector::new(Signatures::default(), vec![Box::new(detection)]).with_asns(asns);
+        let mut detections = Vec::new();
+        for ip in ["198.51.100.1", "198.51.101.1", "198.51.102.1"] {
+            let entry = failed_login(ip, "root");
+            detections = detector.observe(&entry, &mut classify(&entry));
+        }
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].targeted_endpoint, "AS64500");
+    }
+
+    #[test]
+    fn test_long_lists_are_truncated() {
+        let values: Vec<String> = (0..25).map(|i| i.to_string()).collect();
+        let list = listed(values.iter().map(String::as_str));
+        assert!(list.ends_with(", 19 and 5 more"));
+        assert_eq!(listed(std::iter::empty()), "-");
+    }
+}
@@ -0,0 +1,72 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+/// A map holding at most `capacity` keys, forgetting the least recently used
+/// first. Detections use it to bound the state an attacker can make them keep.
+pub struct LruMap<K, V> {
+    capacity: usize,
+    entries: HashMap<K, (V, u64)>,
+    /// Keys by when they were last used, oldest first.
+    recency: BTreeMap<u64, K>,
+    uses: u64,
+}
+
+impl<K: Hash + Eq + Clone, V: Default> LruMap<K, V> {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity: capacity.max(1), entries: HashMap::new(), recency: BTreeMap::new(), uses: 0 }
+    }
+
+    /// The value of `key`, inserting an empty one if needed, marked as the most
+    /// recently used.
+    pub fn touch(&mut self, key: K) -> &mut V {
+        self.uses += 1;
+        let last_use = self.uses;
+        if let Some((_, used)) = self.entries.get_mut(&key) {
+            self.recency.remove(used);
+            *used = last_use;
+        } else {
+            if self.entries.len() >= self.capacity {
+                if let Some((_, oldest)) = self.recency.pop_first() {
+                    self.entries.remove(&oldest);
+                }
+            }
+            self.entries.insert(key.clone(), (V::default(), last_use));
+        }
+        self.recency.insert(last_use, key.clone());
+        &mut self.entries.get_mut(&key).expect("key was just touched").0
+    }
+
+    /// The value of `key` without marking it as used.
+    pub fn get(&self, key: &K) -> Option<&V> {
+        self.entries.get(key).map(|(value, _)| value)
+    }
+
+    /// Like `get`, but mutable.
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        self.entries.get_mut(key).map(|(value, _)| value)
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_least_recently_used_keys_are_forgotten() {
+        let mut map: LruMap<&str, usize> = LruMap::new(2);
+        *map.touch("a") += 1;
+        *map.touch("b") += 1;
+        *map.touch("a") += 1;
+        *map.touch("c") += 1;
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&"a"), Some(&2));
+        assert_eq!(map.get(&"b"), None);
+        assert_eq!(map.get(&"c"), Some(&1));
+    }
+}
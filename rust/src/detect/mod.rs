@@ -0,0 +1,105 @@
+use std::sync::Mutex;
+
+use db::schema::DbLogEntry;
+
+use crate::models::log::LogEntry;
+
+pub mod asn;
+pub mod brute_force;
+pub mod clock;
+pub mod compromise;
+pub mod correlation;
+pub mod lru;
+pub mod signatures;
+
+use asn::AsnTable;
+use signatures::Signatures;
+
+/// Recognises attacks that span several entries, such as repeated failed logins.
+pub trait Detection: Send {
+    /// Rows to store for what `entry` reveals, usually none. `row` is the
+    /// entry's own classified row.
+    fn observe(&mut self, entry: &LogEntry, row: &DbLogEntry) -> Vec<DbLogEntry>;
+}
+
+/// Every detection, shared by the ingestion pipeline and the push endpoint so
+/// entries count towards the same thresholds whichever way they arrive.
+pub struct Detector {
+    signatures: Signatures,
+    asns: AsnTable,
+    detections: Mutex<Vec<Box<dyn Detection>>>,
+}
+
+impl Detector {
+    pub fn new(signatures: Signatures, detections: Vec<Box<dyn Detection>>) -> Self {
+        Self { signatures, asns: AsnTable::default(), detections: Mutex::new(detections) }
+    }
+
+    /// Looks up the `asn` of rows in `asns`.
+    pub fn with_asns(mut self, asns: AsnTable) -> Self {
+        self.asns = asns;
+        self
+    }
+
+    /// The detections configured through the environment, all on the clock
+    /// chosen by `DETECTION_TIME`. Fails if the signatures or ASN prefixes
+    /// file is invalid.
+    pub fn from_env() -> Result<Self, String> {
+        let clock = clock::from_env();
+        let mut detections: Vec<Box<dyn Detection>> = vec![Box::new(brute_force::BruteForce::from_env(clock.clone()))];
+        for correlation in correlation::Correlation::from_env(clock.clone()) {
+            detections.push(Box::new(correlation));
+        }
+        if let Some(compromise) = compromise::SuccessAfterFailures::from_env(clock) {
+            detections.push(Box::new(compromise));
+        }
+        Ok(Self::new(Signatures::from_env()?, detections).with_asns(AsnTable::from_env()?))
+    }
+
+    /// Fills in the `asn` of `row` and raises it to the attack signature
+    /// `entry` matches, then returns the rows the detections add.
+    pub fn observe(&self, entry: &LogEntry, row: &mut DbLogEntry) -> Vec<DbLogEntry> {
+        self.asns.apply(row);
+        self.signatures.apply(entry, row);
+        let mut detections = self.detections.lock().unwrap();
+        detections.iter_mut().flat_map(|detection| detection.observe(entry, row)).collect()
+    }
+}
+
+impl Default for Detector {
+    /// A detector without signatures or detections.
+    fn default() -> Self {
+        Self::new(Signatures::default(), Vec::new())
+    }
+}
+
+/// Entries and calls shared by the detections' tests.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use chrono::{DateTime, Utc};
+    use db::schema::DbLogEntry;
+
+    use super::Detection;
+    use crate::ingest::classify::classify;
+    use crate::models::log::{LogEntry, LogSource};
+
+    /// An sshd login by `user` from `ip`.
+    pub fn login(timestamp: DateTime<Utc>, ip: &str, user: &str, success: bool) -> LogEntry {
+        let message = if success { "Accepted password" } else { "Failed password" };
+        let mut entry = LogEntry::new(LogSource::AuthLog, timestamp, message);
+        entry.ip_address = Some(ip.to_string());
+        entry.user = Some(user.to_string());
+        entry.success = Some(success);
+        entry
+    }
+
+    /// A failed sshd login logged now.
+    pub fn failed_login(ip: &str, user: &str) -> LogEntry {
+        login(Utc::now(), ip, user, false)
+    }
+
+    /// Observes `entry` along with its classified row, as `Detector` does.
+    pub fn observe(detection: &mut dyn Detection, entry: &LogEntry) -> Vec<DbLogEntry> {
+        detection.observe(entry, &classify(entry))
+    }
+}
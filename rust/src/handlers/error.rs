@@ -0,0 +1,77 @@
+use axum::extract::rejection::QueryRejection;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use db::error::DbError;
+use serde_json::json;
+use tracing::error;
+
+/// Error returned by API handlers, rendered as a status code and a JSON body
+/// of the form `{"error": {"kind": "...", "message": "..."}}`.
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    Unauthorized(String),
+    Forbidden(String),
+    Conflict(String),
+    Db(DbError),
+}
+
+impl From<DbError> for ApiError {
+    fn from(error: DbError) -> Self {
+        ApiError::Db(error)
+    }
+}
+
+impl From<QueryRejection> for ApiError {
+    fn from(rejection: QueryRejection) -> Self {
+        ApiError::BadRequest(rejection.body_text())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, kind, message) = match self {
+            ApiError::BadRequest(message) => (StatusCode::BAD_REQUEST, "bad_request", message),
+            ApiError::Unauthorized(message) => (StatusCode::UNAUTHORIZED, "unauthorized", message),
+            ApiError::Forbidden(message) => (StatusCode::FORBIDDEN, "forbidden", message),
+            ApiError::Conflict(message) => (StatusCode::CONFLICT, "conflict", message),
+            ApiError::Db(e) => {
+                let status = match e {
+                    DbError::NotFound => StatusCode::NOT_FOUND,
+                    DbError::Connection(_) => StatusCode::SERVICE_UNAVAILABLE,
+                    DbError::Config(_)
+                    | DbError::SchemaMismatch(_)
+                    | DbError::Serialization(_)
+                    | DbError::Query(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                };
+                if status.is_server_error() {
+                    error!("Database error: {}", e);
+                }
+                (status, e.kind(), e.to_string())
+            }
+        };
+
+        (status, Json(json!({"error": {"kind": kind, "message": message}}))).into_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_db_errors_map_to_status_codes() {
+        let cases = [
+            (DbError::Connection("refused".to_string()), StatusCode::SERVICE_UNAVAILABLE),
+            (DbError::SchemaMismatch("missing column".to_string()), StatusCode::INTERNAL_SERVER_ERROR),
+            (DbError::NotFound, StatusCode::NOT_FOUND),
+        ];
+        for (error, status) in cases {
+            assert_eq!(ApiError::from(error).into_response().status(), status);
+        }
+        assert_eq!(ApiError::BadRequest("bad".to_string()).into_response().status(), StatusCode::BAD_REQUEST);
+        assert_eq!(ApiError::Unauthorized("no".to_string()).into_response().status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(ApiError::Forbidden("no".to_string()).into_response().status(), StatusCode::FORBIDDEN);
+        assert_eq!(ApiError::Conflict("busy".to_string()).into_response().status(), StatusCode::CONFLICT);
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::Read;
+use std::net::IpAddr;
+use std::sync::Mutex;
+
+use axum::body::Bytes;
+use axum::extract::State;
+use axum::http::header::CONTENT_ENCODING;
+use axum::http::HeaderMap;
+use axum::response::Json;
+use chrono::{Duration, Utc};
+use flate2::read::GzDecoder;
+use uuid::Uuid;
+
+use crate::handlers::error::ApiError;
+use crate::ingest::classify::classify;
+use crate::middleware::auth::{AuthorizedAgent, IngestAuth};
+use crate::models::batch::{IngestBatch, IngestResponse, InvalidEntry};
+use crate::server::state::AppState;
+
+/// Largest batch accepted, after decompression.
+pub const MAX_BATCH_BYTES: usize = 32 * 1024 * 1024;
+pub const MAX_BATCH_ENTRIES: usize = 10_000;
+/// Batch IDs remembered to recognise retries.
+const RECENT_BATCHES: usize = 4096;
+
+/// State of the push endpoint.
+#[derive(Default)]
+pub struct IngestState {
+    pub auth: IngestAuth,
+    recent: Mutex<RecentBatches>,
+}
+
+impl IngestState {
+    pub fn new(auth: IngestAuth) -> Self {
+        Self { auth, recent: Mutex::default() }
+    }
+}
+
+/// IDs of the most recently stored batches and of those being stored.
+#[derive(Default)]
+struct RecentBatches {
+    order: VecDeque<Uuid>,
+    ids: HashSet<Uuid>,
+    in_flight: HashSet<Uuid>,
+}
+
+/// Whether a batch still has to be stored.
+#[derive(Debug, PartialEq, Eq)]
+enum Reservation {
+    Reserved,
+    Stored,
+    InFlight,
+}
+
+impl RecentBatches {
+    fn contains(&self, id: &Uuid) -> bool {
+        self.ids.contains(id)
+    }
+
+    /// Marks `id` as being stored unless it is, or has been, already.
+    fn reserve(&mut self, id: Uuid) -> Reservation {
+        if self.contains(&id) {
+            Reservation::Stored
+        } else if !self.in_flight.insert(id) {
+            Reservation::InFlight
+        } else {
+            Reservation::Reserved
+        }
+    }
+
+    /// Ends the reservation of `id`, remembering it if it was stored.
+    fn release(&mut self, id: Uuid, stored: bool) {
+        self.in_flight.remove(&id);
+        if stored {
+            self.insert(id);
+        }
+    }
+
+    fn insert(&mut self, id: Uuid) {
+        if !self.ids.insert(id) {
+            return;
+        }
+        self.order.push_back(id);
+        if self.order.len() > RECENT_BATCHES {
+            let oldest = self.order.pop_front().expect("queue is not empty");
+            self.ids.remove(&oldest);
+        }
+    }
+}
+
+/// A reserved batch ID, released when dropped. A request cancelled while its
+/// batch is being inserted, because the agent gave up waiting or the server is
+/// shutting down, thus does not leave the batch in flight for good.
+struct BatchReservation<'a> {
+    recent: &'a Mutex<RecentBatches>,
+    id: Uuid,
+    stored: bool,
+}
+
+impl Drop for BatchReservation<'_> {
+    fn drop(&mut self) {
+        self.recent.lock().unwrap().release(self.id, self.stored);
+    }
+}
+
+/// Stores a batch pushed by an agent and publishes it to stream clients.
+///
+/// The reply is only sent once the rows are in the database, so an agent that
+/// gets one can drop its copy. A batch whose reply was lost is sent again with
+/// the same ID and acknowledged without being stored twice. Invalid entries do
+/// not fail the batch, they are listed in the reply. Agents may only push
+/// batches under the agent ID they authenticated as.
+pub async fn ingest(
+    State(state): State<AppState>,
+    AuthorizedAgent(agent_id): AuthorizedAgent,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Json<IngestResponse>, ApiError> {
+    let body = decode_body(&headers, &body)?;
+    let mut batch: IngestBatch =
+        serde_json::from_slice(&body).map_err(|e| ApiError::BadRequest(format!("Invalid batch: {}", e)))?;
+    let invalid = validate(&mut batch)?;
+    if batch.agent_id != agent_id {
+        return Err(ApiError::Forbidden(format!("Not allowed to push logs as {}", batch.agent_id)));
+    }
+
+    // Reserved before inserting, so a retry sent while the first copy is
+    // still being stored is not stored as well.
+    let reservation = state.ingest.recent.lock().unwrap().reserve(batch.id);
+    let mut reservation = match reservation {
+        Reservation::Reserved => BatchReservation { recent: &state.ingest.recent, id: batch.id, stored: false },
+        Reservation::Stored => {
+            return Ok(Json(IngestResponse { id: batch.id, accepted: batch.entries.len(), duplicate: true, invalid }));
+        }
+        Reservation::InFlight => return Err(ApiError::Conflict("Batch is already being stored".to_string())),
+    };
+
+    let accepted = batch.entries.len();
+    let mut entries = Vec::with_capacity(accepted);
+    for mut entry in batch.entries {
+        entry.agent_id = Some(agent_id.clone());
+        let mut row = classify(&entry);
+        let detections = state.detector.observe(&entry, &mut row);
+        entries.push((entry, row, detections));
+    }
+    let rows = entries.iter().flat_map(|(_, row, detections)| std::iter::once(row).chain(detections)).cloned().collect();
+    state.db.insert_logs(rows).await?;
+    reservation.stored = true;
+    drop(reservation);
+
+    for (entry, row, detections) in entries {
+        state.hub.publish_observed(entry, row, &detections);
+    }
+    Ok(Json(IngestResponse { id: batch.id, accepted, duplicate: false, invalid }))
+}
+
+/// The JSON body, decompressed according to `Content-Encoding`.
+fn decode_body(headers: &HeaderMap, body: &Bytes) -> Result<Vec<u8>, ApiError> {
+    let encoding = headers.get(CONTENT_ENCODING).and_then(|value| value.to_str().ok()).unwrap_or("identity");
+    match encoding.trim() {
+        "identity" => Ok(body.to_vec()),
+        "gzip" => {
+            let mut decoded = Vec::new();
+            GzDecoder::new(&body[..])
+                .take(MAX_BATCH_BYTES as u64 + 1)
+                .read_to_end(&mut decoded)
+                .map_err(|e| ApiError::BadRequest(format!("Invalid gzip body: {}", e)))?;
+            if decoded.len() > MAX_BATCH_BYTES {
+                return Err(ApiError::BadRequest(format!("Batch is larger than {} bytes", MAX_BATCH_BYTES)));
+            }
+            Ok(decoded)
+        }
+        other => Err(ApiError::BadRequest(format!("Unsupported Content-Encoding: {}", other))),
+    }
+}
+
+/// Rejects a batch that is malformed as a whole. Entries timestamped in the
+/// future are dropped and invalid addresses removed, which classification
+/// would store as 0.0.0.0 anyway, so one bad entry does not cost the others.
+fn validate(batch: &mut IngestBatch) -> Result<Vec<InvalidEntry>, ApiError> {
+    if batch.agent_id.trim().is_empty() {
+        return Err(ApiError::BadRequest("Batch has no agent_id".to_string()));
+    }
+    if batch.entries.len() > MAX_BATCH_ENTRIES {
+        return Err(ApiError::BadRequest(format!("Batch has more than {} entries", MAX_BATCH_ENTRIES)));
+    }
+
+    // Agents' clocks drift, but not by days.
+    let latest = Utc::now() + Duration::days(1);
+    let mut invalid = Vec::new();
+    for (index, mut entry) in std::mem::take(&mut batch.entries).into_iter().enumerate() {
+        if entry.timestamp > latest {
+            invalid.push(InvalidEntry { index, reason: "timestamped in the future, dropped".to_string() });
+            continue;
+        }
+        if entry.ip_address.as_deref().is_some_and(|ip| ip.parse::<IpAddr>().is_err()) {
+            invalid.push(InvalidEntry { index, reason: "invalid IP address, stored without it".to_string() });
+            entry.ip_address = None;
+        }
+        batch.entries.push(entry);
+    }
+    Ok(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use db::database::{Database, DbResult};
+    use db::mock::database::MockDB;
+    use db::query::{LogPage, LogQuery};
+    use db::schema::DbLogEntry;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use tower::ServiceExt;
+
+    use crate::ingest::hub::LogHub;
+    use crate::models::log::{LogEntry, LogSource};
+    use crate::routes::configure_routes;
+
+    /// Never finishes its first insert, then behaves like `MockDB`.
+    #[derive(Default)]
+    struct StalledDB {
+        inner: MockDB,
+        stalled: AtomicBool,
+    }
+
+    #[async_trait::async_trait]
+    impl Database for StalledDB {
+        async fn insert_log(&self, log: DbLogEntry) -> DbResult<()> {
+            self.insert_logs(vec![log]).await
+        }
+
+        async fn insert_logs(&self, logs: Vec<DbLogEntry>) -> DbResult<()> {
+            if !self.stalled.swap(true, Ordering::SeqCst) {
+                std::future::pending::<()>().await;
+            }
+            self.inner.insert_logs(logs).await
+        }
+
+        async fn query_logs(&self, query: &LogQuery) -> DbResult<LogPage> {
+            self.inner.query_logs(query).await
+        }
+
+        async fn count_logs(&self, query: &LogQuery) -> DbResult<u64> {
+            self.inner.count_logs(query).await
+        }
+
+        async fn delete_logs(&self, query: &LogQuery) -> DbResult<()> {
+            self.inner.delete_logs(query).await
+        }
+    }
+
+    fn app(db: Arc<dyn Database>) -> axum::Router {
+        let auth = IngestAuth::new(vec![("agent-1".to_string(), "s3cret".to_string())], None);
+        configure_routes(AppState {
+            hub: Arc::new(LogHub::new(8)),
+            db,
+            ingest: Arc::new(IngestState::new(auth)),
+            detector: Arc::default(),
+            writer: Default::default(),
+        })
+    }
+
+    fn batch(id: u128, ip: &str) -> IngestBatch {
+        let mut entry = LogEntry::new(LogSource::AuthLog, Utc::now(), "Failed password for root");
+        entry.ip_address = Some(ip.to_string());
+        entry.success = Some(false);
+        entry.host = Some("web1".to_string());
+        IngestBatch { id: Uuid::from_u128(id), agent_id: "agent-1".to_string(), entries: vec![entry] }
+    }
+
+    fn gzip(batch: &IngestBatch) -> Vec<u8> {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&serde_json::to_vec(batch).unwrap()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    async fn push(app: &axum::Router, token: &str, body: Vec<u8>) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/api/v1/ingest")
+            .header("authorization", format!("Bearer {}", token))
+            .header("content-type", "application/json")
+            .header("content-encoding", "gzip")
+            .body(Body::from(body))
+            .unwrap();
+        let response = app.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_batches_are_stored_once() {
+        let db = Arc::new(MockDB::new());
+        let app = app(db.clone());
+
+        let (status, body) = push(&app, "s3cret", gzip(&batch(1, "10.0.0.7"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["accepted"], 1);
+        assert_eq!(body["duplicate"], false);
+
+        let rows = db.fetch_logs(None).await.unwrap();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0].host, "web1");
+        assert_eq!(rows[0].agent_id, "agent-1");
+
+        // A retry of the same batch is acknowledged without a second copy.
+        let (status, body) = push(&app, "s3cret", gzip(&batch(1, "10.0.0.7"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["duplicate"], true);
+        assert_eq!(db.fetch_logs(None).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_unauthorized_and_invalid_batches_are_rejected() {
+        let db = Arc::new(MockDB::new());
+        let app = app(db.clone());
+
+        let (status, body) = push(&app, "wrong", gzip(&batch(1, "10.0.0.7"))).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"]["kind"], "unauthorized");
+
+        let mut impostor = batch(2, "10.0.0.7");
+        impostor.agent_id = "agent-2".to_string();
+        let (status, body) = push(&app, "s3cret", gzip(&impostor)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["error"]["kind"], "forbidden");
+
+        let (status, _) = push(&app, "s3cret", b"not gzip".to_vec()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        assert!(db.fetch_logs(None).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_invalid_entries_do_not_fail_the_batch() {
+        let db = Arc::new(MockDB::new());
+        let app = app(db.clone());
+
+        let mut pushed = batch(1, "10.0.0.7");
+        let mut bad_ip = pushed.entries[0].clone();
+        bad_ip.ip_address = Some("not-an-ip".to_string());
+        let mut future = pushed.entries[0].clone();
+        future.timestamp = Utc::now() + Duration::days(7);
+        pushed.entries.extend([bad_ip, future]);
+
+        let (status, body) = push(&app, "s3cret", gzip(&pushed)).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["accepted"], 2);
+        assert_eq!(body["invalid"][0]["index"], 1);
+        assert_eq!(body["invalid"][1]["index"], 2);
+
+        let rows = db.fetch_logs(None).await.unwrap();
+        assert_eq!(rows.len(), 2);
+        assert!(rows.iter().any(|row| row.source_ip == "0.0.0.0".parse::<IpAddr>().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_insert_releases_the_batch() {
+        let db = Arc::new(StalledDB::default());
+        let app = app(db.clone());
+
+        // The agent gives up on the first attempt while it is being inserted.
+        let attempt = push(&app, "s3cret", gzip(&batch(1, "10.0.0.7")));
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(100), attempt).await.is_err());
+
+        let (status, body) = push(&app, "s3cret", gzip(&batch(1, "10.0.0.7"))).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["duplicate"], false);
+        assert_eq!(db.fetch_logs(None).await.unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_recent_batches_forget_the_oldest() {
+        let mut recent = RecentBatches::default();
+        for id in 0..=RECENT_BATCHES as u128 {
+            recent.insert(Uuid::from_u128(id));
+        }
+        assert!(!recent.contains(&Uuid::from_u128(0)));
+        assert!(recent.contains(&Uuid::from_u128(1)));
+        assert!(recent.contains(&Uuid::from_u128(RECENT_BATCHES as u128)));
+    }
+
+    #[test]
+    fn test_batches_are_reserved_while_being_stored() {
+        let mut recent = RecentBatches::default();
+        let id = Uuid::from_u128(1);
+        assert_eq!(recent.reserve(id), Reservation::Reserved);
+        assert_eq!(recent.reserve(id), Reservation::InFlight);
+
+        // A failed insert lets the next retry store it.
+        recent.release(id, false);
+        assert_eq!(recent.reserve(id), Reservation::Reserved);
+        recent.release(id, true);
+        assert_eq!(recent.reserve(id), Reservation::Stored);
+    }
+}
@@ -1,24 +1,251 @@
-use axum::response::IntoResponse;
-use std::fs;
+use axum::extract::rejection::QueryRejection;
+use axum::extract::{Query, State};
+use axum::http::HeaderMap;
+use axum::response::sse::{Event, KeepAlive, Sse};
+use std::convert::Infallible;
 use axum::response::Json;
-use crate::models::log::LogEntry;
-use serde_json::{from_str, json, Value};
-use std::path::PathBuf;
-
-pub async fn get_logs() -> impl IntoResponse {
-    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    path.push("db/logs.json");
-    match fs::read_to_string(path) {
-        Ok(logs) => {
-            let parsed_logs: Value = from_str(&logs).unwrap();
-            Json(parsed_logs)
+use chrono::{DateTime, Utc};
+use db::query::{Cursor, LogPage, LogQuery, SortField, SortOrder};
+use db::schema::{EventType, ThreatLevel};
+use serde::Deserialize;
+use tokio_stream::wrappers::BroadcastStream;
+use tokio_stream::{Stream, StreamExt};
+use tracing::warn;
+
+use crate::handlers::error::ApiError;
+use crate::ingest::hub::{EventKind, LogEvent};
+use crate::models::filter::{IpRange, LogFilter};
+use crate::server::state::AppState;
+
+/// Query parameters for `GET /api/v1/logs`.
+///
+/// `from`/`to` are RFC 3339 timestamps, `source_ip` accepts an address or CIDR
+/// block and `q` is a case-insensitive free-text search. Pass the `next_cursor`
+/// of a response as `cursor` to fetch the following page.
+#[derive(Debug, Default, Deserialize)]
+pub struct LogsQuery {
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    pub source_ip: Option<IpRange>,
+    pub event_type: Option<EventType>,
+    pub threat_level: Option<ThreatLevel>,
+    pub status: Option<u16>,
+    pub targeted_service: Option<String>,
+    pub host: Option<String>,
+    pub environment: Option<String>,
+    pub q: Option<String>,
+    pub sort: Option<SortField>,
+    pub order: Option<SortOrder>,
+    pub limit: Option<u32>,
+    pub cursor: Option<String>,
+}
+
+impl LogsQuery {
+    fn into_log_query(self) -> Result<LogQuery, ApiError> {
+        let sort = self.sort.unwrap_or_default();
+        let cursor = match self.cursor {
+            Some(token) => Some(
+                Cursor::decode(&token)
+                    .filter(|cursor| cursor.fits(sort))
+                    .ok_or_else(|| ApiError::BadRequest("Invalid cursor".to_string()))?,
+            ),
+            None => None,
+        };
+
+        Ok(LogQuery {
+            from: self.from,
+            to: self.to,
+            source_ip: self.source_ip.map(|range| range.net()),
+            event_type: self.event_type,
+            threat_level: self.threat_level,
+            status: self.status,
+            targeted_service: self.targeted_service,
+            host: self.host,
+            environment: self.environment,
+            search: self.q,
+            sort,
+            order: self.order.unwrap_or_default(),
+            limit: self.limit,
+            cursor,
+        })
+    }
+}
+
+/// Returns one page of stored log rows matching the query.
+pub async fn get_logs(
+    State(state): State<AppState>,
+    query: Result<Query<LogsQuery>, QueryRejection>,
+) -> Result<Json<LogPage>, ApiError> {
+    let Query(query) = query?;
+    let page = state.db.query_logs(&query.into_log_query()?).await?;
+    Ok(Json(page))
+}
+
+/// Streams newly parsed log entries as Server-Sent Events.
+///
+/// Each entry is sent as a `log` event, and each detection raised on it as an
+/// `alert` event carrying both the entry and the alert row. Entries can be
+/// narrowed with the `LogFilter` query parameters. A client
+/// reconnecting with `Last-Event-ID` first receives the buffered events it missed.
+pub async fn stream_logs(
+    State(state): State<AppState>,
+    Query(filter): Query<LogFilter>,
+    headers: HeaderMap,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let last_event_id = headers
+        .get("last-event-id")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<u64>().ok());
+
+    let (backlog, receiver) = state.hub.subscribe_since(last_event_id);
+    let live = BroadcastStream::new(receiver).filter_map(|event| match event {
+        Ok(event) => Some(event),
+        Err(e) => {
+            warn!("Log stream client fell behind: {}", e);
+            None
         }
-        ,
-        Err(_) => Json(json!({"error": "Failed to read logs"})),
+    });
+
+    let stream = tokio_stream::iter(backlog)
+        .chain(live)
+        .filter(move |event| filter.matches(&event.entry))
+        .filter_map(|event| to_sse_event(&event).map(Ok));
+
+    Sse::new(stream).keep_alive(KeepAlive::default())
+}
+
+fn to_sse_event(event: &LogEvent) -> Option<Event> {
+    let sse = Event::default().id(event.id.to_string());
+    match event.kind {
+        EventKind::Log => sse.event("log").json_data(&event.entry),
+        EventKind::Alert => sse.event("alert").json_data(event),
     }
+    .ok()
 }
 
-pub async fn stream_logs() -> impl IntoResponse {
-    // TODO: implement streaming logs
-    Json::<Vec<LogEntry>>(Vec::new())  
-}
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode, Uri};
+    use db::database::Database;
+    use db::mock::database::MockDB;
+    use chrono::TimeZone;
+    use db::schema::{ActionTaken, DbLogEntry};
+    use tower::ServiceExt;
+    use uuid::Uuid;
+
+    use crate::ingest::hub::LogHub;
+    use crate::routes::configure_routes;
+
+    fn parse(uri: &str) -> LogsQuery {
+        let uri: Uri = uri.parse().unwrap();
+        Query::<LogsQuery>::try_from_uri(&uri).unwrap().0
+    }
+
+    #[test]
+    fn test_query_parameters_map_onto_log_query() {
+        let query = parse("/api/v1/logs?from=2025-03-09T00:00:00Z&source_ip=10.0.0.1&sort=source_ip&order=asc&q=wp-admin&limit=20")
+            .into_log_query()
+            .unwrap();
+
+        assert_eq!(query.from.unwrap().to_rfc3339(), "2025-03-09T00:00:00+00:00");
+        assert_eq!(query.source_ip, Some("10.0.0.1/32".parse().unwrap()));
+        assert_eq!(query.sort, SortField::SourceIp);
+        assert_eq!(query.order, SortOrder::Asc);
+        assert_eq!(query.search, Some("wp-admin".to_string()));
+        assert_eq!(query.limit, Some(20));
+    }
+
+    #[test]
+    fn test_invalid_cursor_is_rejected() {
+        assert!(parse("/api/v1/logs?cursor=garbage").into_log_query().is_err());
+    }
+
+    #[test]
+    fn test_cursor_for_another_sort_is_rejected() {
+        let cursor = Cursor { value: "10.0.0.1".to_string(), id: Uuid::nil() }.encode();
+        assert!(parse(&format!("/api/v1/logs?sort=source_ip&cursor={}", cursor)).into_log_query().is_ok());
+        assert!(parse(&format!("/api/v1/logs?sort=status&cursor={}", cursor)).into_log_query().is_err());
+    }
+
+    #[test]
+    fn test_typed_filters_are_parsed() {
+        let query = parse("/api/v1/logs?threat_level=critical&status=404&event_type=HTTP%20Not%20Found")
+            .into_log_query()
+            .unwrap();
+        assert_eq!(query.threat_level, Some(ThreatLevel::Critical));
+        assert_eq!(query.status, Some(404));
+        assert_eq!(query.event_type, Some(EventType::HttpNotFound));
+    }
+
+    #[test]
+    fn test_invalid_cidr_is_rejected() {
+        let uri: Uri = "/api/v1/logs?source_ip=10.0.0.0/99".parse().unwrap();
+        assert!(Query::<LogsQuery>::try_from_uri(&uri).is_err());
+    }
+
+    fn row(id: u128, second: u32, threat_level: ThreatLevel) -> DbLogEntry {
+        DbLogEntry {
+            id: Uuid::from_u128(id),
+            timestamp: Utc.with_ymd_and_hms(2025, 3, 9, 12, 0, second).unwrap(),
+            source_ip: "185.93.89.118".parse().unwrap(),
+            country: String::new(),
+            asn: 0,
+            event_type: EventType::SshFailedLogin,
+            targeted_service: "SSH".to_string(),
+            targeted_endpoint: "user root".to_string(),
+            request: "Failed password for root".to_string(),
+            status: 0,
+            action_taken: ActionTaken::Logged,
+            threat_level,
+            host: String::new(),
+            agent_id: String::new(),
+            environment: String::new(),
+        }
+    }
+
+    async fn get_page(app: &axum::Router, uri: &str) -> LogPage {
+        let response = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_logs_pages_through_mock_database() {
+        let db = Arc::new(MockDB::new());
+        db.insert_logs(vec![
+            row(1, 1, ThreatLevel::High),
+            row(2, 2, ThreatLevel::Low),
+            row(3, 3, ThreatLevel::High),
+        ]).await.unwrap();
+        let app = configure_routes(AppState { hub: Arc::new(LogHub::new(8)), db, ingest: Arc::default(), detector: Arc::default(), writer: Default::default() });
+
+        let page = get_page(&app, "/api/v1/logs?threat_level=High&limit=1").await;
+        assert_eq!(page.logs.len(), 1);
+        assert_eq!(page.logs[0].id, Uuid::from_u128(3));
+
+        let next = format!("/api/v1/logs?threat_level=High&limit=1&cursor={}", page.next_cursor.unwrap());
+        let page = get_page(&app, &next).await;
+        assert_eq!(page.logs.len(), 1);
+        assert_eq!(page.logs[0].id, Uuid::from_u128(1));
+        assert!(page.next_cursor.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_bad_requests_get_json_errors() {
+        let app = configure_routes(AppState { hub: Arc::new(LogHub::new(8)), db: Arc::new(MockDB::new()), ingest: Arc::default(), detector: Arc::default(), writer: Default::default() });
+
+        for uri in ["/api/v1/logs?cursor=garbage", "/api/v1/logs?source_ip=10.0.0.0/99", "/api/v1/logs?threat_level=severe"] {
+            let response = app.clone().oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+            assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+            let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+            assert_eq!(body["error"]["kind"], "bad_request");
+        }
+    }
+}
@@ -0,0 +1,54 @@
+use axum::extract::State;
+use axum::response::Json;
+use db::writer::InsertResult;
+
+use crate::server::state::AppState;
+
+/// `GET /api/v1/metrics`: rows and bytes the writer has committed and dropped since startup.
+pub async fn get_metrics(State(state): State<AppState>) -> Json<InsertResult> {
+    Json(state.writer.snapshot())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use axum::body::{to_bytes, Body};
+    use axum::http::{Request, StatusCode};
+    use chrono::Utc;
+    use db::mock::database::MockDB;
+    use db::writer::{LogWriter, WriterConfig};
+    use tower::ServiceExt;
+
+    use crate::ingest::classify::classify;
+    use crate::ingest::hub::LogHub;
+    use crate::models::log::{LogEntry, LogSource};
+    use crate::routes::configure_routes;
+    use crate::server::state::AppState;
+
+    #[tokio::test]
+    async fn test_metrics_report_committed_rows() {
+        let db = Arc::new(MockDB::new());
+        let (writer, written) = LogWriter::spawn(db.clone(), WriterConfig::default());
+        let stats = writer.stats();
+        writer.write(classify(&LogEntry::new(LogSource::AuthLog, Utc::now(), "Accepted password for root"))).await.unwrap();
+        drop(writer);
+        written.await.unwrap();
+
+        let app = configure_routes(AppState {
+            hub: Arc::new(LogHub::new(8)),
+            db,
+            ingest: Arc::default(),
+            detector: Arc::default(),
+            writer: stats,
+        });
+        let response = app.oneshot(Request::get("/api/v1/metrics").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["rows"], 1);
+        assert_eq!(body["dropped"], 0);
+        assert!(body["bytes"].as_u64().unwrap() > 0);
+    }
+}
@@ -1 +1,5 @@
-pub mod logs;
\ No newline at end of file
+pub mod error;
+pub mod ingest;
+pub mod logs;
+pub mod metrics;
+pub mod ws;
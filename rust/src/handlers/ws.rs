@@ -0,0 +1,115 @@
+use std::sync::Arc;
+
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::State;
+use axum::response::Response;
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::debug;
+
+use crate::ingest::hub::{LogEvent, LogHub};
+use crate::models::filter::Subscription;
+use crate::server::state::AppState;
+
+/// Messages a WebSocket client may send, e.g.
+/// `{"type": "subscribe", "ips": ["10.0.0.0/8"], "threat_levels": ["High"]}`.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Replaces the current filter.
+    Subscribe(Subscription),
+    /// Stops delivery until the next `subscribe`.
+    Unsubscribe,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Subscribed { subscription: &'a Subscription },
+    Unsubscribed,
+    Event(&'a LogEvent),
+    /// The client was too slow and `skipped` events were dropped.
+    Lagged { skipped: u64 },
+    Error { message: String },
+}
+
+/// Upgrades to a WebSocket that streams log events matching the client's subscription.
+///
+/// Nothing is sent until the client subscribes. Sending another `subscribe`
+/// swaps the filter without reconnecting.
+pub async fn ws_logs(State(state): State<AppState>, ws: WebSocketUpgrade) -> Response {
+    ws.on_upgrade(move |socket| handle_socket(socket, state.hub))
+}
+
+async fn handle_socket(mut socket: WebSocket, hub: Arc<LogHub>) {
+    let (_, mut events) = hub.subscribe_since(None);
+    let mut subscription: Option<Subscription> = None;
+
+    loop {
+        let sent = tokio::select! {
+            message = socket.recv() => match message {
+                Some(Ok(Message::Text(text))) => match serde_json::from_str::<ClientMessage>(&text) {
+                    Ok(ClientMessage::Subscribe(new)) => {
+                        let subscription = subscription.insert(new);
+                        send(&mut socket, &ServerMessage::Subscribed { subscription }).await
+                    }
+                    Ok(ClientMessage::Unsubscribe) => {
+                        subscription = None;
+                        send(&mut socket, &ServerMessage::Unsubscribed).await
+                    }
+                    Err(e) => send(&mut socket, &ServerMessage::Error { message: e.to_string() }).await,
+                },
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => break,
+                // Pings are answered by axum, binary frames are ignored.
+                Some(Ok(_)) => Ok(()),
+            },
+            event = events.recv() => match event {
+                Ok(event) => match &subscription {
+                    Some(subscription) if subscription.matches(&event.entry, event.row.as_ref()) => {
+                        send(&mut socket, &ServerMessage::Event(&event)).await
+                    }
+                    _ => Ok(()),
+                },
+                Err(RecvError::Lagged(skipped)) => send(&mut socket, &ServerMessage::Lagged { skipped }).await,
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        if let Err(e) = sent {
+            debug!("WebSocket client disconnected: {}", e);
+            break;
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &ServerMessage<'_>) -> Result<(), axum::Error> {
+    let text = serde_json::to_string(message).map_err(axum::Error::new)?;
+    socket.send(Message::Text(text.into())).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_subscribe_message() {
+        let message: ClientMessage = serde_json::from_str(
+            r#"{"type": "subscribe", "sources": ["AuthLog"], "ips": ["10.0.0.0/8"], "event_types": ["SSH Brute Force"]}"#,
+        ).unwrap();
+
+        match message {
+            ClientMessage::Subscribe(subscription) => {
+                assert_eq!(subscription.ips.len(), 1);
+                assert_eq!(subscription.event_types, vec!["SSH Brute Force".to_string()]);
+                assert!(subscription.threat_levels.is_empty());
+            }
+            other => panic!("unexpected message: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reject_invalid_cidr() {
+        let result = serde_json::from_str::<ClientMessage>(r#"{"type": "subscribe", "ips": ["10.0.0.0/99"]}"#);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tracing::warn;
+
+use crate::models::log::LogEntry;
+
+/// Number of leading bytes hashed to recognise a file after it has been rotated.
+pub const FINGERPRINT_LEN: u64 = 256;
+
+/// Hash of the first `len` bytes of a file.
+///
+/// Inodes are reused and change when logrotate compresses or copies a file,
+/// so the fingerprint is what ties a checkpoint to its file generation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fingerprint {
+    pub len: u64,
+    pub hash: u64,
+}
+
+impl Fingerprint {
+    /// Reads up to `len` bytes from `reader`. Returns `None` if fewer bytes are available.
+    pub fn read(reader: impl Read, len: u64) -> io::Result<Option<Self>> {
+        let mut buf = Vec::with_capacity(len as usize);
+        reader.take(len).read_to_end(&mut buf)?;
+        if (buf.len() as u64) < len {
+            return Ok(None);
+        }
+        Ok(Some(Self::of(&buf)))
+    }
+
+    pub fn of(bytes: &[u8]) -> Self {
+        // FNV-1a, stable across builds unlike `DefaultHasher`.
+        let hash = bytes.iter().fold(0xcbf29ce484222325u64, |hash, byte| {
+            (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+        });
+        Self { len: bytes.len() as u64, hash }
+    }
+}
+
+/// How far a file has been ingested.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    pub inode: u64,
+    /// Byte offset just past the last complete line handed to the sink.
+    pub offset: u64,
+    pub fingerprint: Fingerprint,
+}
+
+/// Contents of the checkpoint file.
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Stored {
+    #[serde(default)]
+    files: HashMap<String, Checkpoint>,
+    /// Cursor of the last journal record read, per journal input.
+    #[serde(default)]
+    cursors: HashMap<String, String>,
+}
+
+/// Per-file checkpoints and journal cursors persisted as JSON so restarts
+/// neither re-ingest nor skip lines.
+pub struct CheckpointStore {
+    path: PathBuf,
+    entries: Mutex<Stored>,
+}
+
+impl CheckpointStore {
+    /// Loads `path`, starting empty if it is missing or unreadable.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let entries = match fs::read_to_string(&path) {
+            // Files used to be the only thing checkpointed, at the top level.
+            Ok(contents) => match serde_json::from_str::<HashMap<String, Checkpoint>>(&contents) {
+                Ok(files) => Stored { files, cursors: HashMap::new() },
+                Err(_) => serde_json::from_str(&contents).unwrap_or_else(|e| {
+                    warn!("Ignoring corrupt checkpoint file {}: {}", path.display(), e);
+                    Stored::default()
+                }),
+            },
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Stored::default(),
+            Err(e) => {
+                warn!("Failed to read checkpoint file {}: {}", path.display(), e);
+                Stored::default()
+            }
+        };
+
+        Self { path, entries: Mutex::new(entries) }
+    }
+
+    pub fn get(&self, file: &Path) -> Option<Checkpoint> {
+        self.entries.lock().unwrap().files.get(&key(file)).copied()
+    }
+
+    /// Records `checkpoint` and rewrites the checkpoint file.
+    pub fn set(&self, file: &Path, checkpoint: Checkpoint) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.files.insert(key(file), checkpoint);
+        self.write(&entries)
+    }
+
+    pub fn cursor(&self, input: &str) -> Option<String> {
+        self.entries.lock().unwrap().cursors.get(input).cloned()
+    }
+
+    /// Records the journal cursor of `input` and rewrites the checkpoint file.
+    pub fn set_cursor(&self, input: &str, cursor: &str) -> io::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        entries.cursors.insert(input.to_string(), cursor.to_string());
+        self.write(&entries)
+    }
+
+    fn write(&self, entries: &Stored) -> io::Result<()> {
+        // Write to a temporary file first so a crash never leaves a torn checkpoint.
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(entries)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// What a source hands on: entries, and how far it has read once the entries
+/// before that point are on their way.
+// Nearly everything sent is an entry, boxing them would cost an allocation each.
+#[allow(clippy::large_enum_variant)]
+pub enum Ingested {
+    Entry(LogEntry),
+    Checkpoint(PendingCheckpoint),
+}
+
+enum Position {
+    File(PathBuf, Checkpoint),
+    Cursor(String, String),
+}
+
+/// How far a source has read, to be saved only once every entry read before it
+/// has been stored. Until then a restart reads those entries again.
+pub struct PendingCheckpoint {
+    store: Arc<CheckpointStore>,
+    position: Position,
+}
+
+impl PendingCheckpoint {
+    pub fn file(store: Arc<CheckpointStore>, file: &Path, checkpoint: Checkpoint) -> Self {
+        Self { store, position: Position::File(file.to_path_buf(), checkpoint) }
+    }
+
+    pub fn cursor(store: Arc<CheckpointStore>, input: &str, cursor: &str) -> Self {
+        Self { store, position: Position::Cursor(input.to_string(), cursor.to_string()) }
+    }
+
+    pub fn save(self) -> io::Result<()> {
+        match &self.position {
+            Position::File(file, checkpoint) => self.store.set(file, *checkpoint),
+            Position::Cursor(input, cursor) => self.store.set_cursor(input, cursor),
+        }
+    }
+}
+
+fn key(file: &Path) -> String {
+    file.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoints_survive_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let store_path = dir.path().join("checkpoints.json");
+        let checkpoint = Checkpoint { inode: 42, offset: 1024, fingerprint: Fingerprint::of(b"first line\n") };
+
+        let store = CheckpointStore::load(&store_path);
+        store.set(Path::new("/var/log/auth.log"), checkpoint).unwrap();
+
+        let reloaded = CheckpointStore::load(&store_path);
+        assert_eq!(reloaded.get(Path::new("/var/log/auth.log")), Some(checkpoint));
+        assert_eq!(reloaded.get(Path::new("/var/log/nginx/access.log")), None);
+    }
+
+    #[test]
+    fn test_cursors_survive_reload_and_old_files_still_load() {
+        let dir = tempfile::tempdir().unwrap();
+        let store_path = dir.path().join("checkpoints.json");
+        let checkpoint = Checkpoint { inode: 7, offset: 12, fingerprint: Fingerprint::of(b"x") };
+        let old = HashMap::from([("/var/log/auth.log".to_string(), checkpoint)]);
+        fs::write(&store_path, serde_json::to_vec(&old).unwrap()).unwrap();
+
+        let store = CheckpointStore::load(&store_path);
+        assert_eq!(store.get(Path::new("/var/log/auth.log")), Some(checkpoint));
+        store.set_cursor("stdin", "s=abc;i=1f").unwrap();
+
+        let reloaded = CheckpointStore::load(&store_path);
+        assert_eq!(reloaded.cursor("stdin").as_deref(), Some("s=abc;i=1f"));
+        assert_eq!(reloaded.get(Path::new("/var/log/auth.log")), Some(checkpoint));
+    }
+
+    #[test]
+    fn test_fingerprint_requires_enough_bytes() {
+        assert_eq!(Fingerprint::read(&b"abc"[..], 3).unwrap(), Some(Fingerprint::of(b"abc")));
+        assert_eq!(Fingerprint::read(&b"ab"[..], 3).unwrap(), None);
+    }
+}
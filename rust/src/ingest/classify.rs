@@ -0,0 +1,183 @@
+use std::net::{IpAddr, Ipv4Addr};
+
+use db::schema::{ActionTaken, DbLogEntry, EventType, ThreatLevel};
+use uuid::Uuid;
+
+use crate::models::log::{LogEntry, LogSource};
+
+/// Maps a parsed entry onto the storage row.
+///
+/// This is a baseline classification from the entry alone (status codes and
+/// auth outcomes). Detections can raise `event_type` and `threat_level` later.
+pub fn classify(entry: &LogEntry) -> DbLogEntry {
+    let mut row = DbLogEntry {
+        id: Uuid::new_v4(),
+        timestamp: entry.timestamp,
+        // Entries without a usable address are stored as 0.0.0.0.
+        source_ip: entry
+            .ip_address
+            .as_deref()
+            .and_then(|ip| ip.parse().ok())
+            .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+        country: String::new(),
+        asn: 0,
+        event_type: EventType::HttpRequest,
+        targeted_service: String::new(),
+        targeted_endpoint: String::new(),
+        request: String::new(),
+        status: 0,
+        action_taken: ActionTaken::Logged,
+        threat_level: ThreatLevel::Info,
+        host: entry.host.clone().unwrap_or_default(),
+        agent_id: entry.agent_id.clone().unwrap_or_default(),
+        environment: entry.environment.clone().unwrap_or_default(),
+    };
+
+    match entry.source {
+        LogSource::NginxAccess | LogSource::ApacheAccess => classify_http(entry, &mut row),
+        LogSource::ApacheError => classify_http_error(entry, &mut row),
+        LogSource::AuthLog => classify_auth(entry, &mut row),
+    }
+    row
+}
+
+fn classify_http(entry: &LogEntry, row: &mut DbLogEntry) {
+    let status_code = entry.status_code.unwrap_or(0);
+    let (event_type, threat_level) = match status_code {
+        401 | 403 => (EventType::HttpAccessDenied, ThreatLevel::Low),
+        404 => (EventType::HttpNotFound, ThreatLevel::Info),
+        400..=499 => (EventType::HttpClientError, ThreatLevel::Low),
+        500..=599 => (EventType::HttpServerError, ThreatLevel::Low),
+        _ => (EventType::HttpRequest, ThreatLevel::Info),
+    };
+    let request = entry.request.clone().unwrap_or_default();
+
+    row.event_type = event_type;
+    row.targeted_service = "HTTP".to_string();
+    // "GET /path HTTP/1.1" -> "/path"
+    row.targeted_endpoint = request.split_whitespace().nth(1).unwrap_or("-").to_string();
+    row.request = request;
+    row.status = status_code;
+    row.threat_level = threat_level;
+}
+
+/// Apache error codes for requests refused by access control or authentication.
+const ACCESS_DENIED_CODES: [&str; 5] = ["AH01630", "AH01797", "AH01617", "AH01618", "AH01276"];
+/// "File does not exist", Apache's not-found message.
+const NOT_FOUND_CODE: &str = "AH00128";
+
+fn classify_http_error(entry: &LogEntry, row: &mut DbLogEntry) {
+    let code = entry.extra.get("code").map_or("", String::as_str);
+    let level = entry.extra.get("level").map_or("", String::as_str);
+    let (event_type, threat_level) = if ACCESS_DENIED_CODES.contains(&code) {
+        (EventType::HttpAccessDenied, ThreatLevel::Low)
+    } else if code == NOT_FOUND_CODE {
+        (EventType::HttpNotFound, ThreatLevel::Info)
+    } else if matches!(level, "emerg" | "alert" | "crit" | "error") {
+        (EventType::HttpServerError, ThreatLevel::Low)
+    } else {
+        (EventType::HttpRequest, ThreatLevel::Info)
+    };
+
+    row.event_type = event_type;
+    row.targeted_service = "HTTP".to_string();
+    row.targeted_endpoint = "-".to_string();
+    // Error log lines have no request line or status, the message stands in for them.
+    row.request = entry.message.clone().unwrap_or_default();
+    row.threat_level = threat_level;
+}
+
+fn classify_auth(entry: &LogEntry, row: &mut DbLogEntry) {
+    let user = entry.user.as_deref().unwrap_or("unknown");
+    let action = entry.auth_action.as_deref().unwrap_or("Unknown");
+    let (event_type, threat_level) = match entry.success {
+        Some(true) => (EventType::SshLogin, ThreatLevel::Info),
+        Some(false) => (EventType::SshFailedLogin, ThreatLevel::Low),
+        // Disconnects of clients that had logged in.
+        None => (EventType::SshSession, ThreatLevel::Info),
+    };
+
+    row.event_type = event_type;
+    row.targeted_service = "SSH".to_string();
+    row.targeted_endpoint = format!("user {}", user);
+    // SSH has no status code, the outcome is part of the request text.
+    row.request = match &entry.message {
+        Some(message) => message.clone(),
+        None => format!("{} password for {}", action, user),
+    };
+    row.threat_level = threat_level;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::apache::ApacheErrorParser;
+    use crate::parsers::nginx::NginxParser;
+    use crate::parsers::LogParser;
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn test_classify_nginx_request() {
+        let entry = NginxParser::new().parse(r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET /admin HTTP/1.1" 403 512"#).unwrap();
+        let row = classify(&entry);
+
+        assert_eq!(row.timestamp, Utc.with_ymd_and_hms(2024, 3, 12, 14, 56, 23).unwrap());
+        assert_eq!(row.source_ip, "192.168.1.1".parse::<IpAddr>().unwrap());
+        assert_eq!(row.event_type, EventType::HttpAccessDenied);
+        assert_eq!(row.targeted_service, "HTTP");
+        assert_eq!(row.targeted_endpoint, "/admin");
+        assert_eq!(row.request, "GET /admin HTTP/1.1");
+        assert_eq!(row.status, 403);
+        assert_eq!(row.threat_level, ThreatLevel::Low);
+        assert_eq!(row.id.get_version_num(), 4);
+    }
+
+    #[test]
+    fn test_classify_apache_denied_request() {
+        let entry = ApacheErrorParser::new()
+            .parse("[Tue Mar 12 14:56:23.456789 2024] [authz_core:error] [pid 4242:tid 1401] [client 203.0.113.9:51234] AH01630: client denied by server configuration: /var/www/html/admin")
+            .unwrap();
+        let row = classify(&entry);
+
+        assert_eq!(row.source_ip, "203.0.113.9".parse::<IpAddr>().unwrap());
+        assert_eq!(row.event_type, EventType::HttpAccessDenied);
+        assert_eq!(row.targeted_service, "HTTP");
+        assert_eq!(row.request, "AH01630: client denied by server configuration: /var/www/html/admin");
+        assert_eq!(row.status, 0);
+    }
+
+    #[test]
+    fn test_classify_ssh_logout() {
+        let entry = LogEntry {
+            ip_address: Some("10.0.0.7".to_string()),
+            user: Some("alice".to_string()),
+            auth_action: Some("Disconnected".to_string()),
+            message: Some("Disconnected from user alice 10.0.0.7 port 52144".to_string()),
+            ..LogEntry::new(LogSource::AuthLog, Utc.with_ymd_and_hms(2024, 3, 12, 14, 56, 23).unwrap(), "")
+        };
+        let row = classify(&entry);
+
+        assert_eq!(row.event_type, EventType::SshSession);
+        assert_eq!(row.request, "Disconnected from user alice 10.0.0.7 port 52144");
+        assert_eq!(row.threat_level, ThreatLevel::Info);
+    }
+
+    #[test]
+    fn test_classify_failed_ssh_login() {
+        let entry = LogEntry {
+            ip_address: Some("10.0.0.7".to_string()),
+            user: Some("root".to_string()),
+            auth_action: Some("Failed".to_string()),
+            success: Some(false),
+            ..LogEntry::new(LogSource::AuthLog, Utc.with_ymd_and_hms(2024, 3, 12, 14, 56, 23).unwrap(), "")
+        };
+        let row = classify(&entry);
+
+        assert_eq!(row.event_type, EventType::SshFailedLogin);
+        assert_eq!(row.targeted_service, "SSH");
+        assert_eq!(row.targeted_endpoint, "user root");
+        assert_eq!(row.request, "Failed password for root");
+        assert_eq!(row.status, 0);
+        assert_eq!(row.threat_level, ThreatLevel::Low);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+use db::schema::DbLogEntry;
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+use crate::models::log::LogEntry;
+
+/// Whether an event reports a parsed entry or an alert raised on one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Log,
+    Alert,
+}
+
+/// A parsed entry tagged with a monotonically increasing id.
+///
+/// The id is used as the SSE event id so clients can resume with `Last-Event-ID`.
+/// Ids restart at 1 whenever the server restarts. `row` holds the storage
+/// row derived from the entry once the ingestion pipeline has classified it,
+/// or for an alert the detection row raised on the entry.
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEvent {
+    pub id: u64,
+    pub kind: EventKind,
+    pub entry: LogEntry,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub row: Option<DbLogEntry>,
+}
+
+struct Recent {
+    next_id: u64,
+    events: VecDeque<LogEvent>,
+}
+
+/// Fans newly parsed entries out to every connected stream client.
+///
+/// The hub keeps the last `capacity` events around so a reconnecting client
+/// can replay what it missed.
+pub struct LogHub {
+    sender: broadcast::Sender<LogEvent>,
+    recent: Mutex<Recent>,
+    capacity: usize,
+}
+
+impl LogHub {
+    pub fn new(capacity: usize) -> Self {
+        let (sender, _) = broadcast::channel(capacity.max(1));
+        Self {
+            sender,
+            recent: Mutex::new(Recent {
+                next_id: 1,
+                events: VecDeque::with_capacity(capacity),
+            }),
+            capacity,
+        }
+    }
+
+    /// Publishes a classified entry, then one alert per detection raised on it.
+    pub fn publish_observed(&self, entry: LogEntry, row: DbLogEntry, detections: &[DbLogEntry]) {
+        self.publish(EventKind::Log, entry.clone(), Some(row));
+        for detection in detections {
+            self.publish(EventKind::Alert, entry.clone(), Some(detection.clone()));
+        }
+    }
+
+    pub fn publish(&self, kind: EventKind, entry: LogEntry, row: Option<DbLogEntry>) -> u64 {
+        // Sending while holding the lock keeps `subscribe_since` from seeing
+        // an event both in the replay buffer and on the live channel.
+        let mut recent = self.recent.lock().unwrap();
+        let event = LogEvent { id: recent.next_id, kind, entry, row };
+        recent.next_id += 1;
+
+        if recent.events.len() == self.capacity {
+            recent.events.pop_front();
+        }
+        recent.events.push_back(event.clone());
+
+        let id = event.id;
+        // No receivers is not an error, nobody is listening yet.
+        let _ = self.sender.send(event);
+        id
+    }
+
+    /// Subscribes to live events and returns the buffered events newer than `last_id`.
+    pub fn subscribe_since(&self, last_id: Option<u64>) -> (Vec<LogEvent>, broadcast::Receiver<LogEvent>) {
+        let recent = self.recent.lock().unwrap();
+        let receiver = self.sender.subscribe();
+        let backlog = match last_id {
+            Some(last_id) => recent.events.iter().filter(|e| e.id > last_id).cloned().collect(),
+            None => Vec::new(),
+        };
+        (backlog, receiver)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::nginx::NginxParser;
+    use crate::parsers::LogParser;
+
+    fn entry(line: &str) -> LogEntry {
+        NginxParser::new().parse(line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_live_subscriber_receives_published_entries() {
+        let hub = LogHub::new(8);
+        let (backlog, mut rx) = hub.subscribe_since(None);
+        assert!(backlog.is_empty());
+
+        let id = hub.publish(EventKind::Log, entry(r#"10.0.0.1 - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/1.1" 200 512"#), None);
+        let event = rx.recv().await.unwrap();
+        assert_eq!(event.id, id);
+        assert_eq!(event.entry.ip_address, Some("10.0.0.1".to_string()));
+    }
+
+    #[test]
+    fn test_resume_replays_events_after_last_id() {
+        let hub = LogHub::new(8);
+        for i in 1..=5 {
+            hub.publish(EventKind::Log, entry(&format!(r#"10.0.0.{i} - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/1.1" 200 512"#)), None);
+        }
+
+        let (backlog, _rx) = hub.subscribe_since(Some(3));
+        let ids: Vec<u64> = backlog.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![4, 5]);
+    }
+
+    #[test]
+    fn test_replay_buffer_is_bounded() {
+        let hub = LogHub::new(2);
+        for _ in 0..5 {
+            hub.publish(EventKind::Log, entry(r#"10.0.0.1 - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/1.1" 200 512"#), None);
+        }
+
+        let (backlog, _rx) = hub.subscribe_since(Some(0));
+        let ids: Vec<u64> = backlog.iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![4, 5]);
+    }
+}
@@ -0,0 +1,74 @@
+use std::env;
+use std::fs;
+
+use crate::models::log::LogEntry;
+
+/// Which machine, agent and deployment entries are ingested by, so logs from a
+/// fleet can be told apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentIdentity {
+    pub hostname: String,
+    pub agent_id: String,
+    pub environment: Option<String>,
+}
+
+impl AgentIdentity {
+    /// Reads `AGENT_HOSTNAME` (default: the system hostname), `AGENT_ID`
+    /// (default: the hostname) and `AGENT_ENVIRONMENT`, e.g. `production`.
+    pub fn from_env() -> Self {
+        let var = |name: &str| env::var(name).ok().map(|value| value.trim().to_string()).filter(|value| !value.is_empty());
+        let hostname = var("AGENT_HOSTNAME").unwrap_or_else(system_hostname);
+        Self {
+            agent_id: var("AGENT_ID").unwrap_or_else(|| hostname.clone()),
+            hostname,
+            environment: var("AGENT_ENVIRONMENT"),
+        }
+    }
+
+    /// Labels `entry` with this agent. Entries received from other machines,
+    /// e.g. over syslog, keep the host that sent them.
+    pub fn stamp(&self, entry: &mut LogEntry) {
+        entry.host.get_or_insert_with(|| self.hostname.clone());
+        entry.agent_id.get_or_insert_with(|| self.agent_id.clone());
+        if entry.environment.is_none() {
+            entry.environment = self.environment.clone();
+        }
+    }
+}
+
+fn system_hostname() -> String {
+    fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| env::var("HOSTNAME").ok())
+        .map(|hostname| hostname.trim().to_string())
+        .filter(|hostname| !hostname.is_empty())
+        .unwrap_or_else(|| "localhost".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::log::LogSource;
+    use chrono::Utc;
+
+    #[test]
+    fn test_stamp_keeps_the_sending_host() {
+        let identity = AgentIdentity {
+            hostname: "collector".to_string(),
+            agent_id: "agent-1".to_string(),
+            environment: Some("production".to_string()),
+        };
+
+        let mut local = LogEntry::new(LogSource::AuthLog, Utc::now(), "");
+        identity.stamp(&mut local);
+        assert_eq!(local.host.as_deref(), Some("collector"));
+        assert_eq!(local.agent_id.as_deref(), Some("agent-1"));
+        assert_eq!(local.environment.as_deref(), Some("production"));
+
+        let mut received = LogEntry::new(LogSource::AuthLog, Utc::now(), "");
+        received.host = Some("web1".to_string());
+        identity.stamp(&mut received);
+        assert_eq!(received.host.as_deref(), Some("web1"));
+        assert_eq!(received.agent_id.as_deref(), Some("agent-1"));
+    }
+}
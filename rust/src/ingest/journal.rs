@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chrono::DateTime;
+use serde_json::Value;
+use tracing::{info, warn};
+
+use crate::ingest::checkpoint::{CheckpointStore, Ingested, PendingCheckpoint};
+use crate::ingest::syslog::{SyslogMessage, SyslogRouter};
+use crate::models::log::LogEntry;
+
+/// How often the cursor is handed on while records keep coming.
+const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+/// Largest binary field accepted in the export format.
+const MAX_FIELD: u64 = 16 * 1024 * 1024;
+
+/// Where journal records are read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum JournalInput {
+    Stdin,
+    File(PathBuf),
+}
+
+impl JournalInput {
+    /// Reads `JOURNAL_INPUT`, `-` for stdin or the path of a file written by
+    /// `journalctl -o export` or `-o json`. `None` when unset.
+    pub fn from_env() -> Option<Self> {
+        match env::var("JOURNAL_INPUT") {
+            Ok(input) if input == "-" => Some(JournalInput::Stdin),
+            Ok(input) if !input.trim().is_empty() => Some(JournalInput::File(input.into())),
+            _ => None,
+        }
+    }
+
+    /// Name the cursor is checkpointed under.
+    fn key(&self) -> String {
+        match self {
+            JournalInput::Stdin => "stdin".to_string(),
+            JournalInput::File(path) => path.to_string_lossy().into_owned(),
+        }
+    }
+}
+
+/// One journal record, its fields by name. Repeated fields keep their first value
+/// and binary values are read as lossy UTF-8.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JournalRecord(HashMap<String, String>);
+
+impl JournalRecord {
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.0.get(field).map(String::as_str)
+    }
+
+    fn insert(&mut self, field: String, value: String) {
+        self.0.entry(field).or_insert(value);
+    }
+
+    /// The record as a syslog message, so it is routed like one received over the network.
+    pub fn to_message(&self) -> Option<SyslogMessage> {
+        // The time the program logged, when known, rather than when journald received it.
+        let timestamp = ["_SOURCE_REALTIME_TIMESTAMP", "__REALTIME_TIMESTAMP"]
+            .iter()
+            .find_map(|field| self.get(field)?.parse().ok())
+            .and_then(DateTime::from_timestamp_micros);
+        let app_name = self
+            .get("SYSLOG_IDENTIFIER")
+            .or_else(|| self.get("_SYSTEMD_UNIT").map(|unit| unit.trim_end_matches(".service")))
+            .or_else(|| self.get("_COMM"));
+
+        Some(SyslogMessage {
+            facility: self.get("SYSLOG_FACILITY").and_then(|facility| facility.parse().ok()).unwrap_or(1),
+            severity: self.get("PRIORITY").and_then(|priority| priority.parse().ok()).unwrap_or(6),
+            timestamp,
+            hostname: self.get("_HOSTNAME").map(str::to_string),
+            app_name: app_name.map(str::to_string),
+            proc_id: self.get("_PID").or_else(|| self.get("SYSLOG_PID")).map(str::to_string),
+            message: self.get("MESSAGE")?.to_string(),
+        })
+    }
+}
+
+/// Reads records in the journal export format or as JSON lines, which can be mixed.
+pub struct JournalRecords<R> {
+    reader: R,
+}
+
+impl<R: BufRead> JournalRecords<R> {
+    pub fn new(reader: R) -> Self {
+        Self { reader }
+    }
+
+    /// The next record, `None` at the end of the input.
+    pub fn next_record(&mut self) -> io::Result<Option<JournalRecord>> {
+        loop {
+            // Records are separated by blank lines in the export format.
+            let first = loop {
+                match self.reader.fill_buf()?.first() {
+                    None => return Ok(None),
+                    Some(b'\n' | b'\r' | b' ' | b'\t') => self.reader.consume(1),
+                    Some(&first) => break first,
+                }
+            };
+
+            if first != b'{' {
+                return self.read_export().map(Some);
+            }
+            let mut line = Vec::new();
+            self.reader.read_until(b'\n', &mut line)?;
+            match serde_json::from_slice::<serde_json::Map<String, Value>>(&line) {
+                Ok(fields) => return Ok(Some(json_record(fields))),
+                Err(e) => warn!("Skipping invalid journal JSON: {}", e),
+            }
+        }
+    }
+
+    /// `FIELD=value` lines up to a blank line. Values that are binary or contain
+    /// newlines are written as `FIELD`, a 64-bit little-endian length, the data and a newline.
+    fn read_export(&mut self) -> io::Result<JournalRecord> {
+        let mut record = JournalRecord::default();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if self.reader.read_until(b'\n', &mut line)? == 0 || line == b"\n" {
+                return Ok(record);
+            }
+            let line = line.strip_suffix(b"\n").unwrap_or(&line);
+
+            if let Some(eq) = line.iter().position(|b| *b == b'=') {
+                record.insert(lossy(&line[..eq]), lossy(&line[eq + 1..]));
+                continue;
+            }
+
+            let mut len = [0; 8];
+            self.reader.read_exact(&mut len)?;
+            let len = u64::from_le_bytes(len);
+            if len > MAX_FIELD {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("journal field of {} bytes", len)));
+            }
+            let mut data = Vec::with_capacity(len as usize);
+            (&mut self.reader).take(len).read_to_end(&mut data)?;
+            let mut newline = [0; 1];
+            self.reader.read_exact(&mut newline)?;
+            if data.len() as u64 != len || newline != *b"\n" {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "truncated binary journal field"));
+            }
+            record.insert(lossy(line), lossy(&data));
+        }
+    }
+}
+
+/// `journalctl -o json` writes binary values as byte arrays, repeated fields as
+/// arrays of values and oversized ones as `null`.
+fn json_record(fields: serde_json::Map<String, Value>) -> JournalRecord {
+    fn text(value: Value) -> Option<String> {
+        match value {
+            Value::String(value) => Some(value),
+            Value::Number(value) => Some(value.to_string()),
+            Value::Array(values) if values.iter().all(Value::is_u64) => {
+                let bytes: Vec<u8> = values.iter().filter_map(Value::as_u64).map(|byte| byte as u8).collect();
+                Some(lossy(&bytes))
+            }
+            Value::Array(values) => values.into_iter().find_map(text),
+            _ => None,
+        }
+    }
+
+    let mut record = JournalRecord::default();
+    for (field, value) in fields {
+        if let Some(value) = text(value) {
+            record.insert(field, value);
+        }
+    }
+    record
+}
+
+fn lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+/// Position of a record, from the sequence number ID (`s`), sequence number (`i`)
+/// and realtime clock (`t`) parts of its `__CURSOR`.
+#[derive(Debug, Clone, PartialEq)]
+struct CursorPosition {
+    seqnum_id: String,
+    seqnum: u64,
+    realtime: u64,
+}
+
+impl CursorPosition {
+    fn parse(cursor: &str) -> Option<Self> {
+        let mut position = CursorPosition { seqnum_id: String::new(), seqnum: 0, realtime: 0 };
+        for part in cursor.split(';') {
+            match part.split_once('=')? {
+                ("s", id) => position.seqnum_id = id.to_string(),
+                ("i", seqnum) => position.seqnum = u64::from_str_radix(seqnum, 16).ok()?,
+                ("t", realtime) => position.realtime = u64::from_str_radix(realtime, 16).ok()?,
+                _ => {}
+            }
+        }
+        (!position.seqnum_id.is_empty()).then_some(position)
+    }
+
+    /// Sequence numbers order the records of one journal, records from different
+    /// ones can only be ordered by their clock.
+    fn is_after(&self, other: &CursorPosition) -> bool {
+        if self.seqnum_id == other.seqnum_id {
+            self.seqnum > other.seqnum
+        } else {
+            self.realtime > other.realtime
+        }
+    }
+}
+
+/// Reads journal records, routes their `MESSAGE` to the parser for their program
+/// and hands on the cursor of the last record read, to be saved once the
+/// entries before it are.
+///
+/// After a restart, records up to the saved cursor are skipped, so an export can
+/// be re-read or piped from `journalctl -f -o export` without duplicates.
+pub struct JournalReader {
+    input: JournalInput,
+    router: Arc<SyslogRouter>,
+    checkpoints: Arc<CheckpointStore>,
+}
+
+impl JournalReader {
+    pub fn new(input: JournalInput, router: Arc<SyslogRouter>, checkpoints: Arc<CheckpointStore>) -> Self {
+        Self { input, router, checkpoints }
+    }
+
+    /// Reads the input to its end on the current thread, handing every parsed entry and checkpoint to `sink`.
+    pub fn run(self, mut sink: impl FnMut(Ingested)) {
+        info!("Reading the journal from {:?}", self.input);
+        let result = match &self.input {
+            JournalInput::Stdin => self.read(io::stdin().lock(), &mut sink),
+            JournalInput::File(path) => File::open(path).and_then(|file| self.read(BufReader::new(file), &mut sink)),
+        };
+        match result {
+            Ok(()) => info!("Finished reading the journal from {:?}", self.input),
+            Err(e) => warn!("Failed to read the journal from {:?}: {}", self.input, e),
+        }
+    }
+
+    pub fn read(&self, reader: impl BufRead, sink: &mut impl FnMut(Ingested)) -> io::Result<()> {
+        let key = self.input.key();
+        let saved = self.checkpoints.cursor(&key).and_then(|cursor| CursorPosition::parse(&cursor));
+        let mut records = JournalRecords::new(reader);
+        let mut cursor: Option<String> = None;
+        let mut last_save = Instant::now();
+
+        let result = loop {
+            let record = match records.next_record() {
+                Ok(Some(record)) => record,
+                Ok(None) => break Ok(()),
+                Err(e) => break Err(e),
+            };
+
+            let position = record.get("__CURSOR").and_then(CursorPosition::parse);
+            if let (Some(saved), Some(position)) = (&saved, &position) {
+                if !position.is_after(saved) {
+                    continue;
+                }
+            }
+            if let Some(entry) = self.to_entry(&record) {
+                sink(Ingested::Entry(entry));
+            }
+
+            if let Some(current) = record.get("__CURSOR") {
+                cursor = Some(current.to_string());
+            }
+            if let Some(cursor) = cursor.as_deref().filter(|_| last_save.elapsed() >= SAVE_INTERVAL) {
+                sink(Ingested::Checkpoint(PendingCheckpoint::cursor(self.checkpoints.clone(), &key, cursor)));
+                last_save = Instant::now();
+            }
+        };
+
+        // Keep the progress made before an error too.
+        if let Some(cursor) = cursor {
+            sink(Ingested::Checkpoint(PendingCheckpoint::cursor(self.checkpoints.clone(), &key, &cursor)));
+        }
+        result
+    }
+
+    fn to_entry(&self, record: &JournalRecord) -> Option<LogEntry> {
+        let mut entry = self.router.route_message(record.to_message()?)?;
+        if let Some(unit) = record.get("_SYSTEMD_UNIT") {
+            entry.extra.insert("unit".to_string(), unit.to_string());
+        }
+        Some(entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::registry::ParserRegistry;
+    use crate::parsers::timestamp::SourceTimezone;
+
+    fn export_record(cursor_seqnum: u64, message: &str) -> Vec<u8> {
+        format!(
+            "__CURSOR=s=0b1d;i={:x};b=77;m=1;t=6135f6a5c1b00;x=2\n\
+             __REALTIME_TIMESTAMP=1710000000000000\n\
+             _HOSTNAME=web1\n\
+             _SYSTEMD_UNIT=ssh.service\n\
+             SYSLOG_IDENTIFIER=sshd\n\
+             _PID=1042\n\
+             MESSAGE={}\n\n",
+            cursor_seqnum, message
+        )
+        .into_bytes()
+    }
+
+    /// Reads `input` to its end, saving checkpoints as soon as they are handed on.
+    fn read_all(checkpoints: Arc<CheckpointStore>, input: &[u8]) -> Vec<LogEntry> {
+        let mut entries = Vec::new();
+        reader(checkpoints)
+            .read(input, &mut |ingested| match ingested {
+                Ingested::Entry(entry) => entries.push(entry),
+                Ingested::Checkpoint(checkpoint) => checkpoint.save().unwrap(),
+            })
+            .unwrap();
+        entries
+    }
+
+    fn reader(checkpoints: Arc<CheckpointStore>) -> JournalReader {
+        let routes = HashMap::from([("sshd".to_string(), "auth".to_string())]);
+        let router = SyslogRouter::new(&ParserRegistry::default(), &routes, SourceTimezone::Local).unwrap();
+        JournalReader::new(JournalInput::Stdin, Arc::new(router), checkpoints)
+    }
+
+    #[test]
+    fn test_reads_export_format_with_binary_fields() {
+        let mut input = b"MESSAGE\n".to_vec();
+        input.extend_from_slice(&11u64.to_le_bytes());
+        input.extend_from_slice(b"line1\nline2\n_PID=7\n\nMESSAGE=second\n");
+
+        let mut records = JournalRecords::new(&input[..]);
+        let first = records.next_record().unwrap().unwrap();
+        assert_eq!(first.get("MESSAGE"), Some("line1\nline2"));
+        assert_eq!(first.get("_PID"), Some("7"));
+        assert_eq!(records.next_record().unwrap().unwrap().get("MESSAGE"), Some("second"));
+        assert_eq!(records.next_record().unwrap(), None);
+    }
+
+    #[test]
+    fn test_reads_json_lines() {
+        let input = br#"{"MESSAGE":[104,105],"_PID":"7","_HOSTNAME":["a","b"],"__REALTIME_TIMESTAMP":"1710000000000000"}
+{not json}
+{"MESSAGE":"second"}
+"#;
+        let mut records = JournalRecords::new(&input[..]);
+        let first = records.next_record().unwrap().unwrap();
+        assert_eq!(first.get("MESSAGE"), Some("hi"));
+        assert_eq!(first.get("_HOSTNAME"), Some("a"));
+        assert_eq!(first.to_message().unwrap().timestamp.unwrap().timestamp(), 1710000000);
+        assert_eq!(records.next_record().unwrap().unwrap().get("MESSAGE"), Some("second"));
+        assert_eq!(records.next_record().unwrap(), None);
+    }
+
+    #[test]
+    fn test_sshd_messages_become_entries_and_resume_after_the_cursor() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoints = Arc::new(CheckpointStore::load(dir.path().join("checkpoints.json")));
+        let mut input = export_record(1, "Failed password for root from 10.0.0.7 port 52144 ssh2");
+        input.extend(export_record(2, "Accepted publickey for alice from 10.0.0.8 port 40000 ssh2"));
+
+        let entries = read_all(checkpoints.clone(), &input);
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].auth_action.as_deref(), Some("Failed"));
+        assert_eq!(entries[0].ip_address.as_deref(), Some("10.0.0.7"));
+        assert_eq!(entries[0].timestamp.timestamp(), 1710000000);
+        assert_eq!(entries[0].host.as_deref(), Some("web1"));
+        assert_eq!(entries[0].extra.get("unit").map(String::as_str), Some("ssh.service"));
+        assert_eq!(entries[0].extra.get("pid").map(String::as_str), Some("1042"));
+        assert_eq!(checkpoints.cursor("stdin").as_deref(), Some("s=0b1d;i=2;b=77;m=1;t=6135f6a5c1b00;x=2"));
+
+        // Re-reading the same export with one new record only yields the new one.
+        input.extend(export_record(3, "Invalid user admin from 10.0.0.9 port 2222"));
+        let entries = read_all(checkpoints, &input);
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].auth_action.as_deref(), Some("Invalid user"));
+    }
+
+    #[test]
+    fn test_cursor_order() {
+        let a = CursorPosition::parse("s=aa;i=10;b=1;m=1;t=100;x=1").unwrap();
+        let b = CursorPosition::parse("s=aa;i=11;b=1;m=1;t=90;x=1").unwrap();
+        let c = CursorPosition::parse("s=bb;i=1;b=1;m=1;t=101;x=1").unwrap();
+        assert!(b.is_after(&a));
+        assert!(!a.is_after(&b));
+        assert!(c.is_after(&a));
+        assert!(CursorPosition::parse("garbage").is_none());
+    }
+}
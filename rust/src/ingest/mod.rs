@@ -0,0 +1,8 @@
+pub mod hub;
+pub mod identity;
+pub mod checkpoint;
+pub mod tail;
+pub mod classify;
+pub mod pipeline;
+pub mod syslog;
+pub mod journal;
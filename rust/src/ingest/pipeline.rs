@@ -0,0 +1,218 @@
+use std::future::Future;
+use std::sync::Arc;
+
+use db::writer::LogWriter;
+use tokio::sync::mpsc;
+use tracing::{error, info, warn};
+
+use crate::detect::Detector;
+use crate::ingest::checkpoint::Ingested;
+use crate::ingest::classify::classify;
+use crate::ingest::hub::LogHub;
+use crate::ingest::identity::AgentIdentity;
+
+/// Labels parsed entries with the agent's identity, classifies them, runs the
+/// detections and publishes entries and detections to stream clients and the
+/// batching database writer. Source checkpoints are saved once the writer has
+/// committed every row before them.
+pub struct Pipeline {
+    hub: Arc<LogHub>,
+    writer: LogWriter,
+    identity: AgentIdentity,
+    detector: Arc<Detector>,
+}
+
+impl Pipeline {
+    pub fn new(hub: Arc<LogHub>, writer: LogWriter, identity: AgentIdentity, detector: Arc<Detector>) -> Self {
+        Self { hub, writer, identity, detector }
+    }
+
+    /// Consumes `entries` until every sender is dropped or `shutdown`
+    /// completes, after which what is already queued is finished. While the
+    /// writer's queue is full this waits, which in turn blocks the tailers.
+    /// The writer is dropped on return, which makes it write what it buffered.
+    pub async fn run(self, mut entries: mpsc::Receiver<Ingested>, shutdown: impl Future<Output = ()>) {
+        tokio::pin!(shutdown);
+        let mut stopping = false;
+        'entries: loop {
+            let ingested = tokio::select! {
+                ingested = entries.recv() => match ingested {
+                    Some(ingested) => ingested,
+                    None => break,
+                },
+                _ = &mut shutdown, if !stopping => {
+                    stopping = true;
+                    entries.close();
+                    continue;
+                }
+            };
+            let mut entry = match ingested {
+                Ingested::Entry(entry) => entry,
+                Ingested::Checkpoint(checkpoint) => {
+                    let saved = self.writer.on_commit(move || {
+                        if let Err(e) = checkpoint.save() {
+                            warn!("Failed to save checkpoint: {}", e);
+                        }
+                    });
+                    if let Err(e) = saved.await {
+                        error!("Stopping ingestion: {}", e);
+                        break;
+                    }
+                    continue;
+                }
+            };
+            self.identity.stamp(&mut entry);
+            let mut row = classify(&entry);
+            let detections = self.detector.observe(&entry, &mut row);
+
+            self.hub.publish_observed(entry, row.clone(), &detections);
+            for row in std::iter::once(row).chain(detections) {
+                if let Err(e) = self.writer.write(row).await {
+                    error!("Stopping ingestion: {}", e);
+                    break 'entries;
+                }
+            }
+        }
+
+        info!("Ingestion pipeline stopped");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Utc;
+    use db::database::Database;
+    use db::mock::database::MockDB;
+    use db::query::LogQuery;
+    use db::schema::{ActionTaken, EventType, ThreatLevel};
+    use db::writer::WriterConfig;
+    use tokio::task::JoinHandle;
+
+    use crate::detect::brute_force::BruteForce;
+    use crate::detect::clock::SystemClock;
+    use crate::detect::signatures::Signatures;
+    use crate::ingest::checkpoint::{CheckpointStore, PendingCheckpoint};
+    use crate::ingest::hub::EventKind;
+    use crate::models::failed_login::Threshold;
+    use crate::models::log::{LogEntry, LogSource};
+
+    /// A pipeline for `web1` publishing to `hub`, with the mock database its
+    /// writer stores into and the writer's task.
+    fn pipeline(hub: Arc<LogHub>, detector: Detector) -> (Pipeline, Arc<MockDB>, JoinHandle<()>) {
+        let db = Arc::new(MockDB::new());
+        let (writer, written) = LogWriter::spawn(db.clone(), WriterConfig::default());
+        let identity = AgentIdentity { hostname: "web1".to_string(), agent_id: "web1".to_string(), environment: None };
+        (Pipeline::new(hub, writer, identity, Arc::new(detector)), db, written)
+    }
+
+    /// Flags three failed logins from one address within ten seconds.
+    fn brute_force() -> Detector {
+        let brute_force = BruteForce::new(Threshold::parse_list("3/10s").unwrap(), 100, Arc::new(SystemClock));
+        Detector::new(Signatures::default(), vec![Box::new(brute_force)])
+    }
+
+    /// Sends three failed logins from `203.0.113.9`, then closes the channel.
+    async fn failed_logins() -> mpsc::Receiver<Ingested> {
+        let (sender, receiver) = mpsc::channel(8);
+        for _ in 0..3 {
+            let mut entry = LogEntry::new(LogSource::AuthLog, Utc::now(), "Failed password for root");
+            entry.ip_address = Some("203.0.113.9".to_string());
+            entry.success = Some(false);
+            sender.send(Ingested::Entry(entry)).await.unwrap();
+        }
+        receiver
+    }
+
+    #[tokio::test]
+    async fn test_brute_force_detections_are_stored() {
+        let (pipeline, db, written) = pipeline(Arc::new(LogHub::new(8)), brute_force());
+        pipeline.run(failed_logins().await, std::future::pending()).await;
+        written.await.unwrap();
+
+        assert_eq!(db.fetch_logs(None).await.unwrap().len(), 4);
+        let query = LogQuery { event_type: Some(EventType::SshBruteForce), ..Default::default() };
+        let detections = db.query_logs(&query).await.unwrap().logs;
+        assert_eq!(detections.len(), 1);
+        assert_eq!(detections[0].threat_level, ThreatLevel::High);
+        assert_eq!(detections[0].action_taken, ActionTaken::Alerted);
+        assert_eq!(detections[0].host, "web1");
+    }
+
+    #[tokio::test]
+    async fn test_detections_are_streamed_as_alerts_once() {
+        let hub = Arc::new(LogHub::new(8));
+        let (pipeline, _, written) = pipeline(hub.clone(), brute_force());
+        pipeline.run(failed_logins().await, std::future::pending()).await;
+        written.await.unwrap();
+
+        // The third login crosses the threshold: one log event, one alert.
+        let (events, _) = hub.subscribe_since(Some(0));
+        let kinds: Vec<EventKind> = events.iter().map(|event| event.kind).collect();
+        assert_eq!(kinds, [EventKind::Log, EventKind::Log, EventKind::Log, EventKind::Alert]);
+        let alert = events[3].row.as_ref().unwrap();
+        assert_eq!(alert.event_type, EventType::SshBruteForce);
+        assert_eq!(events[3].entry.raw, "Failed password for root");
+    }
+
+    #[tokio::test]
+    async fn test_requests_matching_signatures_are_stored_raised() {
+        let detector = Detector::new(Signatures::builtin(), Vec::new());
+        let (pipeline, db, written) = pipeline(Arc::new(LogHub::new(8)), detector);
+
+        let (sender, receiver) = mpsc::channel(8);
+        for request in ["GET /.git/config HTTP/1.1", "GET /index.html HTTP/1.1"] {
+            let mut entry = LogEntry::new(LogSource::NginxAccess, Utc::now(), request);
+            entry.ip_address = Some("198.51.100.4".to_string());
+            entry.request = Some(request.to_string());
+            entry.status_code = Some(404);
+            sender.send(Ingested::Entry(entry)).await.unwrap();
+        }
+        drop(sender);
+        pipeline.run(receiver, std::future::pending()).await;
+        written.await.unwrap();
+
+        let query = LogQuery { event_type: Some(EventType::ScannerProbe), ..Default::default() };
+        let probes = db.query_logs(&query).await.unwrap().logs;
+        assert_eq!(probes.len(), 1);
+        assert_eq!(probes[0].targeted_endpoint, "/.git/config");
+        assert_eq!(probes[0].threat_level, ThreatLevel::Medium);
+        assert_eq!(probes[0].status, 404);
+    }
+
+    #[tokio::test]
+    async fn test_checkpoints_are_saved_after_the_rows_before_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoints = Arc::new(CheckpointStore::load(dir.path().join("checkpoints.json")));
+        let (pipeline, db, written) = pipeline(Arc::new(LogHub::new(8)), Detector::default());
+
+        let (sender, receiver) = mpsc::channel(8);
+        let entry = LogEntry::new(LogSource::AuthLog, Utc::now(), "Accepted password for root");
+        sender.send(Ingested::Entry(entry)).await.unwrap();
+        sender.send(Ingested::Checkpoint(PendingCheckpoint::cursor(checkpoints.clone(), "stdin", "s=1;i=1"))).await.unwrap();
+        drop(sender);
+        pipeline.run(receiver, std::future::pending()).await;
+        written.await.unwrap();
+
+        assert_eq!(db.fetch_logs(None).await.unwrap().len(), 1);
+        assert_eq!(checkpoints.cursor("stdin").as_deref(), Some("s=1;i=1"));
+    }
+
+    #[tokio::test]
+    async fn test_queued_entries_are_written_on_shutdown() {
+        let (pipeline, db, written) = pipeline(Arc::new(LogHub::new(8)), Detector::default());
+
+        // The sender stays open, as the tailers' do.
+        let (sender, receiver) = mpsc::channel(8);
+        for _ in 0..3 {
+            let entry = LogEntry::new(LogSource::AuthLog, Utc::now(), "Accepted password for root");
+            sender.send(Ingested::Entry(entry)).await.unwrap();
+        }
+        pipeline.run(receiver, async {}).await;
+        written.await.unwrap();
+
+        assert_eq!(db.fetch_logs(None).await.unwrap().len(), 3);
+        let late = LogEntry::new(LogSource::AuthLog, Utc::now(), "late");
+        assert!(sender.send(Ingested::Entry(late)).await.is_err());
+    }
+}
@@ -0,0 +1,606 @@
+use std::collections::HashMap;
+use std::env;
+use std::io;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, LazyLock};
+use std::time::Duration;
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use regex::Regex;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::sync::{mpsc, Semaphore};
+use tokio::time::timeout;
+use tokio_native_tls::native_tls::{self, Identity};
+use tokio_native_tls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use crate::ingest::checkpoint::Ingested;
+use crate::models::log::LogEntry;
+use crate::parsers::registry::ParserRegistry;
+use crate::parsers::timestamp::{parse_syslog_timestamp, SourceTimezone};
+use crate::parsers::LogParser;
+
+/// Largest message accepted. Longer TCP frames close the connection.
+const MAX_MESSAGE: usize = 64 * 1024;
+/// TCP and TLS connections served at once. Further ones wait to be accepted.
+const MAX_CONNECTIONS: usize = 1024;
+/// Connections that send nothing for this long, or do not finish the TLS
+/// handshake in it, are closed so they cannot hold a slot forever.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Parser for messages from each app-name, unless `SYSLOG_ROUTES` says otherwise.
+const DEFAULT_ROUTES: [(&str, &str); 5] = [
+    ("sshd", "auth"),
+    ("sshd-session", "auth"),
+    ("nginx", "nginx"),
+    ("apache2", "apache"),
+    ("httpd", "apache"),
+];
+
+/// Where the syslog receiver listens.
+#[derive(Debug, Clone)]
+pub struct SyslogConfig {
+    pub udp: Option<SocketAddr>,
+    pub tcp: Option<SocketAddr>,
+    pub tls: Option<TlsConfig>,
+}
+
+/// A TLS listener with its certificate chain and PKCS#8 private key, both PEM.
+#[derive(Debug, Clone)]
+pub struct TlsConfig {
+    pub addr: SocketAddr,
+    pub cert: PathBuf,
+    pub key: PathBuf,
+}
+
+impl SyslogConfig {
+    /// Reads `SYSLOG_UDP_ADDR`, `SYSLOG_TCP_ADDR` and `SYSLOG_TLS_ADDR` (with
+    /// `SYSLOG_TLS_CERT` and `SYSLOG_TLS_KEY`), e.g. `0.0.0.0:514`. Returns
+    /// `None` when no listener is configured.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let addr = |name: &str| match env::var(name) {
+            Ok(addr) => addr.parse().map(Some).map_err(|e| format!("invalid {}: {}", name, e)),
+            Err(_) => Ok(None),
+        };
+
+        let udp = addr("SYSLOG_UDP_ADDR")?;
+        let tcp = addr("SYSLOG_TCP_ADDR")?;
+        let tls = match addr("SYSLOG_TLS_ADDR")? {
+            Some(addr) => {
+                let path = |name: &str| env::var(name).map(PathBuf::from).map_err(|_| format!("SYSLOG_TLS_ADDR needs {}", name));
+                Some(TlsConfig { addr, cert: path("SYSLOG_TLS_CERT")?, key: path("SYSLOG_TLS_KEY")? })
+            }
+            None => None,
+        };
+        if udp.is_none() && tcp.is_none() && tls.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self { udp, tcp, tls }))
+    }
+}
+
+/// A syslog message in either RFC 5424 or RFC 3164 (BSD) format.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyslogMessage {
+    pub facility: u8,
+    pub severity: u8,
+    pub timestamp: Option<DateTime<Utc>>,
+    pub hostname: Option<String>,
+    pub app_name: Option<String>,
+    pub proc_id: Option<String>,
+    pub message: String,
+}
+
+impl SyslogMessage {
+    /// Parses one message. RFC 3164 timestamps are read in `tz`, with the year
+    /// inferred from `reference`, normally the time the message arrived.
+    pub fn parse(raw: &str, tz: SourceTimezone, reference: DateTime<Utc>) -> Option<Self> {
+        let (pri, rest) = raw.strip_prefix('<')?.split_once('>')?;
+        if pri.is_empty() || pri.len() > 3 {
+            return None;
+        }
+        let pri: u8 = pri.parse().ok().filter(|pri| *pri <= 191)?;
+
+        let mut message = match rest.strip_prefix("1 ") {
+            Some(rest) => parse_5424(rest)?,
+            None => parse_3164(rest, tz, reference),
+        };
+        message.facility = pri >> 3;
+        message.severity = pri & 7;
+        Some(message)
+    }
+
+    /// The message as a line of a local syslog file such as `auth.log`, for
+    /// parsers that read those.
+    pub fn to_line(&self) -> String {
+        let timestamp = self.timestamp.unwrap_or_else(Utc::now).to_rfc3339_opts(SecondsFormat::AutoSi, true);
+        let hostname = self.hostname.as_deref().unwrap_or("-");
+        let app_name = self.app_name.as_deref().unwrap_or("-");
+        match &self.proc_id {
+            Some(pid) => format!("{} {} {}[{}]: {}", timestamp, hostname, app_name, pid, self.message),
+            None => format!("{} {} {}: {}", timestamp, hostname, app_name, self.message),
+        }
+    }
+}
+
+/// `TIMESTAMP HOSTNAME APP-NAME PROCID MSGID STRUCTURED-DATA [MSG]`, after the version.
+fn parse_5424(rest: &str) -> Option<SyslogMessage> {
+    let nil = |value: &str| (value != "-").then(|| value.to_string());
+
+    let mut fields = rest.splitn(6, ' ');
+    let timestamp = fields.next()?;
+    let hostname = fields.next()?;
+    let app_name = fields.next()?;
+    let proc_id = fields.next()?;
+    let _msg_id = fields.next()?;
+    let rest = skip_structured_data(fields.next()?)?;
+
+    let timestamp = match timestamp {
+        "-" => None,
+        timestamp => Some(DateTime::parse_from_rfc3339(timestamp).ok()?.to_utc()),
+    };
+    let message = rest.strip_prefix(' ').unwrap_or(rest);
+    Some(SyslogMessage {
+        facility: 0,
+        severity: 0,
+        timestamp,
+        hostname: nil(hostname),
+        app_name: nil(app_name),
+        proc_id: nil(proc_id),
+        message: message.trim_start_matches('\u{feff}').to_string(),
+    })
+}
+
+/// What follows the structured data, which is `-` or `[id key="value" ...]` elements.
+fn skip_structured_data(s: &str) -> Option<&str> {
+    if let Some(rest) = s.strip_prefix('-') {
+        return Some(rest);
+    }
+
+    let mut rest = s;
+    while rest.starts_with('[') {
+        let (mut quoted, mut escaped) = (false, false);
+        let end = rest.char_indices().find_map(|(i, c)| {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => quoted = !quoted,
+                ']' if !quoted => return Some(i),
+                _ => {}
+            }
+            None
+        })?;
+        rest = &rest[end + 1..];
+    }
+    (rest.len() < s.len()).then_some(rest)
+}
+
+/// `HOSTNAME TAG[PID]: MSG` after an RFC 3164 timestamp, the hostname being optional.
+static HEADER_3164: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"^(?:(?P<host>[^\s:\[]+) )?(?P<tag>[^\s:\[]+)(?:\[(?P<pid>[^\]]*)\])?: ?(?P<msg>.*)$").unwrap()
+});
+
+/// `TIMESTAMP HOSTNAME TAG[PID]: MSG`. Senders leave parts out, so whatever is
+/// missing is left empty and unparseable text becomes the message.
+fn parse_3164(rest: &str, tz: SourceTimezone, reference: DateTime<Utc>) -> SyslogMessage {
+    // `Mar  9 12:34:56` is fixed width, rsyslog can send RFC 3339 instead.
+    let (timestamp, rest) = match rest.get(..15).and_then(|classic| parse_syslog_timestamp(classic, tz, reference)) {
+        Some(timestamp) => (Some(timestamp), &rest[15..]),
+        None => match rest.split_once(' ') {
+            Some((first, after)) => match parse_syslog_timestamp(first, tz, reference) {
+                Some(timestamp) => (Some(timestamp), after),
+                None => (None, rest),
+            },
+            None => (None, rest),
+        },
+    };
+
+    let rest = rest.trim_start();
+    let mut message = SyslogMessage {
+        facility: 0,
+        severity: 0,
+        timestamp,
+        hostname: None,
+        app_name: None,
+        proc_id: None,
+        message: rest.to_string(),
+    };
+    if let Some(caps) = HEADER_3164.captures(rest) {
+        message.hostname = caps.name("host").map(|host| host.as_str().to_string());
+        message.app_name = Some(caps["tag"].to_string());
+        message.proc_id = caps.name("pid").map(|pid| pid.as_str().to_string());
+        message.message = caps["msg"].to_string();
+    }
+    message
+}
+
+/// Turns syslog messages into entries with the parser routed to by their app-name.
+pub struct SyslogRouter {
+    parsers: HashMap<String, Arc<dyn LogParser>>,
+    /// Timezone of RFC 3164 timestamps, which have no offset.
+    timezone: SourceTimezone,
+}
+
+impl SyslogRouter {
+    /// `routes` maps app-names (the RFC 3164 tag) to parser names.
+    pub fn new(registry: &ParserRegistry, routes: &HashMap<String, String>, timezone: SourceTimezone) -> Result<Self, String> {
+        let parsers = routes
+            .iter()
+            .map(|(app, name)| match registry.get(name) {
+                Some(parser) => Ok((app.clone(), parser)),
+                None => Err(format!("syslog route {}={} names an unknown parser", app, name)),
+            })
+            .collect::<Result<_, _>>()?;
+        Ok(Self { parsers, timezone })
+    }
+
+    /// The default routes, plus `SYSLOG_ROUTES` as `app=parser` pairs, e.g.
+    /// `sshd=auth,haproxy=nginx`. `LOG_TIMEZONE` sets the timezone.
+    pub fn from_env(registry: &ParserRegistry) -> Result<Self, String> {
+        let mut routes: HashMap<String, String> =
+            DEFAULT_ROUTES.iter().map(|(app, parser)| (app.to_string(), parser.to_string())).collect();
+        if let Ok(custom) = env::var("SYSLOG_ROUTES") {
+            for route in custom.split(',').filter(|route| !route.trim().is_empty()) {
+                let (app, parser) = route
+                    .split_once('=')
+                    .ok_or_else(|| format!("invalid syslog route '{}', expected app=parser", route))?;
+                routes.insert(app.trim().to_string(), parser.trim().to_string());
+            }
+        }
+
+        let timezone = match env::var("LOG_TIMEZONE") {
+            Ok(tz) => tz.parse()?,
+            Err(_) => SourceTimezone::default(),
+        };
+        Self::new(registry, &routes, timezone)
+    }
+
+    /// The entry for `raw`, `None` if it does not parse or no parser is routed to its app-name.
+    pub fn route(&self, raw: &str) -> Option<LogEntry> {
+        self.route_message(SyslogMessage::parse(raw, self.timezone, Utc::now())?)
+    }
+
+    pub fn route_message(&self, message: SyslogMessage) -> Option<LogEntry> {
+        let Some(parser) = message.app_name.as_deref().and_then(|app| self.parsers.get(app)) else {
+            debug!("No parser for syslog messages from {:?}", message.app_name);
+            return None;
+        };
+
+        // Servers like nginx send their own line format as the message, while
+        // formats like auth.log include the syslog header, so both are tried.
+        let reference = message.timestamp.unwrap_or_else(Utc::now);
+        let mut entry = parser
+            .parse_with_reference(&message.message, reference)
+            .or_else(|| parser.parse_with_reference(&message.to_line(), reference))?;
+        entry.host = message.hostname;
+        if let Some(pid) = message.proc_id {
+            entry.extra.entry("pid".to_string()).or_insert(pid);
+        }
+        Some(entry)
+    }
+}
+
+/// Listens for syslog messages and hands the entries to the ingest pipeline.
+pub struct SyslogReceiver {
+    router: SyslogRouter,
+    sender: mpsc::Sender<Ingested>,
+    /// One permit per open TCP or TLS connection.
+    connections: Arc<Semaphore>,
+    idle_timeout: Duration,
+}
+
+impl SyslogReceiver {
+    pub fn new(router: SyslogRouter, sender: mpsc::Sender<Ingested>) -> Arc<Self> {
+        Self::with_limits(router, sender, MAX_CONNECTIONS, IDLE_TIMEOUT)
+    }
+
+    pub fn with_limits(
+        router: SyslogRouter,
+        sender: mpsc::Sender<Ingested>,
+        max_connections: usize,
+        idle_timeout: Duration,
+    ) -> Arc<Self> {
+        Arc::new(Self { router, sender, connections: Arc::new(Semaphore::new(max_connections)), idle_timeout })
+    }
+
+    /// Binds every configured listener and serves them in the background.
+    pub async fn start(self: Arc<Self>, config: &SyslogConfig) -> io::Result<()> {
+        if let Some(addr) = config.udp {
+            let socket = UdpSocket::bind(addr).await?;
+            info!("Receiving syslog on udp://{}", addr);
+            tokio::spawn(self.clone().serve_udp(socket));
+        }
+        if let Some(addr) = config.tcp {
+            let listener = TcpListener::bind(addr).await?;
+            info!("Receiving syslog on tcp://{}", addr);
+            tokio::spawn(self.clone().serve_tcp(listener, None));
+        }
+        if let Some(tls) = &config.tls {
+            let acceptor = tls_acceptor(tls)?;
+            let listener = TcpListener::bind(tls.addr).await?;
+            info!("Receiving syslog on tls://{}", tls.addr);
+            tokio::spawn(self.clone().serve_tcp(listener, Some(acceptor)));
+        }
+        Ok(())
+    }
+
+    /// One message per datagram.
+    pub async fn serve_udp(self: Arc<Self>, socket: UdpSocket) {
+        let mut buf = vec![0; MAX_MESSAGE];
+        loop {
+            let len = match socket.recv_from(&mut buf).await {
+                Ok((len, _)) => len,
+                Err(e) => {
+                    warn!("Failed to receive syslog datagram: {}", e);
+                    continue;
+                }
+            };
+            if !self.deliver(trim_frame(&buf[..len])).await {
+                return;
+            }
+        }
+    }
+
+    pub async fn serve_tcp(self: Arc<Self>, listener: TcpListener, tls: Option<TlsAcceptor>) {
+        loop {
+            // Connections beyond the limit wait in the listen backlog.
+            let permit = self.connections.clone().acquire_owned().await.expect("semaphore is never closed");
+            let (stream, peer) = match listener.accept().await {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    warn!("Failed to accept syslog connection: {}", e);
+                    continue;
+                }
+            };
+
+            let receiver = self.clone();
+            let tls = tls.clone();
+            tokio::spawn(async move {
+                let result = match tls {
+                    Some(tls) => match timeout(receiver.idle_timeout, tls.accept(stream)).await {
+                        Ok(Ok(stream)) => receiver.read_stream(stream).await,
+                        Ok(Err(e)) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+                        Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, "TLS handshake timed out")),
+                    },
+                    None => receiver.read_stream(stream).await,
+                };
+                if let Err(e) = result {
+                    debug!("Syslog connection from {} closed: {}", peer, e);
+                }
+                drop(permit);
+            });
+        }
+    }
+
+    async fn read_stream<S: AsyncRead + Unpin>(&self, stream: S) -> io::Result<()> {
+        let mut reader = BufReader::new(stream);
+        let mut frame = Vec::new();
+        loop {
+            let read = timeout(self.idle_timeout, read_frame(&mut reader, &mut frame))
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "idle for too long"))?;
+            if !read? {
+                break;
+            }
+            if !self.deliver(&frame).await {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses and queues one message. Returns `false` once the pipeline has stopped.
+    async fn deliver(&self, raw: &[u8]) -> bool {
+        let raw = String::from_utf8_lossy(raw);
+        match self.router.route(&raw) {
+            Some(entry) => self.sender.send(Ingested::Entry(entry)).await.is_ok(),
+            None => true,
+        }
+    }
+}
+
+/// Reads the next message of a TCP stream into `frame`, supporting both RFC 6587
+/// octet counting (`LEN SP MSG`) and newline-delimited framing. Returns `false` at
+/// the end of the stream.
+pub async fn read_frame<R: AsyncBufRead + Unpin>(reader: &mut R, frame: &mut Vec<u8>) -> io::Result<bool> {
+    frame.clear();
+    // Skip blank lines between messages.
+    let first = loop {
+        let buf = reader.fill_buf().await?;
+        match buf.first() {
+            None => return Ok(false),
+            Some(b'\n' | b'\r') => reader.consume(1),
+            Some(&first) => break first,
+        }
+    };
+
+    if first.is_ascii_digit() {
+        let mut len = Vec::new();
+        (&mut *reader).take(8).read_until(b' ', &mut len).await?;
+        let len = std::str::from_utf8(&len)
+            .ok()
+            .and_then(|len| len.strip_suffix(' '))
+            .and_then(|len| len.parse::<usize>().ok())
+            .filter(|len| *len <= MAX_MESSAGE)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid octet count"))?;
+        frame.resize(len, 0);
+        reader.read_exact(frame).await?;
+    } else {
+        (&mut *reader).take(MAX_MESSAGE as u64).read_until(b'\n', frame).await?;
+        if frame.len() == MAX_MESSAGE && !frame.ends_with(b"\n") {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+        }
+    }
+
+    let len = trim_frame(frame).len();
+    frame.truncate(len);
+    Ok(true)
+}
+
+/// `frame` without the trailing newline or NUL some senders add.
+fn trim_frame(frame: &[u8]) -> &[u8] {
+    let end = frame.iter().rposition(|b| !matches!(b, b'\n' | b'\r' | b'\0')).map_or(0, |i| i + 1);
+    &frame[..end]
+}
+
+fn tls_acceptor(config: &TlsConfig) -> io::Result<TlsAcceptor> {
+    let cert = std::fs::read(&config.cert)?;
+    let key = std::fs::read(&config.key)?;
+    let identity = Identity::from_pkcs8(&cert, &key).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let acceptor = native_tls::TlsAcceptor::new(identity).map_err(io::Error::other)?;
+    Ok(TlsAcceptor::from(acceptor))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono_tz::Tz;
+
+    const UTC: SourceTimezone = SourceTimezone::Named(Tz::UTC);
+
+    fn reference() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-03-10T00:00:00Z").unwrap().to_utc()
+    }
+
+    pub(crate) fn router() -> SyslogRouter {
+        let routes = DEFAULT_ROUTES.iter().map(|(app, parser)| (app.to_string(), parser.to_string())).collect();
+        SyslogRouter::new(&ParserRegistry::default(), &routes, UTC).unwrap()
+    }
+
+    #[test]
+    fn test_parse_rfc3164() {
+        let message = SyslogMessage::parse(
+            "<38>Mar  9 12:34:56 web1 sshd[1042]: Failed password for root from 10.0.0.7 port 52144 ssh2",
+            UTC,
+            reference(),
+        )
+        .unwrap();
+        assert_eq!((message.facility, message.severity), (4, 6));
+        assert_eq!(message.timestamp.unwrap().to_rfc3339(), "2024-03-09T12:34:56+00:00");
+        assert_eq!(message.hostname.as_deref(), Some("web1"));
+        assert_eq!(message.app_name.as_deref(), Some("sshd"));
+        assert_eq!(message.proc_id.as_deref(), Some("1042"));
+        assert_eq!(message.message, "Failed password for root from 10.0.0.7 port 52144 ssh2");
+
+        // Without hostname, as sent by some embedded devices.
+        let message = SyslogMessage::parse("<13>Mar  9 12:34:56 nginx: hello", UTC, reference()).unwrap();
+        assert_eq!(message.hostname, None);
+        assert_eq!(message.app_name.as_deref(), Some("nginx"));
+        assert_eq!(message.message, "hello");
+
+        assert!(SyslogMessage::parse("no priority", UTC, reference()).is_none());
+        assert!(SyslogMessage::parse("<999>Mar  9 12:34:56 x: y", UTC, reference()).is_none());
+    }
+
+    #[test]
+    fn test_parse_rfc5424() {
+        let raw = r#"<165>1 2024-03-09T12:34:56.003Z web1 nginx 77 ID47 [exampleSDID@32473 iut="3" eventID="1011" note="a]b"][x@1 y="z"] ﻿hello world"#;
+        let message = SyslogMessage::parse(raw, UTC, reference()).unwrap();
+        assert_eq!((message.facility, message.severity), (20, 5));
+        assert_eq!(message.timestamp.unwrap().to_rfc3339(), "2024-03-09T12:34:56.003+00:00");
+        assert_eq!(message.hostname.as_deref(), Some("web1"));
+        assert_eq!(message.app_name.as_deref(), Some("nginx"));
+        assert_eq!(message.proc_id.as_deref(), Some("77"));
+        assert_eq!(message.message, "hello world");
+
+        let message = SyslogMessage::parse("<14>1 - - - - - -", UTC, reference()).unwrap();
+        assert_eq!(message.timestamp, None);
+        assert_eq!(message.app_name, None);
+        assert_eq!(message.message, "");
+    }
+
+    #[test]
+    fn test_routes_by_app_name() {
+        let router = router();
+        let entry = router
+            .route("<38>Mar  9 12:34:56 web1 sshd[1042]: Failed password for root from 10.0.0.7 port 52144 ssh2")
+            .unwrap();
+        assert_eq!(entry.auth_action.as_deref(), Some("Failed"));
+        assert_eq!(entry.host.as_deref(), Some("web1"));
+        assert_eq!(entry.extra.get("pid"), Some(&"1042".to_string()));
+
+        let entry = router
+            .route(r#"<190>1 2024-03-09T12:34:56Z web2 nginx - - - 10.0.0.2 - - [09/Mar/2024:12:34:56 +0000] "GET / HTTP/1.1" 404 12 "-" "curl""#)
+            .unwrap();
+        assert_eq!(entry.status_code, Some(404));
+        assert_eq!(entry.host.as_deref(), Some("web2"));
+
+        assert!(router.route("<38>Mar  9 12:34:56 web1 cron[1]: job done").is_none());
+    }
+
+    #[test]
+    fn test_unknown_parser_in_routes_is_an_error() {
+        let routes = HashMap::from([("haproxy".to_string(), "haproxy".to_string())]);
+        assert!(SyslogRouter::new(&ParserRegistry::default(), &routes, UTC).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_reads_octet_counted_and_newline_frames() {
+        let stream: &[u8] = b"11 <13>1 - - -\n<13>hello\r\n\n5 <13>x";
+        let mut reader = BufReader::new(stream);
+        let mut frame = Vec::new();
+
+        assert!(read_frame(&mut reader, &mut frame).await.unwrap());
+        assert_eq!(frame, b"<13>1 - - -");
+        assert!(read_frame(&mut reader, &mut frame).await.unwrap());
+        assert_eq!(frame, b"<13>hello");
+        assert!(read_frame(&mut reader, &mut frame).await.unwrap());
+        assert_eq!(frame, b"<13>x");
+        assert!(!read_frame(&mut reader, &mut frame).await.unwrap());
+
+        let stream: &[u8] = b"99999999 <13>x";
+        assert!(read_frame(&mut BufReader::new(stream), &mut frame).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_udp_and_tcp_messages_reach_the_pipeline() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        let syslog = SyslogReceiver::new(router(), sender);
+
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let udp_addr = socket.local_addr().unwrap();
+        tokio::spawn(syslog.clone().serve_udp(socket));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let tcp_addr = listener.local_addr().unwrap();
+        tokio::spawn(syslog.serve_tcp(listener, None));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client
+            .send_to(b"<38>Mar  9 12:34:56 web1 sshd[1]: Invalid user admin from 10.0.0.9 port 2222\n", udp_addr)
+            .await
+            .unwrap();
+        let Some(Ingested::Entry(entry)) = receiver.recv().await else { panic!("expected an entry") };
+        assert_eq!(entry.auth_action.as_deref(), Some("Invalid user"));
+
+        let message = "<38>Mar  9 12:34:56 web1 sshd[1]: Accepted publickey for alice from 10.0.0.7 port 52144 ssh2";
+        let mut stream = tokio::net::TcpStream::connect(tcp_addr).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut stream, format!("{} {}", message.len(), message).as_bytes())
+            .await
+            .unwrap();
+        let Some(Ingested::Entry(entry)) = receiver.recv().await else { panic!("expected an entry") };
+        assert_eq!(entry.auth_action.as_deref(), Some("Accepted"));
+    }
+
+    #[tokio::test]
+    async fn test_idle_connections_are_closed_to_free_their_slot() {
+        let (sender, mut receiver) = mpsc::channel(10);
+        let syslog = SyslogReceiver::with_limits(router(), sender, 1, Duration::from_millis(200));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(syslog.serve_tcp(listener, None));
+
+        let mut idle = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let mut waiting = tokio::net::TcpStream::connect(addr).await.unwrap();
+        tokio::io::AsyncWriteExt::write_all(&mut waiting, b"<38>Mar  9 12:34:56 web1 sshd[1]: Invalid user admin from 10.0.0.9\n")
+            .await
+            .unwrap();
+
+        // The only slot is taken until the idle connection times out.
+        assert!(timeout(Duration::from_millis(100), receiver.recv()).await.is_err());
+        let Some(Ingested::Entry(entry)) = receiver.recv().await else { panic!("expected an entry") };
+        assert_eq!(entry.auth_action.as_deref(), Some("Invalid user"));
+        assert_eq!(idle.read(&mut [0; 1]).await.unwrap(), 0);
+    }
+}
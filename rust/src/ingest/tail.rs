@@ -0,0 +1,439 @@
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::os::unix::fs::{FileExt, MetadataExt};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use tracing::{debug, info, warn};
+
+use crate::ingest::checkpoint::{Checkpoint, CheckpointStore, Fingerprint, Ingested, PendingCheckpoint, FINGERPRINT_LEN};
+use crate::parsers::LogParser;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Rotated generations searched for lines written before a rotation, newest first.
+const ROTATED_SUFFIXES: [&str; 4] = [".1", ".1.gz", ".2", ".2.gz"];
+
+/// Where to start reading a file that has no checkpoint yet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TailStart {
+    Beginning,
+    End,
+}
+
+impl TailStart {
+    /// Reads `TAIL_FROM` (`beginning` or `end`), defaulting to `beginning`.
+    pub fn from_env() -> Self {
+        match env::var("TAIL_FROM").as_deref() {
+            Ok("end") => TailStart::End,
+            _ => TailStart::Beginning,
+        }
+    }
+}
+
+/// Location of the checkpoint file, configured through `CHECKPOINT_PATH`.
+pub fn checkpoint_path() -> PathBuf {
+    env::var("CHECKPOINT_PATH")
+        .unwrap_or_else(|_| "cephalog-checkpoints.json".to_string())
+        .into()
+}
+
+struct OpenFile {
+    reader: BufReader<File>,
+    inode: u64,
+    offset: u64,
+    /// Bytes of a line whose newline has not been written yet.
+    pending: Vec<u8>,
+}
+
+/// Follows a log file across appends, logrotate renames and copytruncate.
+///
+/// Progress is checkpointed per file as inode, offset and fingerprint, handed
+/// to the sink after the entries it covers so it is saved once they are. On
+/// startup, and whenever the file is truncated, lines left in the rotated
+/// `.1`/`.gz` generation after the checkpoint are read before the new file.
+pub struct Tailer {
+    path: PathBuf,
+    parser: Arc<dyn LogParser>,
+    checkpoints: Arc<CheckpointStore>,
+    start: TailStart,
+    file: Option<OpenFile>,
+    /// The last checkpoint handed to the sink, or the saved one before that.
+    saved: Option<Checkpoint>,
+}
+
+impl Tailer {
+    pub fn new(
+        path: impl Into<PathBuf>,
+        parser: Arc<dyn LogParser>,
+        checkpoints: Arc<CheckpointStore>,
+        start: TailStart,
+    ) -> Self {
+        let path = path.into();
+        let saved = checkpoints.get(&path);
+        Self { path, parser, checkpoints, start, file: None, saved }
+    }
+
+    /// Tails forever on the current thread, handing every parsed entry and checkpoint to `sink`.
+    pub fn run(mut self, mut sink: impl FnMut(Ingested)) {
+        info!("Tailing {} as {}", self.path.display(), self.parser.name());
+        loop {
+            match self.poll(&mut sink) {
+                Ok(()) => thread::sleep(POLL_INTERVAL),
+                Err(e) => {
+                    warn!("Failed to tail {}: {}", self.path.display(), e);
+                    self.file = None;
+                    thread::sleep(RETRY_INTERVAL);
+                }
+            }
+        }
+    }
+
+    /// Reads everything available, handles rotation and hands on the checkpoint.
+    pub fn poll(&mut self, sink: &mut impl FnMut(Ingested)) -> io::Result<()> {
+        if self.file.is_none() {
+            self.file = Some(self.open(sink)?);
+        }
+        self.read_lines(sink)?;
+
+        match fs::metadata(&self.path) {
+            Ok(meta) => {
+                let open = self.file.as_mut().expect("file opened above");
+                if meta.ino() != open.inode {
+                    debug!("{} was rotated, switching to the new file", self.path.display());
+                    // Pick up anything written to the old file before it was renamed.
+                    self.read_lines(sink)?;
+                    self.checkpoint(sink)?;
+                    self.file = Some(open_at(&self.path, 0)?);
+                    self.read_lines(sink)?;
+                } else if meta.len() < open.offset {
+                    debug!("{} was truncated, reading from the start", self.path.display());
+                    // The fingerprint still identifies the copy made by copytruncate.
+                    if let Some(saved) = self.saved.filter(|saved| saved.inode == open.inode) {
+                        let current = Checkpoint { offset: open.offset, ..saved };
+                        self.catch_up_rotated(&current, sink)?;
+                    }
+                    let open = self.file.as_mut().expect("file opened above");
+                    open.reader.seek(SeekFrom::Start(0))?;
+                    open.offset = 0;
+                    open.pending.clear();
+                    self.read_lines(sink)?;
+                }
+            }
+            // Renamed away and not recreated yet, keep draining the old handle.
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+
+        self.checkpoint(sink)
+    }
+
+    fn open(&mut self, sink: &mut impl FnMut(Ingested)) -> io::Result<OpenFile> {
+        let file = File::open(&self.path)?;
+        let meta = file.metadata()?;
+
+        let offset = match self.saved {
+            Some(saved) if saved.inode == meta.ino()
+                && saved.offset <= meta.len()
+                && fingerprint_at(&file, saved.fingerprint.len)? == Some(saved.fingerprint) =>
+            {
+                saved.offset
+            }
+            Some(saved) => {
+                self.catch_up_rotated(&saved, sink)?;
+                0
+            }
+            None => match self.start {
+                TailStart::Beginning => 0,
+                TailStart::End => meta.len(),
+            },
+        };
+
+        let mut reader = BufReader::new(file);
+        reader.seek(SeekFrom::Start(offset))?;
+        Ok(OpenFile { reader, inode: meta.ino(), offset, pending: Vec::new() })
+    }
+
+    /// Reads the rest of the rotated generation matching `checkpoint`, if one can be found.
+    fn catch_up_rotated(&self, checkpoint: &Checkpoint, sink: &mut impl FnMut(Ingested)) -> io::Result<()> {
+        // Nothing was read from that generation, so there is nothing to identify it by.
+        if checkpoint.fingerprint.len == 0 {
+            return Ok(());
+        }
+
+        for suffix in ROTATED_SUFFIXES {
+            let candidate = rotated_path(&self.path, suffix);
+            let Some(mut reader) = open_rotated(&candidate)? else { continue };
+            if Fingerprint::read(&mut reader, checkpoint.fingerprint.len)? != Some(checkpoint.fingerprint) {
+                continue;
+            }
+
+            // Re-open so skipping starts from the beginning of the (possibly compressed) stream.
+            let mut reader = open_rotated(&candidate)?.expect("rotated file opened above");
+            let skipped = io::copy(&mut reader.by_ref().take(checkpoint.offset), &mut io::sink())?;
+            if skipped < checkpoint.offset {
+                continue;
+            }
+
+            info!("Resuming {} from rotated {}", self.path.display(), candidate.display());
+            let modified = modified_at(&fs::metadata(&candidate)?);
+            for line in BufReader::new(reader).split(b'\n') {
+                self.emit(&line?, modified, sink);
+            }
+            return Ok(());
+        }
+
+        warn!("Could not find the rotated generation of {}, some lines may be missing", self.path.display());
+        Ok(())
+    }
+
+    fn read_lines(&mut self, sink: &mut impl FnMut(Ingested)) -> io::Result<()> {
+        let parser = self.parser.clone();
+        let open = self.file.as_mut().expect("file opened before reading");
+        // Lines read now were written before the file's current modification time,
+        // give or take lines appended while reading.
+        let modified = modified_at(&open.reader.get_ref().metadata()?);
+        loop {
+            let read = open.reader.read_until(b'\n', &mut open.pending)?;
+            if read == 0 || !open.pending.ends_with(b"\n") {
+                return Ok(());
+            }
+
+            open.offset += open.pending.len() as u64;
+            let line = String::from_utf8_lossy(&open.pending);
+            if let Some(entry) = parser.parse_with_reference(line.trim_end(), modified) {
+                sink(Ingested::Entry(entry));
+            }
+            open.pending.clear();
+        }
+    }
+
+    fn emit(&self, line: &[u8], modified: DateTime<Utc>, sink: &mut impl FnMut(Ingested)) {
+        let line = String::from_utf8_lossy(line);
+        if let Some(entry) = self.parser.parse_with_reference(line.trim_end(), modified) {
+            sink(Ingested::Entry(entry));
+        }
+    }
+
+    fn checkpoint(&mut self, sink: &mut impl FnMut(Ingested)) -> io::Result<()> {
+        let Some(open) = &self.file else { return Ok(()) };
+        if self.saved.is_some_and(|saved| saved.inode == open.inode && saved.offset == open.offset) {
+            return Ok(());
+        }
+
+        let fingerprint_len = open.offset.min(FINGERPRINT_LEN);
+        let Some(fingerprint) = fingerprint_at(open.reader.get_ref(), fingerprint_len)? else {
+            return Ok(());
+        };
+        let checkpoint = Checkpoint { inode: open.inode, offset: open.offset, fingerprint };
+        sink(Ingested::Checkpoint(PendingCheckpoint::file(self.checkpoints.clone(), &self.path, checkpoint)));
+        self.saved = Some(checkpoint);
+        Ok(())
+    }
+}
+
+/// The modification time of a file, now where the filesystem does not record it.
+pub fn modified_at(meta: &fs::Metadata) -> DateTime<Utc> {
+    meta.modified().map(DateTime::<Utc>::from).unwrap_or_else(|_| Utc::now())
+}
+
+fn open_at(path: &Path, offset: u64) -> io::Result<OpenFile> {
+    let file = File::open(path)?;
+    let inode = file.metadata()?.ino();
+    let mut reader = BufReader::new(file);
+    reader.seek(SeekFrom::Start(offset))?;
+    Ok(OpenFile { reader, inode, offset, pending: Vec::new() })
+}
+
+/// Fingerprints the start of `file` without moving its cursor.
+fn fingerprint_at(file: &File, len: u64) -> io::Result<Option<Fingerprint>> {
+    let mut buf = vec![0; len as usize];
+    let mut filled = 0;
+    while filled < buf.len() {
+        match file.read_at(&mut buf[filled..], filled as u64)? {
+            0 => return Ok(None),
+            read => filled += read,
+        }
+    }
+    Ok(Some(Fingerprint::of(&buf)))
+}
+
+fn rotated_path(path: &Path, suffix: &str) -> PathBuf {
+    let mut rotated = path.as_os_str().to_owned();
+    rotated.push(suffix);
+    PathBuf::from(rotated)
+}
+
+fn open_rotated(path: &Path) -> io::Result<Option<Box<dyn Read>>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        Ok(Some(Box::new(GzDecoder::new(file))))
+    } else {
+        Ok(Some(Box::new(file)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    use crate::parsers::nginx::NginxParser;
+
+    fn nginx() -> Arc<dyn LogParser> {
+        Arc::new(NginxParser::new())
+    }
+
+    fn line(i: u32) -> String {
+        format!("10.0.0.{i} - - [12/Mar/2024:14:56:23 +0000] \"GET /{i} HTTP/1.1\" 200 512\n")
+    }
+
+    fn append(path: &Path, lines: std::ops::RangeInclusive<u32>) {
+        let mut file = fs::OpenOptions::new().create(true).append(true).open(path).unwrap();
+        for i in lines {
+            file.write_all(line(i).as_bytes()).unwrap();
+        }
+    }
+
+    /// Polls once, saving checkpoints as soon as they are handed on.
+    fn poll(tailer: &mut Tailer) -> Vec<String> {
+        let mut requests = Vec::new();
+        tailer
+            .poll(&mut |ingested| match ingested {
+                Ingested::Entry(entry) => requests.push(entry.request.unwrap()),
+                Ingested::Checkpoint(checkpoint) => checkpoint.save().unwrap(),
+            })
+            .unwrap();
+        requests
+    }
+
+    fn setup() -> (tempfile::TempDir, PathBuf, Arc<CheckpointStore>) {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("access.log");
+        let checkpoints = Arc::new(CheckpointStore::load(dir.path().join("checkpoints.json")));
+        (dir, log, checkpoints)
+    }
+
+    #[test]
+    fn test_follows_appends_and_waits_for_complete_lines() {
+        let (_dir, log, checkpoints) = setup();
+        append(&log, 1..=2);
+
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints, TailStart::Beginning);
+        assert_eq!(poll(&mut tailer), vec!["GET /1 HTTP/1.1", "GET /2 HTTP/1.1"]);
+
+        let mut file = fs::OpenOptions::new().append(true).open(&log).unwrap();
+        let partial = line(3);
+        file.write_all(&partial.as_bytes()[..10]).unwrap();
+        assert!(poll(&mut tailer).is_empty());
+
+        file.write_all(&partial.as_bytes()[10..]).unwrap();
+        assert_eq!(poll(&mut tailer), vec!["GET /3 HTTP/1.1"]);
+    }
+
+    #[test]
+    fn test_restart_resumes_from_checkpoint() {
+        let (_dir, log, checkpoints) = setup();
+        append(&log, 1..=2);
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints.clone(), TailStart::Beginning);
+        assert_eq!(poll(&mut tailer).len(), 2);
+        drop(tailer);
+
+        append(&log, 3..=3);
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints, TailStart::Beginning);
+        assert_eq!(poll(&mut tailer), vec!["GET /3 HTTP/1.1"]);
+    }
+
+    #[test]
+    fn test_restart_before_checkpoint_is_saved_rereads_lines() {
+        let (_dir, log, checkpoints) = setup();
+        append(&log, 1..=2);
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints.clone(), TailStart::Beginning);
+        assert_eq!(poll(&mut tailer).len(), 2);
+        drop(tailer);
+
+        // Line 3 is read, but its entry is never stored.
+        append(&log, 3..=3);
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints.clone(), TailStart::Beginning);
+        tailer.poll(&mut |_| {}).unwrap();
+        drop(tailer);
+
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints, TailStart::Beginning);
+        assert_eq!(poll(&mut tailer), vec!["GET /3 HTTP/1.1"]);
+    }
+
+    #[test]
+    fn test_rename_rotation_drains_old_file_then_follows_new_one() {
+        let (_dir, log, checkpoints) = setup();
+        append(&log, 1..=1);
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints, TailStart::Beginning);
+        assert_eq!(poll(&mut tailer).len(), 1);
+
+        append(&log, 2..=2);
+        fs::rename(&log, rotated_path(&log, ".1")).unwrap();
+        append(&log, 3..=3);
+
+        assert_eq!(poll(&mut tailer), vec!["GET /2 HTTP/1.1", "GET /3 HTTP/1.1"]);
+    }
+
+    #[test]
+    fn test_copytruncate_reads_missed_lines_from_copy() {
+        let (_dir, log, checkpoints) = setup();
+        append(&log, 1..=3);
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints, TailStart::Beginning);
+        assert_eq!(poll(&mut tailer).len(), 3);
+
+        // Lines 4 and 5 are written and rotated away between two polls.
+        append(&log, 4..=5);
+        fs::copy(&log, rotated_path(&log, ".1")).unwrap();
+        fs::OpenOptions::new().write(true).truncate(true).open(&log).unwrap();
+        append(&log, 6..=6);
+
+        assert_eq!(poll(&mut tailer), vec!["GET /4 HTTP/1.1", "GET /5 HTTP/1.1", "GET /6 HTTP/1.1"]);
+    }
+
+    #[test]
+    fn test_restart_after_rotation_reads_rest_of_compressed_generation() {
+        let (_dir, log, checkpoints) = setup();
+        append(&log, 1..=1);
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints.clone(), TailStart::Beginning);
+        assert_eq!(poll(&mut tailer).len(), 1);
+        drop(tailer);
+
+        // While stopped: more lines, then rotation with compression.
+        append(&log, 2..=2);
+        let rotated = fs::read(&log).unwrap();
+        let mut encoder = GzEncoder::new(File::create(rotated_path(&log, ".1.gz")).unwrap(), Compression::default());
+        encoder.write_all(&rotated).unwrap();
+        encoder.finish().unwrap();
+        fs::remove_file(&log).unwrap();
+        append(&log, 3..=3);
+
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints, TailStart::Beginning);
+        assert_eq!(poll(&mut tailer), vec!["GET /2 HTTP/1.1", "GET /3 HTTP/1.1"]);
+    }
+
+    #[test]
+    fn test_start_at_end_skips_existing_lines() {
+        let (_dir, log, checkpoints) = setup();
+        append(&log, 1..=2);
+        let mut tailer = Tailer::new(&log, nginx(), checkpoints, TailStart::End);
+        assert!(poll(&mut tailer).is_empty());
+
+        append(&log, 3..=3);
+        assert_eq!(poll(&mut tailer), vec!["GET /3 HTTP/1.1"]);
+    }
+}
@@ -3,12 +3,24 @@ mod routes;
 mod handlers;
 mod models;
 mod middleware;
+mod ingest;
+mod parsers;
+mod agent;
+mod detect;
 
 extern crate db;
 
-use server::server::start;
+use server::server::{migrate, start};
 
 
 fn main() {
-    start();
+    match std::env::args().nth(1).as_deref() {
+        None | Some("serve") => start(),
+        Some("migrate") => migrate(),
+        Some("agent") => agent::run(),
+        Some(other) => {
+            eprintln!("Unknown command '{}'. Usage: rust [serve|migrate|agent]", other);
+            std::process::exit(2);
+        }
+    }
 }
@@ -0,0 +1,197 @@
+use std::env;
+use std::net::{IpAddr, SocketAddr};
+
+use axum::extract::{ConnectInfo, FromRequestParts};
+use axum::http::header::AUTHORIZATION;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, HeaderName};
+use tracing::warn;
+
+use crate::handlers::error::ApiError;
+use crate::models::filter::IpRange;
+use crate::server::state::AppState;
+
+/// Headers a TLS-terminating proxy sets once it has verified a client
+/// certificate (nginx: `proxy_set_header X-SSL-Client-Verify $ssl_client_verify;`
+/// and `proxy_set_header X-SSL-Client-S-DN $ssl_client_s_dn;`).
+#[derive(Debug, Clone)]
+pub struct ClientCertHeaders {
+    /// Holds `SUCCESS` when the certificate was verified.
+    pub verify: HeaderName,
+    /// The certificate's subject, whose CN is the agent ID.
+    pub subject: HeaderName,
+    /// Peers allowed to set these headers. Anyone else could forge them.
+    pub proxies: Vec<IpRange>,
+}
+
+/// Who may push batches to `POST /api/v1/ingest`, and as which agent.
+///
+/// Agents authenticate with a bearer token issued to their agent ID, or with
+/// a client certificate verified by a proxy in front of the server, whose CN
+/// is their agent ID. The proxy's headers are ignored on connections from
+/// any other address.
+#[derive(Debug, Clone, Default)]
+pub struct IngestAuth {
+    /// Accepted tokens, each with the agent ID it was issued to.
+    tokens: Vec<(String, String)>,
+    client_cert: Option<ClientCertHeaders>,
+}
+
+impl IngestAuth {
+    pub fn new(tokens: Vec<(String, String)>, client_cert: Option<ClientCertHeaders>) -> Self {
+        Self { tokens, client_cert }
+    }
+
+    /// Reads `INGEST_TOKENS`, a comma-separated list of `agent_id:token`
+    /// pairs, and `INGEST_CLIENT_CERT_HEADER`, which enables client
+    /// certificates together with `INGEST_CLIENT_SUBJECT_HEADER` (default
+    /// `X-SSL-Client-S-DN`) and `INGEST_TRUSTED_PROXIES`, addresses or CIDR
+    /// blocks (default loopback). With neither set every push is refused.
+    pub fn from_env() -> Result<Self, String> {
+        let var = |name: &str| env::var(name).ok().filter(|value| !value.trim().is_empty());
+        let header = |name: &str, value: String| -> Result<HeaderName, String> {
+            value.trim().parse().map_err(|_| format!("invalid {}: {}", name, value))
+        };
+
+        let tokens = var("INGEST_TOKENS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| match pair.split_once(':') {
+                Some((agent_id, token)) if !agent_id.trim().is_empty() && !token.trim().is_empty() => {
+                    Ok((agent_id.trim().to_string(), token.trim().to_string()))
+                }
+                _ => Err("INGEST_TOKENS entries must be agent_id:token".to_string()),
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+
+        let client_cert = match var("INGEST_CLIENT_CERT_HEADER") {
+            Some(verify) => Some(ClientCertHeaders {
+                verify: header("INGEST_CLIENT_CERT_HEADER", verify)?,
+                subject: header(
+                    "INGEST_CLIENT_SUBJECT_HEADER",
+                    var("INGEST_CLIENT_SUBJECT_HEADER").unwrap_or_else(|| "x-ssl-client-s-dn".to_string()),
+                )?,
+                proxies: var("INGEST_TRUSTED_PROXIES")
+                    .unwrap_or_else(|| "127.0.0.1,::1".to_string())
+                    .split(',')
+                    .map(|proxy| proxy.trim().parse())
+                    .collect::<Result<_, String>>()
+                    .map_err(|e| format!("invalid INGEST_TRUSTED_PROXIES: {}", e))?,
+            }),
+            None => None,
+        };
+
+        if tokens.is_empty() && client_cert.is_none() {
+            warn!("Neither INGEST_TOKENS nor INGEST_CLIENT_CERT_HEADER is set, agents cannot push logs");
+        }
+        Ok(Self::new(tokens, client_cert))
+    }
+
+    /// The agent ID the request is authenticated as. `peer` is the address the
+    /// request came from, `None` when unknown.
+    pub fn authorize(&self, headers: &HeaderMap, peer: Option<IpAddr>) -> Result<String, ApiError> {
+        if let Some(client_cert) = &self.client_cert {
+            let trusted = peer.is_some_and(|peer| client_cert.proxies.iter().any(|proxy| proxy.contains(&peer)));
+            if trusted && headers.get(&client_cert.verify).is_some_and(|verified| verified == "SUCCESS") {
+                return headers
+                    .get(&client_cert.subject)
+                    .and_then(|subject| subject.to_str().ok())
+                    .and_then(common_name)
+                    .map(str::to_string)
+                    .ok_or_else(|| ApiError::Unauthorized("Client certificate has no CN".to_string()));
+            }
+        }
+
+        let token = headers
+            .get(AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "))
+            .ok_or_else(|| ApiError::Unauthorized("Missing bearer token".to_string()))?;
+        self.tokens
+            .iter()
+            // Compare with every token, so response times do not reveal which one matched.
+            .fold(None, |found, (agent_id, accepted)| {
+                let matches = constant_time_eq(accepted.as_bytes(), token.trim().as_bytes());
+                found.or(matches.then(|| agent_id.clone()))
+            })
+            .ok_or_else(|| ApiError::Unauthorized("Invalid token".to_string()))
+    }
+}
+
+/// The CN of a subject written as `CN=web1,O=Example` or `/O=Example/CN=web1`.
+fn common_name(subject: &str) -> Option<&str> {
+    subject
+        .split([',', '/'])
+        .find_map(|part| part.trim().strip_prefix("CN="))
+        .filter(|cn| !cn.is_empty())
+}
+
+/// Compares without returning early, so response times do not reveal how much of a token matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// Extractor that rejects requests `IngestAuth` does not authorize, holding
+/// the agent ID of those it does.
+pub struct AuthorizedAgent(pub String);
+
+impl FromRequestParts<AppState> for AuthorizedAgent {
+    type Rejection = ApiError;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        // Only known when the server is run with `into_make_service_with_connect_info`.
+        let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>().map(|ConnectInfo(addr)| addr.ip());
+        state.ingest.auth.authorize(&parts.headers, peer).map(AuthorizedAgent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.parse::<HeaderName>().unwrap(), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    fn tokens() -> Vec<(String, String)> {
+        vec![("web1".to_string(), "s3cret".to_string()), ("web2".to_string(), "other".to_string())]
+    }
+
+    #[test]
+    fn test_tokens_identify_their_agent() {
+        let auth = IngestAuth::new(tokens(), None);
+        assert_eq!(auth.authorize(&headers(&[("authorization", "Bearer s3cret")]), None).unwrap(), "web1");
+        assert_eq!(auth.authorize(&headers(&[("authorization", "Bearer other")]), None).unwrap(), "web2");
+        assert!(auth.authorize(&headers(&[("authorization", "Bearer s3cre")]), None).is_err());
+        assert!(auth.authorize(&headers(&[("authorization", "s3cret")]), None).is_err());
+        assert!(auth.authorize(&headers(&[]), None).is_err());
+        assert!(IngestAuth::default().authorize(&headers(&[("authorization", "Bearer ")]), None).is_err());
+    }
+
+    #[test]
+    fn test_client_certificates_verified_by_a_trusted_proxy() {
+        let client_cert = ClientCertHeaders {
+            verify: "x-ssl-client-verify".parse().unwrap(),
+            subject: "x-ssl-client-s-dn".parse().unwrap(),
+            proxies: vec!["10.1.0.0/24".parse().unwrap()],
+        };
+        let auth = IngestAuth::new(tokens(), Some(client_cert));
+        let proxy = Some("10.1.0.5".parse().unwrap());
+        let verified = headers(&[("x-ssl-client-verify", "SUCCESS"), ("x-ssl-client-s-dn", "CN=web3,O=Example")]);
+
+        assert_eq!(auth.authorize(&verified, proxy).unwrap(), "web3");
+        assert_eq!(auth.authorize(&headers(&[("x-ssl-client-verify", "SUCCESS"), ("x-ssl-client-s-dn", "/O=Example/CN=web4")]), proxy).unwrap(), "web4");
+        assert!(auth.authorize(&headers(&[("x-ssl-client-verify", "SUCCESS")]), proxy).is_err());
+        assert!(auth.authorize(&headers(&[("x-ssl-client-verify", "FAILED:unable to verify")]), proxy).is_err());
+
+        // The same headers sent straight to the server are not trusted.
+        assert!(auth.authorize(&verified, Some("203.0.113.9".parse().unwrap())).is_err());
+        assert!(auth.authorize(&verified, None).is_err());
+    }
+}
@@ -0,0 +1,35 @@
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::models::log::LogEntry;
+
+/// Entries an agent pushes to `POST /api/v1/ingest`, as JSON, usually gzipped.
+///
+/// `id` stays the same when a batch is sent again, so the server can tell a
+/// retry from new entries.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IngestBatch {
+    pub id: Uuid,
+    pub agent_id: String,
+    pub entries: Vec<LogEntry>,
+}
+
+/// Reply to a batch the server has stored.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IngestResponse {
+    pub id: Uuid,
+    /// Entries stored.
+    pub accepted: usize,
+    /// The batch had already been stored, nothing was written again.
+    pub duplicate: bool,
+    /// Entries that were dropped or stored without an invalid field.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub invalid: Vec<InvalidEntry>,
+}
+
+/// An entry of a batch that failed validation, by its position in the batch.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InvalidEntry {
+    pub index: usize,
+    pub reason: String,
+}
@@ -1,122 +1,131 @@
-use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::fmt;
 use std::net::IpAddr;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::str::FromStr;
+use std::sync::Arc;
 
-pub struct FailedLogins {
-    per_minute: HashMap<IpAddr, HashMap<u64, usize>>,
-    per_10_seconds: HashMap<IpAddr, HashMap<u64, usize>>,
-    min_threshold: usize,
-    sec_threshold: usize,
-    window_mins: u64,
-    window_secs: u64,
-    last_cleanup: u64, // Tracks when last cleanup happened
-    cleanup_interval: u64, // How often to run cleanup (seconds)
+use chrono::{DateTime, Duration, Utc};
+
+use crate::detect::clock::Clock;
+use crate::detect::lru::LruMap;
+
+/// A number of failed logins within a window, written `5/10s`, `20/5m` or `100/1h`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threshold {
+    pub attempts: usize,
+    pub window: Duration,
 }
 
-impl FailedLogins {
-    pub fn new(min_threshold: usize, sec_threshold: usize, window_mins: u64, window_secs: u64, cleanup_interval: u64) -> Self {
-        Self {
-            per_minute: HashMap::new(),
-            per_10_seconds: HashMap::new(),
-            min_threshold,
-            sec_threshold,
-            window_mins,
-            window_secs,
-            last_cleanup: Self::current_time(),
-            cleanup_interval,
+impl Threshold {
+    /// Parses a comma-separated list such as `5/10s,20/5m,100/1h`.
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, String> {
+        let thresholds = list
+            .split(',')
+            .map(str::trim)
+            .filter(|threshold| !threshold.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        if thresholds.is_empty() {
+            return Err("no thresholds given".to_string());
         }
+        Ok(thresholds)
     }
 
-    fn current_time() -> u64 {
-        SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs()
+    /// The window as written in a threshold, e.g. `10s` or `5m`.
+    pub fn window_label(&self) -> String {
+        let seconds = self.window.num_seconds();
+        match seconds {
+            _ if seconds % 3600 == 0 => format!("{}h", seconds / 3600),
+            _ if seconds % 60 == 0 => format!("{}m", seconds / 60),
+            _ => format!("{}s", seconds),
+        }
     }
+}
 
-    pub fn register_attempt(&mut self, ip: IpAddr) -> bool {
-        let now_seconds = Self::current_time();
-        let now_minute = now_seconds / 60;
-        let now_10s = now_seconds / 10;
-
-        if now_seconds - self.last_cleanup > self.cleanup_interval {
-            self.cleanup_old_attempts();
-            self.last_cleanup = now_seconds;
+impl FromStr for Threshold {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid threshold '{}', expected e.g. 5/10s", s);
+        let (attempts, window) = s.split_once('/').ok_or_else(invalid)?;
+        let attempts: usize = attempts.trim().parse().map_err(|_| invalid())?;
+
+        let window = window.trim();
+        let unit = window.chars().last().ok_or_else(invalid)?;
+        let amount: i64 = window[..window.len() - unit.len_utf8()].parse().map_err(|_| invalid())?;
+        let window = match unit {
+            's' => Duration::seconds(amount),
+            'm' => Duration::minutes(amount),
+            'h' => Duration::hours(amount),
+            _ => return Err(invalid()),
+        };
+        if attempts == 0 || window <= Duration::zero() {
+            return Err(invalid());
         }
+        Ok(Self { attempts, window })
+    }
+}
 
-        let min_buckets = self.per_minute.entry(ip).or_insert_with(HashMap::new);
-        let sec_buckets = self.per_10_seconds.entry(ip).or_insert_with(HashMap::new);
-
-        *min_buckets.entry(now_minute).or_insert(0) += 1;
-        *sec_buckets.entry(now_10s).or_insert(0) += 1;
-
-        let min_failures: usize = min_buckets.values().sum();
-        let sec_failures: usize = sec_buckets.values().sum();
-
-        min_failures >= self.min_threshold || sec_failures >= self.sec_threshold
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} in {}", self.attempts, self.window_label())
     }
+}
 
-    fn cleanup_old_attempts(&mut self) {
-        let now_minute = Self::current_time() / 60;
-        let now_10s = Self::current_time() / 10;
+/// Recent failed logins per address, checked against sliding windows.
+///
+/// Each address keeps only as many attempts as the largest threshold needs,
+/// and at most `max_tracked` addresses are kept, forgetting the least recently
+/// seen first.
+pub struct FailedLogins {
+    thresholds: Vec<Threshold>,
+    tracked: LruMap<IpAddr, VecDeque<DateTime<Utc>>>,
+    clock: Arc<dyn Clock>,
+}
 
-        self.per_minute.retain(|_, buckets| {
-            buckets.retain(|&min, _| now_minute.saturating_sub(min) < self.window_mins);
-            !buckets.is_empty()
-        });
+impl FailedLogins {
+    pub fn new(thresholds: Vec<Threshold>, max_tracked: usize, clock: Arc<dyn Clock>) -> Self {
+        Self { thresholds, tracked: LruMap::new(max_tracked), clock }
+    }
 
-        self.per_10_seconds.retain(|_, buckets| {
-            buckets.retain(|&sec, _| now_10s.saturating_sub(sec) < self.window_secs);
-            !buckets.is_empty()
-        });
+    /// Counts a failed login from `ip` at the clock's current time. Returns the
+    /// first threshold it reaches, if any.
+    pub fn register_attempt(&mut self, ip: IpAddr) -> Option<Threshold> {
+        let now = self.clock.now();
+        let longest = self.thresholds.iter().map(|threshold| threshold.window).max()?;
+        let most = self.thresholds.iter().map(|threshold| threshold.attempts).max()?;
+
+        let attempts = self.tracked.touch(ip);
+        attempts.push_back(now);
+        while attempts.len() > most || attempts.front().is_some_and(|&attempt| now - attempt >= longest) {
+            attempts.pop_front();
+        }
 
-        println!("[Cleanup] Removed stale login attempts.");
+        let attempts = self.tracked.get(&ip).expect("address was just tracked");
+        // The threshold is reached when its n-th most recent attempt is still in its window.
+        self.thresholds
+            .iter()
+            .find(|threshold| {
+                attempts.len() >= threshold.attempts
+                    && now - attempts[attempts.len() - threshold.attempts] < threshold.window
+            })
+            .copied()
     }
 }
 
-
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::net::{IpAddr, Ipv4Addr};
 
-    /// Wrapper to simulate time changes without modifying production code
-    struct MockTimeTracker {
-        failed_logins: FailedLogins,
-        now: u64,
-    }
-
-    impl MockTimeTracker {
-        fn new(min_threshold: usize, sec_threshold: usize, window_mins: u64, window_secs: u64) -> Self {
-            Self {
-                failed_logins: FailedLogins::new(min_threshold, sec_threshold, window_mins, window_secs, 60),
-                now: 1700000000, // Fixed mock start time
-            }
-        }
-
-        fn register_attempt(&mut self, ip: IpAddr) -> bool {
-            // Override `current_time()` with controlled mock time
-            let now_minute = self.now / 60;
-            let now_10s = self.now / 10;
-
-            let min_buckets = self.failed_logins.per_minute.entry(ip).or_insert_with(HashMap::new);
-            let sec_buckets = self.failed_logins.per_10_seconds.entry(ip).or_insert_with(HashMap::new);
-
-            *min_buckets.entry(now_minute).or_insert(0) += 1;
-            *sec_buckets.entry(now_10s).or_insert(0) += 1;
-
-            min_buckets.retain(|&min, _| now_minute.saturating_sub(min) < self.failed_logins.window_mins);
-            sec_buckets.retain(|&sec, _| now_10s.saturating_sub(sec) < self.failed_logins.window_secs);
+    use chrono::{TimeZone, Utc};
 
-            let min_failures: usize = min_buckets.values().sum();
-            let sec_failures: usize = sec_buckets.values().sum();
+    use crate::detect::clock::{EventClock, ManualClock};
 
-            min_failures >= self.failed_logins.min_threshold || sec_failures >= self.failed_logins.sec_threshold
-        }
-
-        fn advance_time(&mut self, secs: u64) {
-            self.now += secs;
-        }
+    fn tracker(thresholds: &str) -> (FailedLogins, Arc<ManualClock>) {
+        let clock = Arc::new(ManualClock::new(Utc.timestamp_opt(1700000000, 0).unwrap()));
+        let failed_logins = FailedLogins::new(Threshold::parse_list(thresholds).unwrap(), 1000, clock.clone());
+        (failed_logins, clock)
     }
 
     fn mock_ip() -> IpAddr {
@@ -125,62 +134,147 @@ mod tests {
 
     #[test]
     fn test_ban_after_three_attempts_in_a_minute() {
-        let mut tracker = MockTimeTracker::new(3, 5, 1, 1);
+        let (mut tracker, _) = tracker("3/1m,5/10s");
         let ip = mock_ip();
 
-        assert_eq!(tracker.register_attempt(ip), false);
-        assert_eq!(tracker.register_attempt(ip), false);
-        assert_eq!(tracker.register_attempt(ip), true);
+        assert_eq!(tracker.register_attempt(ip), None);
+        assert_eq!(tracker.register_attempt(ip), None);
+        assert_eq!(tracker.register_attempt(ip), Some("3/1m".parse().unwrap()));
     }
 
     #[test]
     fn test_ban_after_five_attempts_in_ten_seconds() {
-        let mut tracker = MockTimeTracker::new(10, 5, 1, 1);
+        let (mut tracker, _) = tracker("10/1m,5/10s");
         let ip = mock_ip();
 
         for _ in 0..4 {
-            assert_eq!(tracker.register_attempt(ip), false);
+            assert_eq!(tracker.register_attempt(ip), None);
         }
-        assert_eq!(tracker.register_attempt(ip), true); 
+        assert_eq!(tracker.register_attempt(ip), Some("5/10s".parse().unwrap()));
     }
 
     #[test]
     fn test_no_ban_if_attempts_are_spread_out() {
-        let mut tracker = MockTimeTracker::new(5, 10, 1, 1);
+        let (mut tracker, clock) = tracker("5/1m,10/10s");
         let ip = mock_ip();
 
         for _ in 0..4 {
             tracker.register_attempt(ip);
-            tracker.advance_time(20);
+            clock.advance(Duration::seconds(20));
         }
 
-        tracker.advance_time(120);
-        assert_eq!(tracker.register_attempt(ip), false); 
+        clock.advance(Duration::seconds(120));
+        assert_eq!(tracker.register_attempt(ip), None);
     }
 
     #[test]
     fn test_expired_attempts_dont_contribute_to_ban() {
-        let mut tracker = MockTimeTracker::new(5, 10, 1, 1);
+        let (mut tracker, clock) = tracker("5/1m,10/10s");
         let ip = mock_ip();
 
         for _ in 0..4 {
             tracker.register_attempt(ip);
         }
 
-        tracker.advance_time(61);
-        assert_eq!(tracker.register_attempt(ip), false); 
+        clock.advance(Duration::seconds(61));
+        assert_eq!(tracker.register_attempt(ip), None);
+    }
+
+    #[test]
+    fn test_windows_slide_by_the_second() {
+        let (mut tracker, clock) = tracker("3/10s");
+        let ip = mock_ip();
+
+        tracker.register_attempt(ip);
+        clock.advance(Duration::seconds(5));
+        tracker.register_attempt(ip);
+        // The first attempt is exactly 10 seconds old, just out of the window.
+        clock.advance(Duration::seconds(5));
+        assert_eq!(tracker.register_attempt(ip), None);
+        clock.advance(Duration::seconds(4));
+        assert!(tracker.register_attempt(ip).is_some());
     }
 
     #[test]
     fn test_burst_attack_gets_caught() {
-        let mut tracker = MockTimeTracker::new(10, 5, 1, 1);
+        let (mut tracker, clock) = tracker("10/1m,5/10s");
         let ip = mock_ip();
 
         for _ in 0..4 {
             tracker.register_attempt(ip);
-            tracker.advance_time(2);
+            clock.advance(Duration::seconds(2));
+        }
+
+        assert!(tracker.register_attempt(ip).is_some());
+    }
+
+    #[test]
+    fn test_long_windows() {
+        let (mut tracker, clock) = tracker("5/10s,20/5m,100/1h");
+        let ip = mock_ip();
+
+        // One attempt every 30 seconds never reaches the shorter windows.
+        for _ in 0..99 {
+            assert_eq!(tracker.register_attempt(ip), None);
+            clock.advance(Duration::seconds(30));
+        }
+        let (mut tracker, clock) = self::tracker("5/10s,20/5m,100/1h");
+        for _ in 0..19 {
+            assert_eq!(tracker.register_attempt(ip), None);
+            clock.advance(Duration::seconds(14));
+        }
+        assert_eq!(tracker.register_attempt(ip), Some("20/5m".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_least_recently_seen_addresses_are_forgotten() {
+        let clock = Arc::new(ManualClock::new(Utc::now()));
+        let mut tracker = FailedLogins::new(Threshold::parse_list("2/1m").unwrap(), 2, clock);
+        let first = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let second = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 2));
+        let third = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3));
+
+        tracker.register_attempt(first);
+        tracker.register_attempt(second);
+        tracker.register_attempt(first);
+        tracker.register_attempt(third);
+
+        assert_eq!(tracker.tracked.len(), 2);
+        assert!(tracker.tracked.get(&second).is_none());
+        assert_eq!(tracker.register_attempt(second), None);
+        assert!(tracker.register_attempt(third).is_some());
+    }
+
+    #[test]
+    fn test_event_time_follows_the_logs() {
+        let clock = Arc::new(EventClock::new());
+        let mut tracker = FailedLogins::new(Threshold::parse_list("3/10s").unwrap(), 1000, clock.clone());
+        let ip = mock_ip();
+        let start = Utc.with_ymd_and_hms(2023, 6, 1, 3, 0, 0).unwrap();
+
+        // A replay of attempts an hour apart is not a burst, however fast it is read.
+        for hour in 0..3 {
+            clock.observe(start + Duration::hours(hour));
+            assert_eq!(tracker.register_attempt(ip), None);
+        }
+
+        let later = start + Duration::hours(3);
+        for second in 0..2 {
+            clock.observe(later + Duration::seconds(second));
+            assert_eq!(tracker.register_attempt(ip), None);
         }
+        clock.observe(later + Duration::seconds(2));
+        assert!(tracker.register_attempt(ip).is_some());
+    }
 
-        assert_eq!(tracker.register_attempt(ip), true);
+    #[test]
+    fn test_threshold_parsing() {
+        let thresholds = Threshold::parse_list("5/10s, 20/5m,100/1h").unwrap();
+        assert_eq!(thresholds[1], Threshold { attempts: 20, window: Duration::minutes(5) });
+        assert_eq!(thresholds.iter().map(ToString::to_string).collect::<Vec<_>>(), ["5 in 10s", "20 in 5m", "100 in 1h"]);
+
+        for invalid in ["", "5", "5/10", "0/10s", "5/0s", "5/10d", "x/10s", "5/1é", "5/é"] {
+            assert!(Threshold::parse_list(invalid).is_err(), "{}", invalid);
+        }
     }
 }
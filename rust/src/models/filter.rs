@@ -0,0 +1,265 @@
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use db::schema::{DbLogEntry, ThreatLevel};
+use ipnet::IpNet;
+use serde::{Deserialize, Serialize};
+
+use crate::models::log::{LogEntry, LogSource};
+
+/// A single address or a CIDR block, e.g. `10.0.0.5` or `10.0.0.0/8`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct IpRange(IpNet);
+
+impl IpRange {
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        self.0.contains(ip)
+    }
+
+    pub fn net(&self) -> IpNet {
+        self.0
+    }
+
+    /// Parses `ip` first, so unparseable addresses never match.
+    pub fn contains_str(&self, ip: &str) -> bool {
+        ip.parse::<IpAddr>().is_ok_and(|ip| self.contains(&ip))
+    }
+}
+
+impl FromStr for IpRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(net) = s.parse::<IpNet>() {
+            return Ok(IpRange(net));
+        }
+        s.parse::<IpAddr>()
+            .map(|ip| IpRange(IpNet::from(ip)))
+            .map_err(|_| format!("invalid IP address or CIDR: {}", s))
+    }
+}
+
+impl TryFrom<String> for IpRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl From<IpRange> for String {
+    fn from(range: IpRange) -> Self {
+        range.to_string()
+    }
+}
+
+impl fmt::Display for IpRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.0.prefix_len() == self.0.max_prefix_len() {
+            write!(f, "{}", self.0.addr())
+        } else {
+            write!(f, "{}", self.0)
+        }
+    }
+}
+
+/// Query parameters used to narrow down streamed log entries.
+///
+/// Every field is optional, an empty filter matches everything.
+#[derive(Debug, Default, Clone, Deserialize)]
+pub struct LogFilter {
+    pub source: Option<LogSource>,
+    pub ip: Option<IpRange>,
+    pub user: Option<String>,
+    pub status: Option<u16>,
+    pub success: Option<bool>,
+    pub host: Option<String>,
+    /// Case-insensitive substring match against the raw line.
+    pub q: Option<String>,
+}
+
+impl LogFilter {
+    pub fn matches(&self, entry: &LogEntry) -> bool {
+        if self.source.is_some_and(|source| source != entry.source) {
+            return false;
+        }
+        if let Some(range) = &self.ip {
+            if !entry.ip_address.as_deref().is_some_and(|ip| range.contains_str(ip)) {
+                return false;
+            }
+        }
+        if self.user.is_some() && self.user != entry.user {
+            return false;
+        }
+        if self.status.is_some() && self.status != entry.status_code {
+            return false;
+        }
+        if self.success.is_some() && self.success != entry.success {
+            return false;
+        }
+        if self.host.is_some() && self.host != entry.host {
+            return false;
+        }
+        if let Some(q) = &self.q {
+            if !entry.raw.to_lowercase().contains(&q.to_lowercase()) {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Server-side filter sent by WebSocket clients.
+///
+/// Each non-empty list must contain a match, an empty list places no
+/// restriction. `event_types` and `threat_levels` are compared against the
+/// classified storage row, so entries without one never match them.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Subscription {
+    pub sources: Vec<LogSource>,
+    pub ips: Vec<IpRange>,
+    pub hosts: Vec<String>,
+    pub event_types: Vec<String>,
+    pub threat_levels: Vec<ThreatLevel>,
+}
+
+impl Subscription {
+    pub fn matches(&self, entry: &LogEntry, row: Option<&DbLogEntry>) -> bool {
+        if !self.sources.is_empty() && !self.sources.contains(&entry.source) {
+            return false;
+        }
+        if !self.ips.is_empty() {
+            let in_range = |ip: &IpAddr| self.ips.iter().any(|range| range.contains(ip));
+            let entry_ip = entry.ip_address.as_deref().and_then(|ip| ip.parse().ok());
+            if !entry_ip.iter().chain(row.map(|row| &row.source_ip)).any(in_range) {
+                return false;
+            }
+        }
+        if !self.hosts.is_empty() && !entry.host.as_deref().is_some_and(|host| self.hosts.iter().any(|h| h == host)) {
+            return false;
+        }
+        if !self.event_types.is_empty()
+            && !row.is_some_and(|row| contains_ignore_case(&self.event_types, row.event_type.as_str()))
+        {
+            return false;
+        }
+        if !self.threat_levels.is_empty()
+            && !row.is_some_and(|row| self.threat_levels.contains(&row.threat_level))
+        {
+            return false;
+        }
+        true
+    }
+}
+
+fn contains_ignore_case(values: &[String], value: &str) -> bool {
+    values.iter().any(|v| v.eq_ignore_ascii_case(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parsers::nginx::NginxParser;
+    use crate::parsers::LogParser;
+    use crate::ingest::classify::classify;
+    use db::schema::EventType;
+
+    fn nginx_entry() -> LogEntry {
+        NginxParser::new().parse(r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET /Admin HTTP/1.1" 404 512"#).unwrap()
+    }
+
+    #[test]
+    fn test_empty_filter_matches_everything() {
+        assert!(LogFilter::default().matches(&nginx_entry()));
+    }
+
+    #[test]
+    fn test_filter_by_source_and_ip() {
+        let entry = nginx_entry();
+        let filter = LogFilter {
+            source: Some(LogSource::NginxAccess),
+            ip: Some("192.168.1.0/24".parse().unwrap()),
+            ..Default::default()
+        };
+        assert!(filter.matches(&entry));
+
+        let filter = LogFilter { source: Some(LogSource::AuthLog), ..Default::default() };
+        assert!(!filter.matches(&entry));
+
+        let filter = LogFilter { ip: Some("10.0.0.1".parse().unwrap()), ..Default::default() };
+        assert!(!filter.matches(&entry));
+    }
+
+    #[test]
+    fn test_filter_by_status_and_text() {
+        let entry = nginx_entry();
+        let filter = LogFilter { status: Some(404), q: Some("admin".to_string()), ..Default::default() };
+        assert!(filter.matches(&entry));
+
+        let filter = LogFilter { status: Some(200), ..Default::default() };
+        assert!(!filter.matches(&entry));
+    }
+
+    fn row(event_type: &str, threat_level: ThreatLevel) -> DbLogEntry {
+        let mut row = classify(&nginx_entry());
+        row.event_type = EventType::from(event_type);
+        row.threat_level = threat_level;
+        row
+    }
+
+    #[test]
+    fn test_ip_range_parses_addresses_and_cidrs() {
+        let range: IpRange = "10.0.0.0/8".parse().unwrap();
+        assert!(range.contains_str("10.1.2.3"));
+        assert!(!range.contains_str("11.0.0.1"));
+
+        let single: IpRange = "2001:db8::1".parse().unwrap();
+        assert!(single.contains_str("2001:db8::1"));
+        assert_eq!(single.to_string(), "2001:db8::1");
+
+        assert!("not-an-ip".parse::<IpRange>().is_err());
+    }
+
+    #[test]
+    fn test_subscription_filters_by_cidr_and_source() {
+        let entry = nginx_entry();
+        let subscription: Subscription = serde_json::from_str(
+            r#"{"sources": ["NginxAccess"], "ips": ["192.168.0.0/16"]}"#,
+        ).unwrap();
+        assert!(subscription.matches(&entry, None));
+
+        let subscription: Subscription = serde_json::from_str(r#"{"ips": ["10.0.0.0/8"]}"#).unwrap();
+        assert!(!subscription.matches(&entry, None));
+    }
+
+    #[test]
+    fn test_filters_by_host() {
+        let mut entry = nginx_entry();
+        entry.host = Some("web1".to_string());
+
+        assert!(LogFilter { host: Some("web1".to_string()), ..Default::default() }.matches(&entry));
+        assert!(!LogFilter { host: Some("web2".to_string()), ..Default::default() }.matches(&entry));
+
+        let subscription: Subscription = serde_json::from_str(r#"{"hosts": ["web1", "web3"]}"#).unwrap();
+        assert!(subscription.matches(&entry, None));
+        let subscription: Subscription = serde_json::from_str(r#"{"hosts": ["web2"]}"#).unwrap();
+        assert!(!subscription.matches(&entry, None));
+        assert!(!subscription.matches(&nginx_entry(), None));
+    }
+
+    #[test]
+    fn test_subscription_filters_by_classification() {
+        let entry = nginx_entry();
+        let subscription = Subscription {
+            event_types: vec!["sql injection".to_string()],
+            threat_levels: vec![ThreatLevel::Critical],
+            ..Default::default()
+        };
+        assert!(subscription.matches(&entry, Some(&row("SQL Injection", ThreatLevel::Critical))));
+        assert!(!subscription.matches(&entry, Some(&row("SQL Injection", ThreatLevel::Low))));
+        assert!(!subscription.matches(&entry, None));
+    }
+}
@@ -1,2 +1,4 @@
 pub mod log;
-pub mod failed_login;
\ No newline at end of file
+pub mod failed_login;
+pub mod filter;
+pub mod batch;
@@ -0,0 +1,355 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use regex::Regex;
+
+use crate::models::log::{LogEntry, LogSource};
+use crate::parsers::format::{fields_of, LogFormat, Token};
+use crate::parsers::timestamp::{parse_local_timestamp, SourceTimezone};
+use crate::parsers::{Field, LogParser};
+
+/// Apache's `common` format.
+pub const COMMON: &str = r#"%h %l %u %t "%r" %>s %b"#;
+
+/// Apache's `combined` format, identical to nginx's.
+pub const COMBINED: &str = r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-Agent}i""#;
+
+/// `combined` with the time taken in microseconds appended, a common way to log timings.
+pub const COMBINED_TIMED: &str = r#"%h %l %u %t "%r" %>s %b "%{Referer}i" "%{User-Agent}i" %D"#;
+
+/// Debian's `vhost_combined`, used for `other_vhosts_access.log`.
+pub const VHOST_COMBINED: &str = r#"%v:%p %h %l %u %t "%r" %>s %O "%{Referer}i" "%{User-Agent}i""#;
+
+/// `common` prefixed with the virtual host.
+pub const VHOST_COMMON: &str = r#"%v %h %l %u %t "%r" %>s %b"#;
+
+/// Apache httpd access log lines in the usual formats or a custom `LogFormat`.
+///
+/// Entries have the same fields as nginx's, so both are classified and detected alike.
+pub struct ApacheAccessParser {
+    /// Tried in order, the first one that matches wins.
+    formats: Vec<LogFormat>,
+    fields: Vec<Field>,
+}
+
+impl ApacheAccessParser {
+    /// Accepts `common`, `combined` (with or without `%D`), `vhost_combined` and
+    /// `vhost_common` lines. Formats ending in `%T` need `with_format`, the
+    /// number would be read as microseconds.
+    pub fn new() -> Self {
+        Self::with_formats(&[VHOST_COMBINED, COMBINED_TIMED, COMBINED, VHOST_COMMON, COMMON])
+            .expect("built-in formats compile")
+    }
+
+    /// Accepts lines written with `format`, the string of a `LogFormat`
+    /// directive, e.g. `%h %l %u %t "%r" %>s %b %T`.
+    pub fn with_format(format: &str) -> Result<Self, String> {
+        Self::with_formats(&[format])
+    }
+
+    fn with_formats(formats: &[&str]) -> Result<Self, String> {
+        let formats = formats
+            .iter()
+            .map(|format| LogFormat::compile(&tokenize(format)?))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { fields: fields_of(&formats), formats })
+    }
+}
+
+impl Default for ApacheAccessParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogParser for ApacheAccessParser {
+    fn name(&self) -> &'static str {
+        "apache"
+    }
+
+    fn source(&self) -> LogSource {
+        LogSource::ApacheAccess
+    }
+
+    fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        self.formats.iter().any(|format| format.is_match(line))
+    }
+
+    /// Lines that match none of the formats, or whose values do not parse, are rejected.
+    fn parse(&self, line: &str) -> Option<LogEntry> {
+        self.formats.iter().find_map(|format| format.parse(line, LogSource::ApacheAccess))
+    }
+}
+
+/// Splits a `LogFormat` string into literals and variables named like nginx's.
+fn tokenize(format: &str) -> Result<Vec<Token>, String> {
+    // Formats copied from a config file keep their escaped quotes.
+    let format = format.replace("\\\"", "\"");
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            literal.push(c);
+            continue;
+        }
+        if chars.next_if_eq(&'%').is_some() {
+            literal.push('%');
+            continue;
+        }
+
+        // Status conditions such as `%!200,304{Referer}i` and the `<` / `>`
+        // (original / final request) modifiers do not change what is logged.
+        while chars.next_if(|c| matches!(c, '<' | '>' | '!' | ',') || c.is_ascii_digit()).is_some() {}
+        let argument = match chars.next_if_eq(&'{') {
+            Some(_) => {
+                let argument: String = chars.by_ref().take_while(|c| *c != '}').collect();
+                Some(argument)
+            }
+            None => None,
+        };
+        let directive = chars.next().ok_or("'%' without a directive in log format")?;
+
+        let variable = match (directive, argument.as_deref()) {
+            ('h' | 'a', _) => "remote_addr".to_string(),
+            ('l', None) => "remote_ident".to_string(),
+            ('u', None) => "remote_user".to_string(),
+            ('t', None) => {
+                // `%t` includes the brackets: `[10/Oct/2000:13:55:36 -0700]`.
+                literal.push('[');
+                tokens.push(Token::Literal(std::mem::take(&mut literal)));
+                tokens.push(Token::Variable("time_local".to_string()));
+                literal.push(']');
+                continue;
+            }
+            ('r', None) => "request".to_string(),
+            ('s', None) => "status".to_string(),
+            ('b' | 'B', None) => "body_bytes_sent".to_string(),
+            ('O', None) => "bytes_sent".to_string(),
+            ('I', None) => "request_length".to_string(),
+            ('D', None) | ('T', Some("us")) => "request_time_us".to_string(),
+            ('T', Some("ms")) => "request_time_ms".to_string(),
+            ('T', None | Some("s")) => "request_time".to_string(),
+            ('v' | 'V', None) => "server_name".to_string(),
+            ('p', None | Some("canonical" | "local")) => "server_port".to_string(),
+            ('P', None | Some("pid")) => "pid".to_string(),
+            ('m', None) => "request_method".to_string(),
+            ('U', None) => "uri".to_string(),
+            ('q', None) => "query_string".to_string(),
+            ('H', None) => "server_protocol".to_string(),
+            ('k', None) => "connection_requests".to_string(),
+            ('L', None) => "request_id".to_string(),
+            ('i', Some(header)) => format!("http_{}", variable_name(header)),
+            ('o', Some(header)) => format!("sent_http_{}", variable_name(header)),
+            ('C', Some(cookie)) => format!("cookie_{}", variable_name(cookie)),
+            ('e', Some(name)) => format!("env_{}", variable_name(name)),
+            (directive, Some(argument)) => return Err(format!("unsupported directive %{{{}}}{}", argument, directive)),
+            (directive, None) => return Err(format!("unsupported directive %{}", directive)),
+        };
+
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(std::mem::take(&mut literal)));
+        }
+        tokens.push(Token::Variable(variable));
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    Ok(tokens)
+}
+
+/// `User-Agent` -> `user_agent`, the way nginx names header variables.
+fn variable_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' })
+        .collect()
+}
+
+/// Apache 2.4 error log lines in the default `ErrorLogFormat`, e.g.
+/// `[Tue Mar 12 14:56:23.456789 2024] [authz_core:error] [pid 4242:tid 1401] [client 203.0.113.9:51234] AH01630: client denied by server configuration: /var/www/html/admin`.
+///
+/// The module, level, pid and `AH` error code are kept in `extra`.
+pub struct ApacheErrorParser {
+    re: Regex,
+    client: Regex,
+    code: Regex,
+    /// The error log has no offset, times are in the server's timezone.
+    tz: SourceTimezone,
+}
+
+impl ApacheErrorParser {
+    pub fn new() -> Self {
+        Self {
+            re: Regex::new(
+                r"^\[(?P<timestamp>[A-Z][a-z]{2} [A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}(?:\.\d+)? \d{4})\]\s+\[(?:(?P<module>[^:\]]*):)?(?P<level>\w+)\](?:\s+\[pid (?P<pid>\d+)[^\]]*\])?\s+(?P<rest>.*)$",
+            )
+            .unwrap(),
+            client: Regex::new(r"\[client (?P<client>[^\]]+)\]\s*").unwrap(),
+            code: Regex::new(r"^(?:.*?: )?(?P<code>AH\d{5}):").unwrap(),
+            tz: SourceTimezone::Local,
+        }
+    }
+}
+
+impl Default for ApacheErrorParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogParser for ApacheErrorParser {
+    fn name(&self) -> &'static str {
+        "apache_error"
+    }
+
+    fn source(&self) -> LogSource {
+        LogSource::ApacheError
+    }
+
+    fn fields(&self) -> &[Field] {
+        &[Field::IpAddress, Field::Referer, Field::Message]
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        self.re.is_match(line)
+    }
+
+    fn parse(&self, line: &str) -> Option<LogEntry> {
+        let caps = self.re.captures(line)?;
+        let timestamp = parse_local_timestamp(&caps["timestamp"], "%a %b %d %H:%M:%S%.f %Y", self.tz)?;
+        let mut entry = LogEntry::new(LogSource::ApacheError, timestamp, line);
+
+        let mut message = caps["rest"].to_string();
+        if let Some(client) = self.client.captures(&message) {
+            entry.ip_address = Some(client_ip(&client["client"])?.to_string());
+            message.replace_range(client.get(0)?.range(), "");
+        }
+        if let Some((text, referer)) = message.rsplit_once(", referer: ") {
+            entry.referer = Some(referer.to_string());
+            message.truncate(text.len());
+        }
+        if let Some(code) = self.code.captures(&message) {
+            entry.extra.insert("code".to_string(), code["code"].to_string());
+        }
+        entry.message = Some(message);
+
+        for name in ["module", "level", "pid"] {
+            if let Some(value) = caps.name(name) {
+                entry.extra.insert(name.to_string(), value.as_str().to_string());
+            }
+        }
+        Some(entry)
+    }
+
+    fn with_timezone(&self, tz: SourceTimezone) -> Option<Arc<dyn LogParser>> {
+        Some(Arc::new(Self { tz, ..Self::new() }))
+    }
+}
+
+/// `203.0.113.9:51234`, `2001:db8::1:51234` or, from older versions, an address without a port.
+fn client_ip(client: &str) -> Option<IpAddr> {
+    if let Ok(ip) = client.parse() {
+        return Some(ip);
+    }
+    let (ip, _port) = client.rsplit_once(':')?;
+    ip.trim_matches(|c| c == '[' || c == ']').parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_combined_with_microseconds() {
+        let line = r#"203.0.113.9 - bob [12/Mar/2024:14:56:23 +0000] "GET /login HTTP/1.1" 401 381 "-" "Mozilla/5.0" 1500"#;
+        let entry = ApacheAccessParser::new().parse(line).unwrap();
+        assert_eq!(entry.source, LogSource::ApacheAccess);
+        assert_eq!(entry.ip_address, Some("203.0.113.9".to_string()));
+        assert_eq!(entry.user, Some("bob".to_string()));
+        assert_eq!(entry.status_code, Some(401));
+        assert_eq!(entry.bytes_sent, Some(381));
+        assert_eq!(entry.referer, None);
+        assert_eq!(entry.user_agent, Some("Mozilla/5.0".to_string()));
+        assert_eq!(entry.request_time, Some(0.0015));
+    }
+
+    #[test]
+    fn test_parse_vhost_combined() {
+        let line = r#"www.example.com:443 2001:db8::7 - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/2.0" 200 5120 "-" "curl/8.5.0""#;
+        let entry = ApacheAccessParser::new().parse(line).unwrap();
+        assert_eq!(entry.ip_address, Some("2001:db8::7".to_string()));
+        assert_eq!(entry.extra.get("server_name"), Some(&"www.example.com".to_string()));
+        assert_eq!(entry.extra.get("server_port"), Some(&"443".to_string()));
+        assert_eq!(entry.extra.get("bytes_sent"), Some(&"5120".to_string()));
+
+        let line = r#"example.org 10.0.0.2 - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/1.1" 404 209"#;
+        let entry = ApacheAccessParser::new().parse(line).unwrap();
+        assert_eq!(entry.ip_address, Some("10.0.0.2".to_string()));
+        assert_eq!(entry.status_code, Some(404));
+    }
+
+    #[test]
+    fn test_parse_custom_format_with_seconds() {
+        let parser = ApacheAccessParser::with_format(r#"%a %l %u %t \"%r\" %>s %b %T \"%{X-Forwarded-For}i\""#).unwrap();
+        let line = r#"10.0.0.2 - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/1.1" 200 12 3 "198.51.100.4""#;
+        let entry = parser.parse(line).unwrap();
+        assert_eq!(entry.request_time, Some(3.0));
+        assert_eq!(entry.forwarded_for, Some("198.51.100.4".to_string()));
+
+        assert!(ApacheAccessParser::with_format("%h %{%d/%b/%Y}t").is_err());
+        assert!(ApacheAccessParser::with_format("%h %Z %t").is_err());
+    }
+
+    #[test]
+    fn test_rejects_other_lines() {
+        let parser = ApacheAccessParser::new();
+        assert!(parser.parse("").is_none());
+        assert!(parser.parse(r#"203.0.113.9 - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/1.1" abc 12"#).is_none());
+        assert!(!parser.sniff("Mar 12 14:56:23 host sshd[42]: Accepted password for root from 10.0.0.1 port 22 ssh2"));
+    }
+
+    #[test]
+    fn test_parse_error_log() {
+        let parser = ApacheErrorParser::new().with_timezone("UTC".parse().unwrap()).unwrap();
+        let line = "[Tue Mar 12 14:56:23.456789 2024] [authz_core:error] [pid 4242:tid 1401] [client 203.0.113.9:51234] AH01630: client denied by server configuration: /var/www/html/admin, referer: http://example.com/";
+        let entry = parser.parse(line).unwrap();
+
+        assert_eq!(entry.source, LogSource::ApacheError);
+        assert_eq!(entry.timestamp.to_string(), "2024-03-12 14:56:23.456789 UTC");
+        assert_eq!(entry.ip_address, Some("203.0.113.9".to_string()));
+        assert_eq!(entry.referer, Some("http://example.com/".to_string()));
+        assert_eq!(
+            entry.message,
+            Some("AH01630: client denied by server configuration: /var/www/html/admin".to_string())
+        );
+        assert_eq!(entry.extra.get("module"), Some(&"authz_core".to_string()));
+        assert_eq!(entry.extra.get("level"), Some(&"error".to_string()));
+        assert_eq!(entry.extra.get("code"), Some(&"AH01630".to_string()));
+    }
+
+    #[test]
+    fn test_parse_error_log_without_client() {
+        let parser = ApacheErrorParser::new();
+        let line = "[Sun Mar 10 06:25:03.204563 2024] [mpm_event:notice] [pid 1:tid 140] AH00489: Apache/2.4.58 (Unix) configured -- resuming normal operations";
+        let entry = parser.parse(line).unwrap();
+        assert_eq!(entry.ip_address, None);
+        assert_eq!(entry.extra.get("level"), Some(&"notice".to_string()));
+
+        let line = "[Sun Mar 10 06:25:03.204563 2024] [core:error] [pid 7] (13)Permission denied: [client 2001:db8::1:50130] AH00035: access to /index.html denied";
+        let entry = parser.parse(line).unwrap();
+        assert_eq!(entry.ip_address, Some("2001:db8::1".to_string()));
+        assert_eq!(entry.extra.get("code"), Some(&"AH00035".to_string()));
+    }
+
+    #[test]
+    fn test_error_sniff() {
+        let parser = ApacheErrorParser::new();
+        assert!(!parser.sniff(r#"10.0.0.2 - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/1.1" 200 12"#));
+        assert!(parser.sniff("[Sun Mar 10 06:25:03 2024] [notice] Apache configured"));
+    }
+}
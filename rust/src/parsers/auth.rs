@@ -0,0 +1,302 @@
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use regex::Regex;
+
+use crate::models::log::{LogEntry, LogSource};
+use crate::parsers::timestamp::{parse_syslog_timestamp, SourceTimezone};
+use crate::parsers::{Field, LogParser};
+
+/// A classic `Mar  9 12:34:56` or RFC 3339 syslog timestamp.
+const SYSLOG_TIMESTAMP: &str =
+    r"(?:[A-Z][a-z]{2} [ \d]\d \d{2}:\d{2}:\d{2}|\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:\.\d+)?(?:Z|[+-]\d{2}:?\d{2}))";
+
+/// What an sshd log line reports, recorded as `LogEntry::auth_action`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SshAction {
+    Accepted,
+    Failed,
+    /// A login attempt for a user that does not exist.
+    InvalidUser,
+    /// The client used up `MaxAuthTries`.
+    MaxAuthTries,
+    Disconnected,
+    ConnectionClosed,
+}
+
+impl SshAction {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SshAction::Accepted => "Accepted",
+            SshAction::Failed => "Failed",
+            SshAction::InvalidUser => "Invalid user",
+            SshAction::MaxAuthTries => "Max auth attempts",
+            SshAction::Disconnected => "Disconnected",
+            SshAction::ConnectionClosed => "Connection closed",
+        }
+    }
+}
+
+/// The parts of an sshd message that make up an entry.
+#[derive(Debug, PartialEq)]
+struct SshEvent {
+    action: SshAction,
+    user: Option<String>,
+    ip: IpAddr,
+    port: Option<u16>,
+    method: Option<String>,
+    /// The user does not exist on the host.
+    invalid_user: bool,
+    /// Logged before authentication completed, so the client never got in.
+    preauth: bool,
+}
+
+/// sshd authentication events from a syslog-style auth log such as `/var/log/auth.log`.
+///
+/// Other lines, including other sshd messages, are skipped. The sshd PID, the
+/// client port and whether the user is invalid are kept in `extra`.
+pub struct AuthLogParser {
+    /// `Mar  9 12:34:56 host sshd[1042]: message`
+    header: Regex,
+    events: Vec<(SshAction, Regex)>,
+    prefix: Regex,
+    tz: SourceTimezone,
+}
+
+impl AuthLogParser {
+    pub fn new() -> Self {
+        // Usernames are whatever the client sent, so they are matched lazily up to the address.
+        const FROM: &str = r"(?P<ip>[0-9A-Fa-f:.]+)(?: port (?P<port>\d+))?";
+        const USER: &str = r"(?:(?:authenticating |(?P<invalid>invalid) )?user (?P<user>.*?) )?";
+        let events = [
+            (SshAction::Accepted, format!(r"^Accepted (?P<method>[\w/-]+) for (?P<user>.*?) from {}(?: ssh2)?(?:: .*)?$", FROM)),
+            (
+                SshAction::Failed,
+                format!(r"^Failed (?P<method>[\w/-]+) for (?:(?P<invalid>invalid) user )?(?P<user>.*?) from {}(?: ssh2)?(?:: .*)?$", FROM),
+            ),
+            (SshAction::InvalidUser, format!(r"^(?P<invalid>Invalid) user (?P<user>.*?) from {}$", FROM)),
+            (
+                SshAction::MaxAuthTries,
+                format!(
+                    r"^(?:error: )?maximum authentication attempts exceeded for (?:(?P<invalid>invalid) user )?(?P<user>.*?) from {}(?: ssh2)?(?P<preauth> \[preauth\])?$",
+                    FROM
+                ),
+            ),
+            (
+                SshAction::MaxAuthTries,
+                format!(r"^Disconnecting {}{}: Too many authentication failures(?P<preauth> \[preauth\])?$", USER, FROM),
+            ),
+            (SshAction::Disconnected, format!(r"^Disconnected from {}{}(?P<preauth> \[preauth\])?$", USER, FROM)),
+            (
+                SshAction::ConnectionClosed,
+                format!(r"^Connection (?:closed|reset) by {}{}(?P<preauth> \[preauth\])?$", USER, FROM),
+            ),
+        ];
+
+        Self {
+            header: Regex::new(&format!(
+                r"^(?P<timestamp>{}) \S+ sshd(?:-session)?\[(?P<pid>\d+)\]: (?P<message>.*)$",
+                SYSLOG_TIMESTAMP
+            ))
+            .unwrap(),
+            events: events.into_iter().map(|(action, re)| (action, Regex::new(&re).unwrap())).collect(),
+            // `Mar  9 12:34:56 host program[pid]:`
+            prefix: Regex::new(&format!(r"^{} \S+ [^\s:\[]+(\[\d+\])?:", SYSLOG_TIMESTAMP)).unwrap(),
+            tz: SourceTimezone::Local,
+        }
+    }
+
+    fn parse_message(&self, message: &str) -> Option<SshEvent> {
+        self.events.iter().find_map(|(action, re)| {
+            let caps = re.captures(message)?;
+            Some(SshEvent {
+                action: *action,
+                user: caps.name("user").map(|user| user.as_str().to_string()),
+                ip: caps["ip"].parse().ok()?,
+                port: caps.name("port").and_then(|port| port.as_str().parse().ok()),
+                method: caps.name("method").map(|method| method.as_str().to_string()),
+                invalid_user: caps.name("invalid").is_some(),
+                preauth: caps.name("preauth").is_some(),
+            })
+        })
+    }
+}
+
+impl Default for AuthLogParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogParser for AuthLogParser {
+    fn name(&self) -> &'static str {
+        "auth"
+    }
+
+    fn source(&self) -> LogSource {
+        LogSource::AuthLog
+    }
+
+    fn fields(&self) -> &[Field] {
+        &[Field::IpAddress, Field::User, Field::AuthAction, Field::AuthMethod, Field::Success, Field::Message]
+    }
+
+    /// Any syslog line, most lines of an auth log are not sshd logins.
+    fn sniff(&self, line: &str) -> bool {
+        self.prefix.is_match(line)
+    }
+
+    fn parse(&self, line: &str) -> Option<LogEntry> {
+        self.parse_with_reference(line, Utc::now())
+    }
+
+    fn parse_with_reference(&self, line: &str, reference: DateTime<Utc>) -> Option<LogEntry> {
+        let caps = self.header.captures(line)?;
+        let message = &caps["message"];
+        let event = self.parse_message(message)?;
+        let timestamp = parse_syslog_timestamp(&caps["timestamp"], self.tz, reference)?;
+
+        let success = match event.action {
+            SshAction::Accepted => Some(true),
+            SshAction::Failed | SshAction::InvalidUser | SshAction::MaxAuthTries => Some(false),
+            // A client that leaves after logging in is not a failure.
+            SshAction::Disconnected | SshAction::ConnectionClosed => event.preauth.then_some(false),
+        };
+
+        let mut entry = LogEntry {
+            ip_address: Some(event.ip.to_string()),
+            user: event.user,
+            auth_action: Some(event.action.as_str().to_string()),
+            auth_method: event.method,
+            success,
+            message: Some(message.to_string()),
+            ..LogEntry::new(LogSource::AuthLog, timestamp, line)
+        };
+        entry.extra.insert("pid".to_string(), caps["pid"].to_string());
+        if let Some(port) = event.port {
+            entry.extra.insert("port".to_string(), port.to_string());
+        }
+        if event.invalid_user {
+            entry.extra.insert("invalid_user".to_string(), "true".to_string());
+        }
+        Some(entry)
+    }
+
+    fn with_timezone(&self, tz: SourceTimezone) -> Option<Arc<dyn LogParser>> {
+        Some(Arc::new(Self { tz, ..Self::new() }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(message: &str) -> SshEvent {
+        AuthLogParser::new().parse_message(message).unwrap()
+    }
+
+    #[test]
+    fn test_sniff_accepts_syslog_lines() {
+        let parser = AuthLogParser::new();
+        assert!(parser.sniff("Mar  9 12:34:56 web1 sshd[1042]: Failed password for root from 10.0.0.7 port 52144 ssh2"));
+        assert!(parser.sniff("Mar 19 08:00:01 web1 CRON[2211]: pam_unix(cron:session): session opened for user root"));
+        assert!(!parser.sniff(r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/1.1" 200 512"#));
+    }
+
+    #[test]
+    fn test_accepted_logins_keep_the_method() {
+        let accepted = event("Accepted publickey for deploy-bot.ci from 2001:db8::5 port 40022 ssh2: ED25519 SHA256:abc");
+        assert_eq!(accepted.action, SshAction::Accepted);
+        assert_eq!(accepted.user.as_deref(), Some("deploy-bot.ci"));
+        assert_eq!(accepted.ip, "2001:db8::5".parse::<IpAddr>().unwrap());
+        assert_eq!(accepted.port, Some(40022));
+        assert_eq!(accepted.method.as_deref(), Some("publickey"));
+
+        let accepted = event("Accepted keyboard-interactive/pam for alice from 10.0.0.7 port 52144 ssh2");
+        assert_eq!(accepted.method.as_deref(), Some("keyboard-interactive/pam"));
+        assert_eq!(accepted.user.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn test_failed_logins_and_invalid_users() {
+        let failed = event("Failed password for invalid user j.doe from 203.0.113.9 port 2222 ssh2");
+        assert_eq!(failed.action, SshAction::Failed);
+        assert_eq!(failed.user.as_deref(), Some("j.doe"));
+        assert!(failed.invalid_user);
+
+        let failed = event("Failed none for root from 203.0.113.9 port 2222 ssh2");
+        assert_eq!(failed.method.as_deref(), Some("none"));
+        assert!(!failed.invalid_user);
+
+        let invalid = event("Invalid user admin from 203.0.113.9 port 2222");
+        assert_eq!(invalid.action, SshAction::InvalidUser);
+        assert_eq!(invalid.user.as_deref(), Some("admin"));
+        assert!(invalid.invalid_user);
+    }
+
+    #[test]
+    fn test_preauth_disconnects() {
+        let max = event("error: maximum authentication attempts exceeded for root from 203.0.113.9 port 2222 ssh2 [preauth]");
+        assert_eq!(max.action, SshAction::MaxAuthTries);
+        assert!(max.preauth);
+
+        let max = event("Disconnecting invalid user oracle 203.0.113.9 port 2222: Too many authentication failures [preauth]");
+        assert_eq!(max.action, SshAction::MaxAuthTries);
+        assert_eq!(max.user.as_deref(), Some("oracle"));
+
+        let disconnected = event("Disconnected from authenticating user root 203.0.113.9 port 2222 [preauth]");
+        assert_eq!(disconnected.action, SshAction::Disconnected);
+        assert_eq!(disconnected.user.as_deref(), Some("root"));
+        assert!(disconnected.preauth);
+
+        let closed = event("Connection closed by 203.0.113.9 port 2222 [preauth]");
+        assert_eq!(closed.action, SshAction::ConnectionClosed);
+        assert_eq!(closed.user, None);
+        assert!(closed.preauth);
+
+        let closed = event("Connection closed by invalid user test 2001:db8::9 port 2222 [preauth]");
+        assert_eq!(closed.user.as_deref(), Some("test"));
+        assert!(closed.invalid_user);
+
+        let logout = event("Disconnected from user alice 10.0.0.7 port 52144");
+        assert!(!logout.preauth);
+    }
+
+    #[test]
+    fn test_parse_infers_year_and_timezone() {
+        let parser = AuthLogParser::new().with_timezone("America/New_York".parse().unwrap()).unwrap();
+        let reference = DateTime::parse_from_rfc3339("2025-01-02T00:00:00Z").unwrap().to_utc();
+        let entry = parser
+            .parse_with_reference("Dec 31 19:30:00 web1 sshd[1042]: Failed password for root from 10.0.0.7 port 52144 ssh2", reference)
+            .unwrap();
+
+        assert_eq!(entry.timestamp.to_rfc3339(), "2025-01-01T00:30:00+00:00");
+        assert_eq!(entry.auth_action.as_deref(), Some("Failed"));
+        assert_eq!(entry.auth_method.as_deref(), Some("password"));
+        assert_eq!(entry.success, Some(false));
+        assert_eq!(entry.extra.get("pid"), Some(&"1042".to_string()));
+        assert_eq!(entry.extra.get("port"), Some(&"52144".to_string()));
+    }
+
+    #[test]
+    fn test_parse_rfc3339_header() {
+        let line = "2024-03-09T12:34:56.123456+01:00 web1 sshd[1042]: Accepted publickey for alice from 10.0.0.7 port 52144 ssh2";
+        let parser = AuthLogParser::new();
+        assert!(parser.sniff(line));
+
+        let entry = parser.parse(line).unwrap();
+        assert_eq!(entry.timestamp.to_rfc3339(), "2024-03-09T11:34:56.123456+00:00");
+        assert_eq!(entry.success, Some(true));
+    }
+
+    #[test]
+    fn test_other_messages_are_skipped() {
+        let parser = AuthLogParser::new();
+        assert!(parser.parse_message("Server listening on 0.0.0.0 port 22.").is_none());
+        assert!(parser.parse_message("pam_unix(sshd:session): session opened for user root(uid=0) by (uid=0)").is_none());
+        assert!(parser
+            .parse("Mar 19 08:00:01 web1 CRON[2211]: Failed password for root from 10.0.0.7 port 52144 ssh2")
+            .is_none());
+    }
+}
@@ -0,0 +1,228 @@
+use std::net::IpAddr;
+
+use chrono::{DateTime, Utc};
+use regex::{Captures, Regex};
+
+use crate::models::log::{LogEntry, LogSource};
+use crate::parsers::Field;
+
+/// Part of a log format. Variables use nginx's names, e.g. `remote_addr` or
+/// `http_user_agent`, whatever syntax the server's own format uses.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Literal(String),
+    Variable(String),
+}
+
+/// Variables that can provide the entry's timestamp.
+const TIME_VARIABLES: [&str; 3] = ["time_local", "time_iso8601", "msec"];
+
+/// An access log format compiled into an anchored regex with one group per variable.
+pub struct LogFormat {
+    re: Regex,
+    variables: Vec<String>,
+}
+
+impl LogFormat {
+    pub fn compile(tokens: &[Token]) -> Result<Self, String> {
+        let mut pattern = String::from(r"^\s*");
+        let mut variables: Vec<String> = Vec::new();
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                Token::Literal(literal) => {
+                    // Any run of whitespace matches any other, configs are often aligned by hand.
+                    let mut spaces = false;
+                    for c in literal.chars() {
+                        if c.is_whitespace() {
+                            if !spaces {
+                                pattern.push_str(r"\s+");
+                            }
+                            spaces = true;
+                        } else {
+                            pattern.push_str(&regex::escape(&c.to_string()));
+                            spaces = false;
+                        }
+                    }
+                }
+                Token::Variable(name) => {
+                    let next = match tokens.get(i + 1) {
+                        Some(Token::Literal(literal)) => literal.chars().next(),
+                        Some(Token::Variable(next)) => {
+                            return Err(format!("{} directly follows {}, they cannot be told apart", next, name));
+                        }
+                        None => None,
+                    };
+                    let value = value_pattern(name, next);
+                    // A variable logged twice must only be captured once.
+                    if variables.contains(name) {
+                        pattern.push_str(&format!("(?:{})", value));
+                    } else {
+                        pattern.push_str(&format!("(?P<{}>{})", name, value));
+                        variables.push(name.clone());
+                    }
+                }
+            }
+        }
+        pattern.push_str(r"\s*$");
+
+        if !variables.iter().any(|variable| TIME_VARIABLES.contains(&variable.as_str())) {
+            return Err(format!("log format has no timestamp, it needs one of {}", TIME_VARIABLES.join(", ")));
+        }
+        let re = Regex::new(&pattern).map_err(|e| format!("cannot compile log format: {}", e))?;
+        Ok(Self { re, variables })
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        self.re.is_match(line)
+    }
+
+    /// Rejects lines that do not match or whose values do not parse.
+    pub fn parse(&self, line: &str, source: LogSource) -> Option<LogEntry> {
+        let caps = self.re.captures(line)?;
+        let mut entry = LogEntry::new(source, self.timestamp(&caps)?, line);
+
+        for variable in &self.variables {
+            let value = &caps[variable.as_str()];
+            // Unset values are logged as `-`.
+            if value == "-" || value.is_empty() || TIME_VARIABLES.contains(&variable.as_str()) {
+                continue;
+            }
+
+            match variable.as_str() {
+                "remote_addr" => entry.ip_address = Some(value.parse::<IpAddr>().ok()?.to_string()),
+                "remote_user" => entry.user = Some(value.to_string()),
+                "request" => entry.request = Some(value.to_string()),
+                "status" => entry.status_code = Some(value.parse().ok()?),
+                "body_bytes_sent" => entry.bytes_sent = Some(value.parse().ok()?),
+                "http_referer" => entry.referer = Some(value.to_string()),
+                "http_user_agent" => entry.user_agent = Some(value.to_string()),
+                "request_time" => entry.request_time = Some(value.parse().ok()?),
+                "request_time_ms" => entry.request_time = Some(value.parse::<f64>().ok()? / 1e3),
+                "request_time_us" => entry.request_time = Some(value.parse::<f64>().ok()? / 1e6),
+                "upstream_response_time" => entry.upstream_response_time = Some(upstream_total(value)?),
+                "http_x_forwarded_for" => entry.forwarded_for = Some(value.to_string()),
+                _ => {
+                    entry.extra.insert(variable.clone(), value.to_string());
+                }
+            }
+        }
+        Some(entry)
+    }
+
+    /// The first time variable in the format that parses.
+    fn timestamp(&self, caps: &Captures) -> Option<DateTime<Utc>> {
+        self.variables.iter().find_map(|variable| {
+            let value = &caps[variable.as_str()];
+            match variable.as_str() {
+                "time_local" => DateTime::parse_from_str(value, "%d/%b/%Y:%H:%M:%S %z").ok().map(|dt| dt.to_utc()),
+                "time_iso8601" => DateTime::parse_from_rfc3339(value).ok().map(|dt| dt.to_utc()),
+                "msec" => {
+                    let millis = (value.parse::<f64>().ok()? * 1000.0).round() as i64;
+                    DateTime::from_timestamp_millis(millis)
+                }
+                _ => None,
+            }
+        })
+    }
+}
+
+/// Fields set by any of `formats`, for `LogParser::fields`.
+pub fn fields_of(formats: &[LogFormat]) -> Vec<Field> {
+    let mut fields = Vec::new();
+    for field in formats.iter().flat_map(|format| format.variables.iter().filter_map(|variable| field_of(variable))) {
+        if !fields.contains(&field) {
+            fields.push(field);
+        }
+    }
+    fields
+}
+
+/// The `LogEntry` field a variable fills, `None` for variables kept in `extra`.
+fn field_of(variable: &str) -> Option<Field> {
+    Some(match variable {
+        "remote_addr" => Field::IpAddress,
+        "remote_user" => Field::User,
+        "request" => Field::Request,
+        "status" => Field::StatusCode,
+        "body_bytes_sent" => Field::BytesSent,
+        "http_referer" => Field::Referer,
+        "http_user_agent" => Field::UserAgent,
+        "request_time" | "request_time_ms" | "request_time_us" => Field::RequestTime,
+        "upstream_response_time" => Field::UpstreamResponseTime,
+        "http_x_forwarded_for" => Field::ForwardedFor,
+        _ => return None,
+    })
+}
+
+/// The regex for a variable's value. `next` is the character logged right after it,
+/// which ends values the server does not constrain, such as `request` or headers.
+fn value_pattern(name: &str, next: Option<char>) -> String {
+    const TIMINGS: &str = r"(?:[\d.]+|-)(?:(?:, | : )(?:[\d.]+|-))*";
+
+    let known = match name {
+        "remote_addr" | "realip_remote_addr" => r"[0-9A-Fa-f:.]+",
+        "time_local" => r"\d{2}/[A-Za-z]{3}/\d{4}:\d{2}:\d{2}:\d{2} [+-]\d{4}",
+        "time_iso8601" => r"\d{4}-\d{2}-\d{2}T\d{2}:\d{2}:\d{2}(?:Z|[+-]\d{2}:\d{2})",
+        "status" => r"\d{3}",
+        "body_bytes_sent" | "bytes_sent" | "request_length" | "connection" | "connection_requests" => r"\d+|-",
+        "server_name" | "host" => r"[^\s:]*",
+        "server_port" | "pid" | "request_time_ms" | "request_time_us" => r"\d+|-",
+        "request_time" | "msec" => r"[\d.]+|-",
+        _ if name.starts_with("upstream_") && name.ends_with("_time") => TIMINGS,
+        _ => "",
+    };
+    if !known.is_empty() {
+        return known.to_string();
+    }
+
+    match next {
+        Some(c) if !c.is_whitespace() => format!("[^{}]*", regex::escape(&c.to_string())),
+        _ => r"\S*".to_string(),
+    }
+}
+
+/// Total of an upstream timing list such as `0.004, 0.012 : 0.001`, which nginx
+/// logs when a request was passed to several servers. Unknown (`-`) entries count as 0.
+fn upstream_total(value: &str) -> Option<f64> {
+    value
+        .split([',', ':'])
+        .map(str::trim)
+        .filter(|time| *time != "-")
+        .map(|time| time.parse::<f64>().ok())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variable(name: &str) -> Token {
+        Token::Variable(name.to_string())
+    }
+
+    fn literal(text: &str) -> Token {
+        Token::Literal(text.to_string())
+    }
+
+    #[test]
+    fn test_compile_requires_a_timestamp_and_separators() {
+        assert!(LogFormat::compile(&[variable("remote_addr"), literal(" "), variable("status")]).is_err());
+        assert!(LogFormat::compile(&[variable("time_local"), variable("status")]).is_err());
+        assert!(LogFormat::compile(&[variable("time_iso8601"), literal(" "), variable("status")]).is_ok());
+    }
+
+    #[test]
+    fn test_request_time_units() {
+        let format = LogFormat::compile(&[variable("msec"), literal(" "), variable("request_time_us")]).unwrap();
+        let entry = format.parse("1710255383.250 1500000", LogSource::NginxAccess).unwrap();
+        assert_eq!(entry.timestamp.timestamp_millis(), 1710255383250);
+        assert_eq!(entry.request_time, Some(1.5));
+    }
+
+    #[test]
+    fn test_upstream_total() {
+        assert_eq!(upstream_total("0.004"), Some(0.004));
+        assert_eq!(upstream_total("0.5, - : 0.25"), Some(0.75));
+        assert_eq!(upstream_total("abc"), None);
+    }
+}
@@ -0,0 +1,184 @@
+use std::env;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+
+use crate::models::log::{LogEntry, LogSource};
+use crate::parsers::timestamp::SourceTimezone;
+
+pub mod apache;
+pub mod auth;
+pub mod format;
+pub mod nginx;
+pub mod registry;
+pub mod timestamp;
+
+/// Optional `LogEntry` fields. Every entry has a timestamp, source and raw line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Field {
+    IpAddress,
+    User,
+    Request,
+    StatusCode,
+    UserAgent,
+    AuthAction,
+    AuthMethod,
+    Success,
+    BytesSent,
+    Referer,
+    RequestTime,
+    UpstreamResponseTime,
+    ForwardedFor,
+    Message,
+}
+
+/// Turns the lines of one log format into `LogEntry`s.
+///
+/// Implement this and add the parser to a `ParserRegistry` to support a new format.
+pub trait LogParser: Send + Sync {
+    /// Unique name used to select the parser in `LOG_SOURCES`, e.g. `nginx`.
+    fn name(&self) -> &'static str;
+
+    /// Source recorded on the entries, which decides how they are classified.
+    fn source(&self) -> LogSource;
+
+    /// Fields this parser sets on the entries it returns.
+    fn fields(&self) -> &[Field];
+
+    /// Cheap check whether `line` looks like this format, used for auto-detection.
+    /// It may accept lines `parse` later rejects.
+    fn sniff(&self, line: &str) -> bool;
+
+    /// Parses one line without its trailing newline. Lines that are not log
+    /// entries of interest return `None`.
+    fn parse(&self, line: &str) -> Option<LogEntry>;
+
+    /// Like `parse` for a line read from a file. `reference` is a time the line
+    /// cannot be later than, the file's modification time when it was read, so
+    /// formats without a year in their timestamps can infer it. `parse` uses now.
+    fn parse_with_reference(&self, line: &str, reference: DateTime<Utc>) -> Option<LogEntry> {
+        let _ = reference;
+        self.parse(line)
+    }
+
+    /// A copy of this parser that reads timestamps without an offset in `tz`.
+    /// Formats whose timestamps carry their offset return `None`.
+    fn with_timezone(&self, tz: SourceTimezone) -> Option<Arc<dyn LogParser>> {
+        let _ = tz;
+        None
+    }
+}
+
+/// A file to ingest and the name of its parser, `None` to detect the format
+/// from the first lines of the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceConfig {
+    pub path: String,
+    pub parser: Option<String>,
+    /// Timezone of timestamps without an offset, `None` for the host's.
+    pub timezone: Option<SourceTimezone>,
+}
+
+impl FromStr for SourceConfig {
+    type Err = String;
+
+    /// Parses `path=parser@timezone`, where a missing parser or `auto` means
+    /// auto-detection and the timezone is optional, e.g. `/var/log/secure=auto@UTC`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, parser) = match s.rsplit_once('=') {
+            Some((path, parser)) => (path.trim(), Some(parser.trim())),
+            None => (s.trim(), None),
+        };
+        if path.is_empty() {
+            return Err(format!("missing path in log source: {}", s));
+        }
+
+        let (parser, timezone) = match parser.and_then(|parser| parser.split_once('@')) {
+            Some((parser, timezone)) => (Some(parser.trim()), Some(timezone.trim().parse()?)),
+            None => (parser, None),
+        };
+        let parser = parser.filter(|parser| !parser.is_empty() && *parser != "auto");
+        Ok(Self { path: path.to_string(), parser: parser.map(str::to_string), timezone })
+    }
+}
+
+/// Files to ingest, from `LOG_SOURCES`, a comma-separated list of `path=parser`
+/// entries such as `/var/log/nginx/access.log=nginx,/var/log/secure=auto@UTC`.
+///
+/// Without it, `NGINX_LOG_PATH` and `AUTH_LOG_PATH` are read with the `nginx`
+/// and `auth` parsers. `LOG_TIMEZONE` sets the timezone of sources that do not
+/// name one.
+pub fn source_configs() -> Vec<SourceConfig> {
+    let default_timezone = env::var("LOG_TIMEZONE").ok().and_then(|tz| match tz.parse() {
+        Ok(tz) => Some(tz),
+        Err(e) => {
+            tracing::warn!("Ignoring LOG_TIMEZONE: {}", e);
+            None
+        }
+    });
+
+    let sources = match env::var("LOG_SOURCES") {
+        Ok(sources) => sources
+            .split(',')
+            .filter(|source| !source.trim().is_empty())
+            .filter_map(|source| match source.parse() {
+                Ok(source) => Some(source),
+                Err(e) => {
+                    tracing::warn!("Ignoring log source: {}", e);
+                    None
+                }
+            })
+            .collect(),
+        Err(_) => {
+            let nginx_log_path = env::var("NGINX_LOG_PATH").unwrap_or_else(|_| "/var/log/nginx/access.log".to_string());
+            let auth_log_path = env::var("AUTH_LOG_PATH").unwrap_or_else(|_| "/var/log/auth.log".to_string());
+            vec![
+                SourceConfig { path: nginx_log_path, parser: Some("nginx".to_string()), timezone: None },
+                SourceConfig { path: auth_log_path, parser: Some("auth".to_string()), timezone: None },
+            ]
+        }
+    };
+
+    sources
+        .into_iter()
+        .map(|source| SourceConfig { timezone: source.timezone.or(default_timezone), ..source })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_config_parsing() {
+        assert_eq!(
+            "/var/log/nginx/access.log=nginx".parse(),
+            Ok(SourceConfig {
+                path: "/var/log/nginx/access.log".to_string(),
+                parser: Some("nginx".to_string()),
+                timezone: None,
+            })
+        );
+        assert_eq!(
+            " /var/log/secure = auto ".parse(),
+            Ok(SourceConfig { path: "/var/log/secure".to_string(), parser: None, timezone: None })
+        );
+        assert_eq!(
+            "/var/log/messages".parse(),
+            Ok(SourceConfig { path: "/var/log/messages".to_string(), parser: None, timezone: None })
+        );
+        assert_eq!(
+            "/var/log/auth.log=auth@Europe/Berlin".parse(),
+            Ok(SourceConfig {
+                path: "/var/log/auth.log".to_string(),
+                parser: Some("auth".to_string()),
+                timezone: Some(SourceTimezone::Named(chrono_tz::Europe::Berlin)),
+            })
+        );
+        assert!("=nginx".parse::<SourceConfig>().is_err());
+        assert!("/var/log/auth.log=auth@Nowhere".parse::<SourceConfig>().is_err());
+    }
+}
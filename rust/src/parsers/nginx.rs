@@ -0,0 +1,229 @@
+use crate::models::log::{LogEntry, LogSource};
+use crate::parsers::format::{fields_of, LogFormat, Token};
+use crate::parsers::{Field, LogParser};
+
+/// nginx's predefined `combined` format.
+pub const COMBINED: &str =
+    r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent "$http_referer" "$http_user_agent""#;
+
+/// The Common Log Format, `combined` without referer and user agent.
+pub const COMMON: &str = r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent"#;
+
+/// nginx access log lines in `combined`, `common` or a custom `log_format`.
+pub struct NginxParser {
+    /// Tried in order, the first one that matches wins.
+    formats: Vec<LogFormat>,
+    fields: Vec<Field>,
+}
+
+impl NginxParser {
+    /// Accepts `combined` and `common` lines.
+    pub fn new() -> Self {
+        Self::with_formats(&[COMBINED, COMMON]).expect("built-in formats compile")
+    }
+
+    /// Accepts lines written with `format`, the string of an nginx `log_format`
+    /// directive without the name, e.g.
+    /// `$remote_addr [$time_local] "$request" $status $request_time`.
+    pub fn with_format(format: &str) -> Result<Self, String> {
+        Self::with_formats(&[format])
+    }
+
+    fn with_formats(formats: &[&str]) -> Result<Self, String> {
+        let formats = formats
+            .iter()
+            .map(|format| LogFormat::compile(&tokenize(format)?))
+            .collect::<Result<Vec<_>, String>>()?;
+        Ok(Self { fields: fields_of(&formats), formats })
+    }
+}
+
+impl Default for NginxParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LogParser for NginxParser {
+    fn name(&self) -> &'static str {
+        "nginx"
+    }
+
+    fn source(&self) -> LogSource {
+        LogSource::NginxAccess
+    }
+
+    fn fields(&self) -> &[Field] {
+        &self.fields
+    }
+
+    fn sniff(&self, line: &str) -> bool {
+        self.formats.iter().any(|format| format.is_match(line))
+    }
+
+    /// Lines that match none of the formats, or whose values do not parse, are rejected.
+    fn parse(&self, line: &str) -> Option<LogEntry> {
+        self.formats.iter().find_map(|format| format.parse(line, LogSource::NginxAccess))
+    }
+}
+
+/// Splits a format into literals and `$name` / `${name}` variables.
+fn tokenize(format: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = format.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        if c != '$' {
+            literal.push(c);
+            continue;
+        }
+
+        let braced = chars.next_if_eq(&'{').is_some();
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+            name.push(c);
+        }
+        if braced && chars.next_if_eq(&'}').is_none() {
+            return Err(format!("unterminated ${{{} in log format", name));
+        }
+        if name.is_empty() {
+            return Err("'$' without a variable name in log format".to_string());
+        }
+
+        if !literal.is_empty() {
+            tokens.push(Token::Literal(std::mem::take(&mut literal)));
+        }
+        tokens.push(Token::Variable(name));
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_valid_nginx_log() {
+        let log_entry = r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET /index.html HTTP/1.1" 200 512"#;
+        let parsed = NginxParser::new().parse(log_entry);
+
+        let parsed = parsed.unwrap();
+        assert_eq!(parsed.ip_address, Some("192.168.1.1".to_string()));
+        assert_eq!(parsed.request, Some("GET /index.html HTTP/1.1".to_string()));
+        assert_eq!(parsed.timestamp.to_string(), "2024-03-12 14:56:23 UTC".to_string());
+        assert_eq!(parsed.status_code, Some(200));
+        assert_eq!(parsed.bytes_sent, Some(512));
+    }
+
+    #[test]
+    fn test_parse_log_with_missing_fields() {
+        // Without a status the line is neither `combined` nor `common`.
+        let log_entry = r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET /index.html HTTP/1.1""#;
+        assert!(NginxParser::new().parse(log_entry).is_none());
+
+        let log_entry = r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET /index.html HTTP/1.1" 304 - "-" "-""#;
+        let result = NginxParser::new().parse(log_entry).unwrap();
+        assert_eq!(result.status_code, Some(304));
+        assert_eq!(result.bytes_sent, None);
+        assert_eq!(result.referer, None);
+        assert_eq!(result.user_agent, None);
+    }
+
+    #[test]
+    fn test_parse_log_with_invalid_status_code() {
+        let log_entry = r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET /index.html HTTP/1.1" XYZ 512"#;
+        assert!(NginxParser::new().parse(log_entry).is_none());
+    }
+
+    #[test]
+    fn test_parse_log_with_extra_spaces() {
+        let log_entry = r#"   192.168.1.1    - -   [12/Mar/2024:14:56:23 +0000]  "GET   /index.html HTTP/1.1"   200   512 "#;
+        let parsed = NginxParser::new().parse(log_entry);
+        let parsed = parsed.unwrap();
+        assert_eq!(parsed.ip_address, Some("192.168.1.1".to_string()));
+        assert_eq!(parsed.request, Some("GET   /index.html HTTP/1.1".to_string()));
+        assert_eq!(parsed.timestamp.to_string(), "2024-03-12 14:56:23 UTC".to_string());
+        assert_eq!(parsed.status_code, Some(200));
+    }
+
+    #[test]
+    fn test_parse_combined_with_ipv6_and_user() {
+        let log_entry = r#"2001:db8::1 - alice [12/Mar/2024:16:56:23 +0200] "GET /admin HTTP/2.0" 403 153 "https://example.com/" "curl/8.5.0""#;
+        let parsed = NginxParser::new().parse(log_entry).unwrap();
+        assert_eq!(parsed.ip_address, Some("2001:db8::1".to_string()));
+        assert_eq!(parsed.user, Some("alice".to_string()));
+        assert_eq!(parsed.timestamp.to_string(), "2024-03-12 14:56:23 UTC".to_string());
+        assert_eq!(parsed.referer, Some("https://example.com/".to_string()));
+        assert_eq!(parsed.user_agent, Some("curl/8.5.0".to_string()));
+    }
+
+    #[test]
+    fn test_parse_custom_format() {
+        let parser = NginxParser::with_format(
+            r#"$remote_addr - $remote_user [$time_local] "$request" $status $body_bytes_sent rt=$request_time uct="$upstream_connect_time" urt="$upstream_response_time" "${http_x_forwarded_for}""#,
+        )
+        .unwrap();
+        let log_entry = r#"10.0.0.2 - - [12/Mar/2024:15:10:45 +0000] "POST /api/data HTTP/1.1" 502 0 rt=1.250 uct="0.001, 0.002" urt="0.500, 0.250 : 0.125" "203.0.113.7, 10.0.0.1""#;
+        let parsed = parser.parse(log_entry).unwrap();
+
+        assert_eq!(parsed.status_code, Some(502));
+        assert_eq!(parsed.bytes_sent, Some(0));
+        assert_eq!(parsed.request_time, Some(1.25));
+        assert_eq!(parsed.upstream_response_time, Some(0.875));
+        assert_eq!(parsed.forwarded_for, Some("203.0.113.7, 10.0.0.1".to_string()));
+        assert_eq!(parsed.extra.get("upstream_connect_time"), Some(&"0.001, 0.002".to_string()));
+        assert!(parser.fields().contains(&Field::ForwardedFor));
+
+        // A `combined` line does not match the custom format.
+        assert!(parser.parse(r#"10.0.0.2 - - [12/Mar/2024:15:10:45 +0000] "GET / HTTP/1.1" 200 1 "-" "-""#).is_none());
+    }
+
+    #[test]
+    fn test_parse_iso8601_format() {
+        let parser = NginxParser::with_format("$time_iso8601 $remote_addr $status \"$request\"").unwrap();
+        let parsed = parser.parse(r#"2024-03-12T16:56:23+02:00 ::1 404 "GET /x HTTP/1.1""#).unwrap();
+        assert_eq!(parsed.timestamp.to_string(), "2024-03-12 14:56:23 UTC".to_string());
+        assert_eq!(parsed.ip_address, Some("::1".to_string()));
+    }
+
+    #[test]
+    fn test_invalid_formats_are_rejected() {
+        assert!(NginxParser::with_format(r#"$remote_addr "$request""#).is_err());
+        assert!(NginxParser::with_format("${time_local").is_err());
+        assert!(NginxParser::with_format("$time_local$status").is_err());
+    }
+
+    #[test]
+    fn test_sniff_accepts_access_log_lines_only() {
+        let parser = NginxParser::new();
+        assert!(parser.sniff(r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET /index.html HTTP/1.1" 200 512"#));
+        assert!(!parser.sniff("Mar 12 14:56:23 host sshd[42]: Accepted password for root from 10.0.0.1 port 22 ssh2"));
+    }
+
+    #[test]
+    fn test_parse_log_with_empty_line() {
+        let log_entry = "";
+        let result = NginxParser::new().parse(log_entry);
+
+        assert!(result.is_none()); // empty line should return None
+    }
+
+    #[test]
+    fn test_parse_multiple_log_entries() {
+        let logs = vec![
+            r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET /index.html HTTP/1.1" 200 512"#,
+            r#"10.0.0.2 - - [12/Mar/2024:15:10:45 +0000] "POST /api/data HTTP/1.1" 201 1024"#,
+        ];
+
+        for log in logs {
+            let parsed = NginxParser::new().parse(log);
+            let parsed = parsed.unwrap();
+            assert!(parsed.ip_address.as_ref().is_some_and(|ip| !ip.is_empty()));
+            assert!(parsed.status_code.unwrap_or(0) > 0);
+        }
+    }
+}
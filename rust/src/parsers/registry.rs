@@ -0,0 +1,193 @@
+use std::env;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::parsers::apache::{ApacheAccessParser, ApacheErrorParser};
+use crate::parsers::auth::AuthLogParser;
+use crate::parsers::nginx::NginxParser;
+use crate::parsers::{LogParser, SourceConfig};
+
+/// Lines read from the start of a file to detect its format.
+pub const SAMPLE_LINES: usize = 50;
+
+/// Known parsers by name. `ParserRegistry::default()` holds the built-in formats.
+pub struct ParserRegistry {
+    parsers: Vec<Arc<dyn LogParser>>,
+}
+
+impl ParserRegistry {
+    /// A registry without any parsers.
+    pub fn new() -> Self {
+        Self { parsers: Vec::new() }
+    }
+
+    /// Adds `parser`, replacing a registered parser with the same name.
+    pub fn register(&mut self, parser: impl LogParser + 'static) {
+        self.parsers.retain(|registered| registered.name() != parser.name());
+        self.parsers.push(Arc::new(parser));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<dyn LogParser>> {
+        self.parsers.iter().find(|parser| parser.name() == name).cloned()
+    }
+
+    pub fn names(&self) -> Vec<&'static str> {
+        self.parsers.iter().map(|parser| parser.name()).collect()
+    }
+
+    /// Picks the parser that sniffs the most of `lines`. At least half of the
+    /// non-empty lines must match, and on a tie the parser registered first wins.
+    pub fn detect<'a>(&self, lines: impl IntoIterator<Item = &'a str>) -> Option<Arc<dyn LogParser>> {
+        let lines: Vec<&str> = lines.into_iter().filter(|line| !line.trim().is_empty()).collect();
+        if lines.is_empty() {
+            return None;
+        }
+
+        let (best, matched) = self
+            .parsers
+            .iter()
+            .map(|parser| (parser, lines.iter().filter(|line| parser.sniff(line)).count()))
+            .rev()
+            .max_by_key(|(_, matched)| *matched)?;
+        (matched * 2 >= lines.len() && matched > 0).then(|| best.clone())
+    }
+
+    /// Detects the format of `path` from its first `SAMPLE_LINES` lines.
+    pub fn detect_file(&self, path: &Path) -> io::Result<Option<Arc<dyn LogParser>>> {
+        let reader = BufReader::new(File::open(path)?);
+        let lines: Vec<String> = reader.lines().take(SAMPLE_LINES).collect::<Result<_, _>>()?;
+        Ok(self.detect(lines.iter().map(String::as_str)))
+    }
+
+    /// The parser configured for `source`, detected from the file when none is
+    /// named, and set to the source's timezone.
+    pub fn resolve(&self, source: &SourceConfig) -> Result<Arc<dyn LogParser>, String> {
+        let parser = self.find(source)?;
+        Ok(match source.timezone {
+            Some(tz) => parser.with_timezone(tz).unwrap_or(parser),
+            None => parser,
+        })
+    }
+
+    fn find(&self, source: &SourceConfig) -> Result<Arc<dyn LogParser>, String> {
+        match &source.parser {
+            Some(name) => self.get(name).ok_or_else(|| {
+                format!("unknown parser '{}', expected one of {}", name, self.names().join(", "))
+            }),
+            None => match self.detect_file(Path::new(&source.path)) {
+                Ok(Some(parser)) => Ok(parser),
+                Ok(None) => Err("cannot detect the log format, name a parser in LOG_SOURCES".to_string()),
+                Err(e) => Err(format!("cannot read a sample to detect the log format: {}", e)),
+            },
+        }
+    }
+}
+
+impl ParserRegistry {
+    /// The built-in parsers, with the nginx and Apache access log parsers reading
+    /// the format in `NGINX_LOG_FORMAT` / `APACHE_LOG_FORMAT` instead of their
+    /// defaults when set.
+    pub fn from_env() -> Result<Self, String> {
+        let mut registry = Self::default();
+        if let Ok(format) = env::var("NGINX_LOG_FORMAT") {
+            let parser = NginxParser::with_format(&format).map_err(|e| format!("invalid NGINX_LOG_FORMAT: {}", e))?;
+            registry.register(parser);
+        }
+        if let Ok(format) = env::var("APACHE_LOG_FORMAT") {
+            let parser =
+                ApacheAccessParser::with_format(&format).map_err(|e| format!("invalid APACHE_LOG_FORMAT: {}", e))?;
+            registry.register(parser);
+        }
+        Ok(registry)
+    }
+}
+
+impl Default for ParserRegistry {
+    fn default() -> Self {
+        let mut registry = Self::new();
+        registry.register(NginxParser::new());
+        registry.register(AuthLogParser::new());
+        registry.register(ApacheAccessParser::new());
+        registry.register(ApacheErrorParser::new());
+        registry
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::log::{LogEntry, LogSource};
+    use crate::parsers::Field;
+
+    const NGINX: &str = r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET /index.html HTTP/1.1" 200 512"#;
+    const AUTH: &str = "Mar  9 12:34:56 web1 sshd[1042]: Failed password for root from 10.0.0.7 port 52144 ssh2";
+
+    /// Accepts every line, to check that registering replaces by name.
+    struct Everything(&'static str);
+
+    impl LogParser for Everything {
+        fn name(&self) -> &'static str {
+            self.0
+        }
+
+        fn source(&self) -> LogSource {
+            LogSource::NginxAccess
+        }
+
+        fn fields(&self) -> &[Field] {
+            &[]
+        }
+
+        fn sniff(&self, _line: &str) -> bool {
+            true
+        }
+
+        fn parse(&self, _line: &str) -> Option<LogEntry> {
+            None
+        }
+    }
+
+    #[test]
+    fn test_detects_format_from_sample_lines() {
+        let registry = ParserRegistry::default();
+        assert_eq!(registry.detect([NGINX, NGINX, ""]).unwrap().name(), "nginx");
+        assert_eq!(registry.detect([AUTH, NGINX, AUTH]).unwrap().name(), "auth");
+        assert!(registry.detect(["garbage", "more garbage", NGINX]).is_none());
+        assert!(registry.detect([""]).is_none());
+
+        // Plain `combined` lines are the same in both servers, Apache's own formats are not.
+        let timed = r#"192.168.1.1 - - [12/Mar/2024:14:56:23 +0000] "GET / HTTP/1.1" 200 512 "-" "curl/8.5.0" 1500"#;
+        assert_eq!(registry.detect([timed, NGINX]).unwrap().name(), "apache");
+        let error = "[Tue Mar 12 14:56:23.456789 2024] [core:notice] [pid 1] AH00094: Command line: '/usr/sbin/apache2'";
+        assert_eq!(registry.detect([error]).unwrap().name(), "apache_error");
+    }
+
+    #[test]
+    fn test_register_replaces_parsers_by_name() {
+        let mut registry = ParserRegistry::default();
+        registry.register(Everything("nginx"));
+        assert_eq!(registry.names(), vec!["auth", "apache", "apache_error", "nginx"]);
+        assert!(registry.get("nginx").unwrap().parse(NGINX).is_none());
+    }
+
+    #[test]
+    fn test_resolve_named_and_detected_sources() {
+        let registry = ParserRegistry::default();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("access.log");
+        std::fs::write(&path, format!("{}\n{}\n", NGINX, NGINX)).unwrap();
+        let path = path.to_string_lossy().into_owned();
+
+        let named = SourceConfig { path: path.clone(), parser: Some("auth".to_string()), timezone: None };
+        assert_eq!(registry.resolve(&named).unwrap().name(), "auth");
+
+        let detected = SourceConfig { path, parser: None, timezone: None };
+        assert_eq!(registry.resolve(&detected).unwrap().name(), "nginx");
+
+        let unknown = SourceConfig { path: "/nonexistent".to_string(), parser: Some("iis".to_string()), timezone: None };
+        let Err(e) = registry.resolve(&unknown) else { panic!("iis is not registered") };
+        assert!(e.contains("nginx, auth, apache, apache_error"));
+    }
+}
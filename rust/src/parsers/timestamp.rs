@@ -0,0 +1,174 @@
+use std::fmt;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+
+/// How far past the reference a timestamp may be before it is taken to be
+/// from the year before. Covers clock skew between the logger and the filesystem.
+const FUTURE_SLACK: Duration = Duration::days(1);
+
+/// Timezone of a log whose timestamps carry no offset, such as classic syslog.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SourceTimezone {
+    /// The timezone of the host cephalog runs on.
+    #[default]
+    Local,
+    Named(Tz),
+}
+
+impl SourceTimezone {
+    /// `naive` read as a wall-clock time in this timezone. Times repeated when
+    /// clocks go back resolve to the first occurrence, and times skipped when
+    /// they go forward are moved past the gap.
+    pub fn to_utc(self, naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            SourceTimezone::Local => resolve(&Local, naive),
+            SourceTimezone::Named(tz) => resolve(&tz, naive),
+        }
+    }
+
+    /// The year of `instant` on a wall clock in this timezone.
+    fn year_at(self, instant: DateTime<Utc>) -> i32 {
+        match self {
+            SourceTimezone::Local => instant.with_timezone(&Local).year(),
+            SourceTimezone::Named(tz) => instant.with_timezone(&tz).year(),
+        }
+    }
+}
+
+fn resolve<T: TimeZone>(tz: &T, naive: NaiveDateTime) -> Option<DateTime<Utc>> {
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .or_else(|| tz.from_local_datetime(&(naive + Duration::hours(1))).earliest())
+        .map(|dt| dt.to_utc())
+}
+
+impl FromStr for SourceTimezone {
+    type Err = String;
+
+    /// `local`, `UTC` or an IANA name such as `Europe/Berlin`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("local") {
+            return Ok(SourceTimezone::Local);
+        }
+        s.parse::<Tz>()
+            .map(SourceTimezone::Named)
+            .map_err(|_| format!("unknown timezone: {}", s))
+    }
+}
+
+impl fmt::Display for SourceTimezone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SourceTimezone::Local => f.write_str("local"),
+            SourceTimezone::Named(tz) => f.write_str(tz.name()),
+        }
+    }
+}
+
+/// Parses the timestamp at the start of a syslog line, either RFC 3339 as
+/// written by rsyslog and journald (`2024-03-09T12:34:56.123456+01:00`) or the
+/// classic `Mar  9 12:34:56`.
+///
+/// The classic form has no year or offset. It is read in `tz` and given the
+/// latest year that does not put it after `reference`, a time no line can be
+/// later than, e.g. the file's modification time. A `Dec 31` line read on
+/// January 1st therefore lands in the previous year.
+pub fn parse_syslog_timestamp(value: &str, tz: SourceTimezone, reference: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if let Ok(dt) = DateTime::parse_from_rfc3339(value) {
+        return Some(dt.to_utc());
+    }
+    // journald's `short-iso` writes the offset without a colon.
+    if let Ok(dt) = DateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S%.f%z") {
+        return Some(dt.to_utc());
+    }
+
+    let mut parts = value.split_whitespace();
+    let month = month_number(parts.next()?)?;
+    let day: u32 = parts.next()?.parse().ok()?;
+    let time = NaiveTime::parse_from_str(parts.next()?, "%H:%M:%S%.f").ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    // Going back a few years also finds the previous February 29th.
+    let year = tz.year_at(reference);
+    (0..=4).find_map(|back| {
+        let date = NaiveDate::from_ymd_opt(year - back, month, day)?;
+        let timestamp = tz.to_utc(date.and_time(time))?;
+        (timestamp <= reference + FUTURE_SLACK).then_some(timestamp)
+    })
+}
+
+/// Like `parse_syslog_timestamp`, for timestamps that include the year but no offset.
+pub fn parse_local_timestamp(value: &str, format: &str, tz: SourceTimezone) -> Option<DateTime<Utc>> {
+    let value = value.split_whitespace().collect::<Vec<_>>().join(" ");
+    tz.to_utc(NaiveDateTime::parse_from_str(&value, format).ok()?)
+}
+
+fn month_number(name: &str) -> Option<u32> {
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    MONTHS.iter().position(|month| month.eq_ignore_ascii_case(name)).map(|i| i as u32 + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().to_utc()
+    }
+
+    #[test]
+    fn test_year_comes_from_the_reference() {
+        let parsed = parse_syslog_timestamp("Mar  9 12:34:56", SourceTimezone::Named(Tz::UTC), utc("2024-06-01T00:00:00Z"));
+        assert_eq!(parsed, Some(utc("2024-03-09T12:34:56Z")));
+    }
+
+    #[test]
+    fn test_december_lines_read_in_january_belong_to_last_year() {
+        let reference = utc("2025-01-01T00:10:00Z");
+        let tz = SourceTimezone::Named(Tz::UTC);
+        assert_eq!(parse_syslog_timestamp("Dec 31 23:59:59", tz, reference), Some(utc("2024-12-31T23:59:59Z")));
+        assert_eq!(parse_syslog_timestamp("Jan  1 00:05:00", tz, reference), Some(utc("2025-01-01T00:05:00Z")));
+    }
+
+    #[test]
+    fn test_leap_day_goes_back_to_the_last_leap_year() {
+        let parsed = parse_syslog_timestamp("Feb 29 10:00:00", SourceTimezone::Named(Tz::UTC), utc("2025-03-01T00:00:00Z"));
+        assert_eq!(parsed, Some(utc("2024-02-29T10:00:00Z")));
+    }
+
+    #[test]
+    fn test_timezone_is_applied() {
+        let berlin: SourceTimezone = "Europe/Berlin".parse().unwrap();
+        let parsed = parse_syslog_timestamp("Jul  1 12:00:00", berlin, utc("2024-07-02T00:00:00Z"));
+        assert_eq!(parsed, Some(utc("2024-07-01T10:00:00Z")));
+
+        // 02:30 does not exist on the night clocks go forward.
+        let parsed = parse_syslog_timestamp("Mar 31 02:30:00", berlin, utc("2024-04-01T00:00:00Z"));
+        assert_eq!(parsed, Some(utc("2024-03-31T01:30:00Z")));
+
+        assert!("Mars/Olympus_Mons".parse::<SourceTimezone>().is_err());
+        assert_eq!("LOCAL".parse::<SourceTimezone>(), Ok(SourceTimezone::Local));
+    }
+
+    #[test]
+    fn test_rfc3339_timestamps_keep_their_offset_and_precision() {
+        let reference = utc("2000-01-01T00:00:00Z");
+        let parsed = parse_syslog_timestamp("2024-03-09T12:34:56.123456+01:00", SourceTimezone::Local, reference);
+        assert_eq!(parsed, Some(utc("2024-03-09T11:34:56.123456Z")));
+
+        let parsed = parse_syslog_timestamp("2024-03-09T12:34:56+0100", SourceTimezone::Local, reference);
+        assert_eq!(parsed, Some(utc("2024-03-09T11:34:56Z")));
+    }
+
+    #[test]
+    fn test_rejects_other_text() {
+        let reference = utc("2024-06-01T00:00:00Z");
+        assert!(parse_syslog_timestamp("Foo  9 12:34:56", SourceTimezone::Local, reference).is_none());
+        assert!(parse_syslog_timestamp("Mar 32 12:34:56", SourceTimezone::Local, reference).is_none());
+        assert!(parse_syslog_timestamp("yesterday", SourceTimezone::Local, reference).is_none());
+    }
+}
@@ -0,0 +1,10 @@
+use axum::extract::DefaultBodyLimit;
+use axum::{Router, routing::post};
+use crate::handlers::ingest::{ingest, MAX_BATCH_BYTES};
+use crate::server::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", post(ingest))
+        .layer(DefaultBodyLimit::max(MAX_BATCH_BYTES))
+}
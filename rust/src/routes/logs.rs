@@ -1,8 +1,11 @@
 use axum::{Router, routing::get};
 use crate::handlers::logs::{get_logs, stream_logs};
+use crate::handlers::ws::ws_logs;
+use crate::server::state::AppState;
 
-pub fn routes() -> Router {
+pub fn routes() -> Router<AppState> {
     Router::new()
         .route("/", get(get_logs))
         .route("/stream", get(stream_logs))
-}
\ No newline at end of file
+        .route("/ws", get(ws_logs))
+}
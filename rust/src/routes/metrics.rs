@@ -0,0 +1,8 @@
+use axum::{Router, routing::get};
+use crate::handlers::metrics::get_metrics;
+use crate::server::state::AppState;
+
+pub fn routes() -> Router<AppState> {
+    Router::new()
+        .route("/", get(get_metrics))
+}
@@ -1,11 +1,18 @@
-use axum::{Router, routing::{get, post}};
+use axum::Router;
 use tower_http::cors::CorsLayer;
 
+use crate::server::state::AppState;
+
+mod ingest;
 mod logs;
-pub fn configure_routes() -> Router {
+mod metrics;
+pub fn configure_routes(state: AppState) -> Router {
     Router::new()
     .nest("/api/v1", Router::new()
         .nest("/logs", logs::routes())
+        .nest("/ingest", ingest::routes())
+        .nest("/metrics", metrics::routes())
     )
     .layer(CorsLayer::permissive())
+    .with_state(state)
 }
@@ -1 +1,3 @@
-pub mod server;
\ No newline at end of file
+#[allow(clippy::module_inception)]
+pub mod server;
+pub mod state;
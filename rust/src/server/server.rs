@@ -1,17 +1,197 @@
-use axum::{Router, routing::get};
-use axum::response::Html;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::thread;
 
+use db::clickhouse::{ClickHouseConfig, ClickHouseDB};
+use db::database::Database;
+use db::migrations::Migrator;
+use db::writer::{LogWriter, WriterConfig};
+use tokio::signal;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{error, info, warn};
+use tracing_subscriber::EnvFilter;
+
+use crate::detect::Detector;
+use crate::handlers::ingest::IngestState;
+use crate::ingest::checkpoint::{CheckpointStore, Ingested};
+use crate::ingest::hub::LogHub;
+use crate::ingest::identity::AgentIdentity;
+use crate::ingest::journal::{JournalInput, JournalReader};
+use crate::ingest::pipeline::Pipeline;
+use crate::ingest::syslog::{SyslogConfig, SyslogReceiver, SyslogRouter};
+use crate::middleware::auth::IngestAuth;
+use crate::ingest::tail::{checkpoint_path, TailStart, Tailer};
+use crate::parsers::registry::ParserRegistry;
+use crate::parsers::source_configs;
 use crate::routes::*;
+use crate::server::state::AppState;
+
+/// Number of recent events kept for `Last-Event-ID` replay.
+const STREAM_BUFFER: usize = 1024;
+/// Parsed entries queued between the tailers and the pipeline.
+const INGEST_QUEUE: usize = 10_000;
+
+pub(crate) fn init_tracing() {
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
+        .init();
+}
+
+fn load_config() -> ClickHouseConfig {
+    ClickHouseConfig::from_env().unwrap_or_else(|e| {
+        error!("Cannot start without a database: {}", e);
+        std::process::exit(1);
+    })
+}
+
+/// Applies pending migrations, exiting if any of them fails.
+async fn run_migrations(config: &ClickHouseConfig) {
+    match Migrator::new(config.clone()).run().await {
+        Ok(applied) if applied.is_empty() => info!("Schema is up to date"),
+        Ok(applied) => info!("Applied migrations {:?}", applied),
+        Err(e) => {
+            error!("Migration failed: {}", e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Entry point of the `migrate` subcommand.
+#[tokio::main]
+pub async fn migrate() {
+    init_tracing();
+    run_migrations(&load_config()).await;
+}
 
 #[tokio::main]
 pub async fn start() {
-    //let addr = "0.0.0.0:3000".parse::<SocketAddr>().await.unwrap();
+    init_tracing();
+
+    let config = load_config();
+    // Set CLICKHOUSE_AUTO_MIGRATE=false when migrations are run separately, e.g. by `migrate`.
+    if std::env::var("CLICKHOUSE_AUTO_MIGRATE").map_or(true, |value| value != "false") {
+        run_migrations(&config).await;
+    }
+
+    let hub = Arc::new(LogHub::new(STREAM_BUFFER));
+    let db: Arc<dyn Database> = Arc::new(ClickHouseDB::connect(&config));
 
+    let (sender, receiver) = mpsc::channel(INGEST_QUEUE);
+    let (writer, written) = LogWriter::spawn(db.clone(), WriterConfig::default());
+    let stats = writer.stats();
+    let detector = Arc::new(Detector::from_env().unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    }));
+    let (stop, stopped) = oneshot::channel();
+    let pipeline = Pipeline::new(hub.clone(), writer, AgentIdentity::from_env(), detector.clone());
+    let pipeline = tokio::spawn(pipeline.run(receiver, async {
+        let _ = stopped.await;
+    }));
+
+    start_sources(sender).await;
+
+    let ingest = IngestAuth::from_env().unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
     let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await.unwrap();
-    //let addr = listener.local_addr()?;
-    let app = configure_routes();
-    //let app = Router::new().route("/", get(|| async { Html("<h1>Hello, World!</h1>".to_string()) }));
+    let app = configure_routes(AppState {
+        hub,
+        db,
+        ingest: Arc::new(IngestState::new(ingest)),
+        detector,
+        writer: stats.clone(),
+    });
+
+    // The peer address tells the ingest endpoint whether a request came through the trusted proxy.
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(shutdown_signal())
+        .await
+        .unwrap();
+
+    info!("Shutting down, writing queued rows");
+    let _ = stop.send(());
+    let _ = pipeline.await;
+    let _ = written.await;
+    info!("Stopped: {:?}", stats.snapshot());
+}
+
+/// Completes on Ctrl-C or SIGTERM.
+async fn shutdown_signal() {
+    let terminate = async {
+        match signal::unix::signal(signal::unix::SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        _ = signal::ctrl_c() => {}
+        _ = terminate => {}
+    }
+}
+
+/// Starts reading every configured source: log files, the syslog receiver and
+/// the journal, sending entries and checkpoints to `sender`. A bad configuration is fatal.
+pub(crate) async fn start_sources(sender: mpsc::Sender<Ingested>) {
+    let checkpoints = Arc::new(CheckpointStore::load(checkpoint_path()));
+    let parsers = ParserRegistry::from_env().unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+    for source in source_configs() {
+        let parser = match parsers.resolve(&source) {
+            Ok(parser) => parser,
+            Err(e) => {
+                warn!("Skipping {}: {}", source.path, e);
+                continue;
+            }
+        };
+        info!(
+            "Reading {} with the {} parser ({:?}, fields {:?})",
+            source.path, parser.name(), parser.source(), parser.fields()
+        );
+        let tailer = Tailer::new(source.path, parser, checkpoints.clone(), TailStart::from_env());
+        let sender = sender.clone();
+        // A full queue blocks the tailer, which simply stops reading until there is room.
+        thread::spawn(move || tailer.run(|ingested| {
+            let _ = sender.blocking_send(ingested);
+        }));
+    }
+
+    start_syslog(&parsers, sender.clone()).await;
+
+    if let Some(input) = JournalInput::from_env() {
+        let router = SyslogRouter::from_env(&parsers).unwrap_or_else(|e| {
+            error!("{}", e);
+            std::process::exit(1);
+        });
+        let reader = JournalReader::new(input, Arc::new(router), checkpoints.clone());
+        thread::spawn(move || reader.run(|ingested| {
+            let _ = sender.blocking_send(ingested);
+        }));
+    }
+}
 
-    axum::serve(listener, app).await.unwrap();
-    
-}   
\ No newline at end of file
+/// Starts the syslog receiver when `SYSLOG_UDP_ADDR`, `SYSLOG_TCP_ADDR` or
+/// `SYSLOG_TLS_ADDR` is set. A bad configuration is fatal, like a bad parser.
+async fn start_syslog(parsers: &ParserRegistry, sender: mpsc::Sender<Ingested>) {
+    let config = match SyslogConfig::from_env() {
+        Ok(Some(config)) => config,
+        Ok(None) => return,
+        Err(e) => {
+            error!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let router = SyslogRouter::from_env(parsers).unwrap_or_else(|e| {
+        error!("{}", e);
+        std::process::exit(1);
+    });
+    if let Err(e) = SyslogReceiver::new(router, sender).start(&config).await {
+        error!("Failed to start the syslog receiver: {}", e);
+        std::process::exit(1);
+    }
+}
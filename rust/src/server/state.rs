@@ -0,0 +1,20 @@
+use std::sync::Arc;
+
+use db::database::Database;
+use db::writer::WriterStats;
+
+use crate::detect::Detector;
+use crate::handlers::ingest::IngestState;
+use crate::ingest::hub::LogHub;
+
+/// Shared state handed to every axum handler.
+#[derive(Clone)]
+pub struct AppState {
+    pub hub: Arc<LogHub>,
+    pub db: Arc<dyn Database>,
+    pub ingest: Arc<IngestState>,
+    /// Shared with the ingestion pipeline.
+    pub detector: Arc<Detector>,
+    /// Totals of the ingestion pipeline's database writer.
+    pub writer: WriterStats,
+}
@@ -1,16 +1,16 @@
 use db::clickhouse::ClickHouseDB;
-use db::schema::DbLogEntry;
+use db::database::Database;
 use db::util::get_test_logs;
 
 #[tokio::test]
+#[ignore = "requires a running ClickHouse, use `make test`"]
 async fn test_insert_log() {
-    let db = ClickHouseDB::new();
+    let db = ClickHouseDB::new().unwrap();
 
     let logs = get_test_logs().await.unwrap();
-    let result = db.insert_logs(logs).await.unwrap();
-    assert_eq!(result, ());
+    db.insert_logs(logs).await.unwrap();
 
     let logs = db.fetch_logs(None).await.unwrap();
     assert_eq!(logs.len(), 10);
 
-}
\ No newline at end of file
+}